@@ -9,10 +9,13 @@
 //! 
 //! ## Game Structure
 //! 
-//! The game consists of three map types:
-//! - **World Map**: Large overworld with towns and dungeon entrances
+//! The game consists of four map types:
+//! - **World Map**: Large overworld with towns and dungeon entrances, generated from the run seed
 //! - **Towns**: Safe areas with friendly NPCs and merchants
 //! - **Dungeons**: Dangerous areas with hostile enemies
+//! - **Interiors**: Cellars reached through hatches inside a town's buildings
+//! 
+//! Entering a map pushes the map being left onto a location stack; ESC pops back out one level.
 //! 
 //! ## Core Systems
 //! 
@@ -29,16 +32,39 @@
 //! ## Game States
 //! 
 //! - **Playing**: Normal exploration and movement
-//! - **Inventory**: Item management interface
-//! - **Dialogue**: Conversation with NPCs
+//! - **Inventory**: Item management interface (use consumables, equip gear)
+//! - **Dialogue**: Conversation with NPCs (PageUp/PageDown scrolls the transcript)
+//! - **Dialogue Log**: Re-read past conversations
 //! - **Combat**: Turn-based battle with enemies
+//! - **Perk Selection**: Choose one of three random perks on level up
+//! - **Level Up**: Spend skill points on SPECIAL stats and skills
+//! - **Character Sheet**: SPECIAL stats, derived values and equipment
+//! - **Journal**: Active and completed quests
+//! - **Bestiary**: Enemies fought so far, with kill counts (B)
+//! - **Statistics**: Playtime, kills, caps and other tallies of the run (Shift+S)
+//! - **Fast Travel**: Jump to a discovered town from the world map
+//! - **Map View**: The whole current map scaled to the window (Shift+M)
+//! - **Game Over**: The player has died; Enter returns to the new game screen
+//! - **New Game**: Pick a difficulty, seed and world before the run starts
+//! - **Character Creation**: Spread a pool of points across the SPECIAL stats before play
+//! - **Paused**: Pause menu showing the current difficulty
+//! - **Confirm Attack**: Y/N prompt before turning on a friendly NPC (K)
+//! - **Editor**: Developer map editor for tiles, enemies and items (F4, debug builds or `--editor`)
+
 
 // Import macroquad game engine core functionality
 // Includes graphics rendering, input handling, color definitions, etc.
 use macroquad::prelude::*;
 
-// Import HashMap for storing item positions on the map
-use std::collections::HashMap;
+// Random selection from slices (used for perk choices)
+use macroquad::rand::ChooseRandom;
+
+// Import HashMap for storing item positions on the map, HashSet for field of view
+use std::collections::{HashMap, HashSet};
+
+// A* search shared by enemy chases, NPC schedules and click-to-travel
+mod pathfinding;
+use pathfinding::astar;
 
 // ========== Core Data Structures ==========
 
@@ -48,38 +74,95 @@ use std::collections::HashMap;
 enum TileType {
     Floor,     // Floor - walkable
     Wall,      // Wall - not walkable
-    Door,      // Door - walkable
+    WeakWall,  // Crumbling wall - not walkable, but a strong character can smash through
+    SecretDoor, // Hidden door - looks and acts like a wall until noticed, then becomes a `Door`
+    Door,      // Door - open, closed or locked as recorded in `GameMap::doors`
+    DoorClosed, // Closed door - how a closed `Door` is drawn; opens when bumped
+    DoorLocked, // Locked door - how a locked `Door` is drawn; needs a key to open
     Water,     // Water - not walkable
     Grass,     // Grass - walkable (world map)
     Mountain,  // Mountain - not walkable (world map)
     Forest,    // Forest - walkable (world map)
     Town,      // Town entrance - enterable
     Dungeon,   // Dungeon entrance - enterable
+    Trap,      // Trap - how a spotted trap from `GameMap::traps` is drawn; walkable
+    Workbench, // Workbench - walkable, crafting happens here
+    Lever,     // Lever - not walkable, pulled from next to it to toggle its link in `GameMap::links`
+    PressurePlate, // Pressure plate - walkable, holds its link open while stood on
+    Sign,      // Signpost - not walkable, read from next to it; its text is in `GameMap::signs`
 }
 
 /// Map type enumeration
 /// Distinguishes between world map and small maps (towns/dungeons)
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
 enum MapType {
+    #[default]
     WorldMap,   // World map
     Town,       // Town
     Dungeon,    // Dungeon
+    Interior,   // Cellar under a town building
 }
 
 impl TileType {
+    const ALL: [TileType; 18] = [
+        TileType::Floor, TileType::Wall, TileType::WeakWall, TileType::SecretDoor, TileType::Door, TileType::DoorClosed,
+        TileType::DoorLocked, TileType::Water, TileType::Grass, TileType::Mountain,
+        TileType::Forest, TileType::Town, TileType::Dungeon, TileType::Trap, TileType::Workbench,
+        TileType::Lever, TileType::PressurePlate, TileType::Sign,
+    ];
+    
+    /// Tile drawn with the given character, for reading text maps
+    /// Glyphs shared by two tiles read as the first: `#` is a wall, `^` a mountain and `+` a closed door
+    fn from_char(c: char) -> Option<TileType> {
+        TileType::ALL.into_iter().find(|tile| tile.as_char().starts_with(c))
+    }
+    
     /// Convert tile type to corresponding UTF-8 character representation
     /// Uses standard Roguelike character style
-    fn as_char(&self) -> &str {
+    fn as_char(&self) -> &'static str {
         match self {
             TileType::Floor => ".",      // Floor represented by dot
             TileType::Wall => "#",       // Wall represented by hash
-            TileType::Door => "+",       // Door represented by plus
+            TileType::WeakWall => "#",   // Weak wall also a hash (drawn in a lighter shade)
+            TileType::SecretDoor => "#", // Hidden door passes for a wall
+            TileType::Door => "/",       // Open door represented by slash
+            TileType::DoorClosed => "+", // Closed door represented by plus
+            TileType::DoorLocked => "+", // Locked door also a plus (drawn in a different color)
             TileType::Water => "~",      // Water represented by tilde
             TileType::Grass => "\"",     // Grass represented by quote
             TileType::Mountain => "^",   // Mountain represented by caret
             TileType::Forest => "&",     // Forest represented by ampersand
             TileType::Town => "※",      // Town represented by asterisk
             TileType::Dungeon => "▼",    // Dungeon represented by triangle
+            TileType::Trap => "^",       // Trap represented by caret (like mountains, but dark)
+            TileType::Workbench => "=",  // Workbench represented by equals sign
+            TileType::Lever => "!",      // Lever represented by exclamation mark
+            TileType::PressurePlate => "_", // Pressure plate represented by underscore
+            TileType::Sign => "┼",       // Signpost represented by a post with its crossbar
+        }
+    }
+    
+    /// Background color used when rendering this tile
+    fn color(&self) -> Color {
+        match self {
+            TileType::Floor => DARKGRAY,     // Floor: dark gray
+            TileType::Wall => GRAY,          // Wall: gray
+            TileType::WeakWall => Color::new(0.7, 0.7, 0.7, 1.0), // Weak wall: a lighter gray
+            TileType::SecretDoor => GRAY,    // Hidden door: wall gray
+            TileType::Door => BROWN,         // Open door: brown
+            TileType::DoorClosed => BROWN,   // Closed door: brown
+            TileType::DoorLocked => MAROON,  // Locked door: maroon
+            TileType::Water => BLUE,         // Water: blue
+            TileType::Grass => DARKGREEN,    // Grass: dark green
+            TileType::Mountain => LIGHTGRAY, // Mountain: light gray
+            TileType::Forest => GREEN,       // Forest: green
+            TileType::Town => ORANGE,        // Town: orange
+            TileType::Dungeon => DARKPURPLE, // Dungeon: dark purple
+            TileType::Trap => RED,           // Trap: red
+            TileType::Workbench => BEIGE,    // Workbench: beige
+            TileType::Lever => GOLD,         // Lever: gold
+            TileType::PressurePlate => LIGHTGRAY, // Pressure plate: light gray
+            TileType::Sign => BROWN,         // Signpost: brown
         }
     }
     
@@ -92,14 +175,46 @@ impl TileType {
             TileType::Grass | 
             TileType::Forest |
             TileType::Town |
-            TileType::Dungeon
+            TileType::Dungeon |
+            TileType::Trap |
+            TileType::Workbench |
+            TileType::PressurePlate
         )
     }
     
+    /// Check if this tile blocks line of sight
+    fn blocks_sight(&self) -> bool {
+        matches!(self, TileType::Wall | TileType::WeakWall | TileType::SecretDoor | TileType::DoorClosed | TileType::DoorLocked | TileType::Mountain)
+    }
+    
     /// Check if this is an enterable location (town or dungeon)
     fn is_enterable(&self) -> bool {
         matches!(self, TileType::Town | TileType::Dungeon)
     }
+    
+    /// Turns it takes to step onto this tile
+    /// Forest undergrowth slows travel; everything else walkable takes a single turn
+    fn move_cost(&self) -> u64 {
+        match self {
+            TileType::Forest => 2,
+            _ => 1,
+        }
+    }
+    
+    /// How long stepping onto this tile takes, as a multiple of BASE_MOVE_INTERVAL
+    /// Every turn past the first adds half a step, so rough ground visibly slows the walk
+    fn movement_cost(&self) -> f32 {
+        1.0 + (self.move_cost() - 1) as f32 * 0.5
+    }
+    
+    /// Chance per step of a random encounter on the world map
+    fn encounter_chance(&self) -> f32 {
+        match self {
+            TileType::Forest => 0.04, // Ambushes hide in the trees
+            TileType::Grass => 0.01,  // Open ground is mostly safe
+            _ => 0.0,                 // Entrances and impassable tiles never trigger
+        }
+    }
 }
 
 /// Item structure
@@ -109,6 +224,82 @@ struct Item {
     name: String,      // Item name
     char: &'static str, // Character displayed on map
     item_type: ItemType, // Item type (weapon, armor, consumable, etc.)
+    durability: Option<(i32, i32)>, // (current, max) wear left on weapons and armor; None for everything else
+    quantity: u32,     // How many are in this stack (only consumables and throwables stack)
+}
+
+impl Item {
+    /// Weight counted against the player's carry capacity
+    fn weight(&self) -> i32 {
+        match self.item_type {
+            ItemType::Weapon { .. } => 5,
+            ItemType::Armor { .. } => 10,
+            ItemType::Consumable { .. } => 1,
+            ItemType::Potion { .. } => 1,
+            ItemType::Torch { .. } => 1,
+            ItemType::Quest => 0,
+            ItemType::Material => 1,
+            ItemType::Gold { .. } => 0,
+            ItemType::Throwable { .. } => 1,
+            ItemType::Note { .. } => 0,
+        }
+    }
+    
+    /// The stat gear is compared on, e.g. (18, "DMG") for a weapon; None for everything else
+    fn gear_stat(&self) -> Option<(i32, &'static str)> {
+        match self.item_type {
+            ItemType::Weapon { damage } => Some((damage, "DMG")),
+            ItemType::Armor { defense } => Some((defense, "DEF")),
+            _ => None,
+        }
+    }
+    
+    /// Whether copies of this item share one inventory slot
+    fn stacks(&self) -> bool {
+        matches!(self.item_type, ItemType::Consumable { .. } | ItemType::Potion { .. } | ItemType::Throwable { .. })
+    }
+    
+    /// Name with the stack size, e.g. "Stimpak ×3"
+    fn display_name(&self) -> String {
+        if self.quantity > 1 {
+            format!("{} ×{}", self.name, self.quantity)
+        } else {
+            self.name.clone()
+        }
+    }
+    
+    /// A pile of `amount` caps lying on the ground
+    fn caps(amount: i32) -> Item {
+        Item {
+            name: format!("{} Caps", amount),
+            char: "$",
+            item_type: ItemType::Gold { amount },
+            durability: None,
+            quantity: 1,
+        }
+    }
+    
+    /// A grenade that catches everything next to whatever it is thrown at
+    fn grenade() -> Item {
+        Item {
+            name: "Grenade".to_string(),
+            char: "o",
+            item_type: ItemType::Throwable { damage: 35, radius: 1 },
+            durability: None,
+            quantity: 1,
+        }
+    }
+    
+    /// A fresh, unlit torch
+    fn torch() -> Item {
+        Item {
+            name: "Torch".to_string(),
+            char: "i",
+            item_type: ItemType::Torch { turns: TORCH_TURNS },
+            durability: None,
+            quantity: 1,
+        }
+    }
 }
 
 /// Item type enumeration
@@ -117,8 +308,165 @@ struct Item {
 enum ItemType {
     Weapon { damage: i32 },      // Weapon - with damage value
     Armor { defense: i32 },      // Armor - with defense value
-    Consumable { heal: i32 },    // Consumable - with heal value
+    Consumable { heal: i32, hunger_restore: i32, thirst_restore: i32 }, // Consumable - heals and/or feeds
+    Potion { heal_pct: i32 },     // Heals a share of max HP, so it keeps up as the player levels
+    Torch { turns: i32 },         // Light source - burns for this many dungeon turns
     Quest,                        // Quest item
+    Material,                     // Crafting component, only good at a workbench
+    Gold { amount: i32 },         // Pile of caps, added to the purse rather than the inventory
+    Throwable { damage: i32, radius: i32 }, // Thrown in combat, hurting every hostile within `radius` of the target
+    Note { text: String },        // Readable page, opened from the inventory with R
+}
+
+impl ItemType {
+    /// Durability a fresh item of this type starts with: weapons and armor wear out, nothing else does
+    fn full_durability(&self) -> Option<(i32, i32)> {
+        match self {
+            ItemType::Weapon { .. } => Some((WEAPON_DURABILITY, WEAPON_DURABILITY)),
+            ItemType::Armor { .. } => Some((ARMOR_DURABILITY, ARMOR_DURABILITY)),
+            _ => None,
+        }
+    }
+}
+
+/// Uses a fresh weapon survives before breaking
+const WEAPON_DURABILITY: i32 = 50;
+/// Hits fresh armor absorbs before breaking
+const ARMOR_DURABILITY: i32 = 80;
+
+/// One possible drop in a loot table
+struct LootEntry {
+    item_factory: Box<dyn Fn() -> Item>, // Builds a fresh copy of the item
+    weight: u32,                         // Relative chance against the other entries
+}
+
+/// Weighted table of items that can be rolled for map placement
+struct LootTable {
+    entries: Vec<LootEntry>,
+}
+
+impl LootTable {
+    fn new() -> Self {
+        LootTable { entries: Vec::new() }
+    }
+    
+    /// Add an item with the given weight
+    fn with(mut self, weight: u32, name: &'static str, char: &'static str, item_type: ItemType) -> Self {
+        self.entries.push(LootEntry {
+            item_factory: Box::new(move || Item {
+                name: name.to_string(),
+                char,
+                item_type: item_type.clone(),
+                durability: item_type.full_durability(),
+                quantity: 1,
+            }),
+            weight,
+        });
+        self
+    }
+    
+    /// Pick one entry by cumulative weight
+    /// Returns None when the table is empty or every weight is zero
+    fn roll(&self) -> Option<Item> {
+        self.roll_from(|total| rand::gen_range(0, total))
+    }
+    
+    /// Roll using a map generator's own RNG instead of the global one
+    fn roll_with(&self, rng: &rand::RandGenerator) -> Option<Item> {
+        self.roll_from(|total| rng.gen_range(0, total))
+    }
+    
+    /// Pick an entry given a function returning a number below the total weight
+    fn roll_from(&self, pick: impl FnOnce(u32) -> u32) -> Option<Item> {
+        let total: u32 = self.entries.iter().map(|entry| entry.weight).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = pick(total);
+        for entry in &self.entries {
+            if pick < entry.weight {
+                return Some((entry.item_factory)());
+            }
+            pick -= entry.weight;
+        }
+        None
+    }
+}
+
+/// Loot found on a given dungeon floor (0 is the shallowest)
+/// Deeper floors shift weight toward stronger gear and improve its stats
+fn default_dungeon_loot_table(floor: usize) -> LootTable {
+    let depth = floor as i32;
+    let deep = floor as u32 * 10;
+    LootTable::new()
+        .with(40u32.saturating_sub(deep), "Stimpak", "+", ItemType::Consumable { heal: 30, hunger_restore: 0, thirst_restore: 0 })
+        .with(30u32.saturating_sub(deep), "Combat Knife", "/", ItemType::Weapon { damage: 18 + depth * 3 })
+        .with(10 + deep, "Super Stimpak", "+", ItemType::Consumable { heal: 60 + depth * 10, hunger_restore: 0, thirst_restore: 0 })
+        .with(10, "Healing Tonic", "!", ItemType::Potion { heal_pct: 25 })
+        .with(10 + deep, "Hunting Rifle", "/", ItemType::Weapon { damage: 25 + depth * 5 })
+        .with(5 + deep, "Metal Armor", "[", ItemType::Armor { defense: 6 + depth * 2 })
+        .with(8, "Broken Sword", "/", ItemType::Weapon { damage: 6 })
+        .with(15, "Scrap Metal", "*", ItemType::Material)
+        .with(10, "Lockpicks", "-", ItemType::Material)
+        .with(8 + deep, "Grenade", "o", ItemType::Throwable { damage: 35, radius: 1 })
+}
+
+/// Loot lying around towns: mostly consumables and light armor
+fn town_loot_table() -> LootTable {
+    LootTable::new()
+        .with(40, "Stimpak", "+", ItemType::Consumable { heal: 30, hunger_restore: 0, thirst_restore: 0 })
+        .with(30, "Nuka-Cola", "!", ItemType::Consumable { heal: 10, hunger_restore: 0, thirst_restore: 25 })
+        .with(25, "Canned Food", "%", ItemType::Consumable { heal: 5, hunger_restore: 40, thirst_restore: 0 })
+        .with(25, "Purified Water", "!", ItemType::Consumable { heal: 0, hunger_restore: 0, thirst_restore: 40 })
+        .with(15, "Leather Jacket", "[", ItemType::Armor { defense: 2 })
+        .with(10, "Leather Armor", "[", ItemType::Armor { defense: 4 })
+        .with(5, "Switchblade", "/", ItemType::Weapon { damage: 16 })
+        .with(20, "Herb", "\"", ItemType::Consumable { heal: 5, hunger_restore: 0, thirst_restore: 0 })
+        .with(15, "Scrap Metal", "*", ItemType::Material)
+        .with(15, "Leather Scraps", "*", ItemType::Material)
+        .with(10, "Lockpicks", "-", ItemType::Material)
+}
+
+/// Two items that combine into a new one at a workbench (in either order)
+struct Recipe {
+    input_a: String,  // Name of one ingredient
+    input_b: String,  // Name of the other ingredient
+    output: Item,     // What the ingredients become
+}
+
+impl Recipe {
+    fn new(input_a: &str, input_b: &str, output: Item) -> Self {
+        Recipe { input_a: input_a.to_string(), input_b: input_b.to_string(), output }
+    }
+}
+
+/// Everything that can be made at a workbench
+fn recipes() -> Vec<Recipe> {
+    let item = |name: &str, char, item_type: ItemType| Item {
+        name: name.to_string(),
+        char,
+        durability: item_type.full_durability(),
+        quantity: 1,
+        item_type,
+    };
+    vec![
+        Recipe::new("Scrap Metal", "Leather Scraps", item("Padded Vest", "[", ItemType::Armor { defense: 3 })),
+        Recipe::new("Herb", "Purified Water", item("Healing Potion", "!", ItemType::Consumable { heal: 40, hunger_restore: 0, thirst_restore: 10 })),
+        Recipe::new("Broken Sword", "Scrap Metal", item("Reforged Blade", "/", ItemType::Weapon { damage: 22 })),
+        Recipe::new("Herb", "Canned Food", item("Trail Stew", "%", ItemType::Consumable { heal: 10, hunger_restore: 60, thirst_restore: 0 })),
+        Recipe::new("Stimpak", "Nuka-Cola", item("Fizzy Stim", "+", ItemType::Consumable { heal: 45, hunger_restore: 0, thirst_restore: 25 })),
+        Recipe::new("Scrap Metal", "Scrap Metal", item("Scrap Knife", "/", ItemType::Weapon { damage: 14 })),
+    ]
+}
+
+/// What combining two items makes, if any recipe takes them (in either order)
+fn try_craft(item_a: &Item, item_b: &Item, recipes: &[Recipe]) -> Option<Item> {
+    recipes.iter()
+        .find(|recipe| {
+            (recipe.input_a == item_a.name && recipe.input_b == item_b.name)
+                || (recipe.input_a == item_b.name && recipe.input_b == item_a.name)
+        })
+        .map(|recipe| recipe.output.clone())
 }
 
 /// Dialogue option structure
@@ -126,6 +474,92 @@ enum ItemType {
 struct DialogueOption {
     text: String,           // Option text
     next_node: Option<usize>, // Next node to jump to (None means end dialogue)
+    low_int_text: Option<String>, // Alternate text for low-intelligence characters
+    low_int_next: Option<usize>,  // Alternate node reached only by low-intelligence characters
+    action: Option<DialogueAction>, // Effect triggered when this option is chosen
+    speech_check: Option<(i32, usize)>, // (difficulty, node on success); failure follows `next_node`
+    requires_item: Option<String>, // Item that must be carried to pick this option; it is handed over
+    requires_class: Option<PlayerClass>, // Class the player must be for this option to be offered at all
+}
+
+/// Side effects triggered by choosing a dialogue option
+#[derive(Clone)]
+enum DialogueAction {
+    StartQuest(usize), // Start the quest with this id
+    RepairItem { cost: i32 }, // Restore the equipped weapon to full durability for this many caps
+    Buy(fn() -> Item, i32), // Buy the item built by this factory at the given base price
+    Reward { caps: i32 }, // The NPC pays the player for a service
+    RevealRegion { center_x: i32, center_y: i32, radius: i32, cost: i32 }, // Sell a map uncovering the world around a spot
+}
+
+impl DialogueOption {
+    /// Create an option with no low-intelligence variant
+    fn new(text: &str, next_node: Option<usize>) -> Self {
+        DialogueOption {
+            text: text.to_string(),
+            next_node,
+            low_int_text: None,
+            low_int_next: None,
+            action: None,
+            speech_check: None,
+            requires_item: None,
+            requires_class: None,
+        }
+    }
+    
+    /// Gate this option on a speech check routing to `success_node` when passed
+    fn speech_check(mut self, difficulty: i32, success_node: usize) -> Self {
+        self.speech_check = Some((difficulty, success_node));
+        self
+    }
+    
+    /// Only allow this option while the player carries the named item, which is given away
+    fn requires_item(mut self, name: &str) -> Self {
+        self.requires_item = Some(name.to_string());
+        self
+    }
+    
+    /// Only offer this option to characters of the given class
+    fn requires_class(mut self, class: PlayerClass) -> Self {
+        self.requires_class = Some(class);
+        self
+    }
+    
+    /// Trigger an action when this option is chosen
+    fn with_action(mut self, action: DialogueAction) -> Self {
+        self.action = Some(action);
+        self
+    }
+    
+    /// Set the text shown to low-intelligence characters
+    fn low_int(mut self, text: &str) -> Self {
+        self.low_int_text = Some(text.to_string());
+        self
+    }
+    
+    /// Route low-intelligence characters to a different node
+    fn low_int_route(mut self, next_node: usize) -> Self {
+        self.low_int_next = Some(next_node);
+        self
+    }
+    
+    /// Resolve this option for the given intelligence mode
+    /// Falls back to the normal text/route when no variant is provided
+    fn resolved(&self, low_int: bool) -> DialogueOption {
+        if !low_int {
+            return self.clone();
+        }
+        DialogueOption {
+            text: self.low_int_text.clone().unwrap_or_else(|| self.text.clone()),
+            next_node: self.low_int_next.or(self.next_node),
+            low_int_text: None,
+            low_int_next: None,
+            action: self.action.clone(),
+            speech_check: self.speech_check,
+            requires_item: self.requires_item.clone(),
+            requires_class: self.requires_class,
+        }
+    }
 }
 
 /// Dialogue node structure
@@ -135,522 +569,6278 @@ struct DialogueNode {
     options: Vec<DialogueOption>,  // Available options
 }
 
-/// NPC (Non-Player Character) structure
+/// What a quest asks the player to do
 #[derive(Clone)]
-struct NPC {
-    name: String,           // NPC name
-    char: &'static str,     // Character displayed on map
-    x: i32,                 // NPC X coordinate
-    y: i32,                 // NPC Y coordinate
-    hp: i32,                // Current health
-    max_hp: i32,            // Maximum health
-    hostile: bool,          // Whether hostile (true = enemy, false = friendly)
-    dialogue: Vec<DialogueNode>,  // Branching dialogue tree
+enum QuestObjective {
+    KillNpc { name: String, map: String, count: i32 }, // Defeat `count` NPCs with this name on a map
+    DeliverItem { item: String, to: String },          // Hand an item over to an NPC
 }
 
-/// Player structure
-struct Player {
-    x: i32,                      // Player X coordinate
-    y: i32,                      // Player Y coordinate
-    hp: i32,                     // Current health
-    max_hp: i32,                 // Maximum health
-    inventory: Vec<Item>,        // Inventory item list
-    stats: PlayerStats,          // Player attributes
+/// Quest progress state
+#[derive(Clone, Copy, PartialEq)]
+enum QuestState {
+    Active,     // Objective not yet met
+    Completed,  // Objective met and reward granted
 }
 
-/// Player stats structure
-/// Mimics Fallout series SPECIAL system
-struct PlayerStats {
-    strength: i32,      // Strength - affects melee damage and carry weight
-    perception: i32,    // Perception - affects ranged accuracy
-    endurance: i32,     // Endurance - affects health and resistance
-    charisma: i32,      // Charisma - affects dialogue options
-    intelligence: i32,  // Intelligence - affects skill points
-    agility: i32,       // Agility - affects action points
-    luck: i32,          // Luck - affects critical hit rate
+/// Reward granted when a quest is completed
+#[derive(Clone)]
+struct QuestReward {
+    gold: i32,          // Caps
+    xp: i32,            // Experience points
+    item: Option<Item>, // Optional item
 }
 
-/// Game map structure
+/// Quest tracked in the journal
 #[derive(Clone)]
-struct GameMap {
-    width: i32,                          // Map width
-    height: i32,                         // Map height
-    tiles: Vec<Vec<TileType>>,           // 2D tile array
-    items: HashMap<(i32, i32), Item>,    // Item position mapping (coordinates -> item)
-    map_type: MapType,                   // Map type
-    name: String,                        // Map name
+struct Quest {
+    id: usize,                 // Quest identifier (see `Quest::by_id`)
+    title: String,             // Short title
+    description: String,       // Who gave it and why
+    objective: QuestObjective, // What needs to be done
+    progress: i32,             // Progress towards counted objectives
+    reward: QuestReward,       // Reward on completion
+    state: QuestState,         // Active or completed
 }
 
-impl GameMap {
-    /// Create world map
-    fn new_world_map() -> Self {
-        let width = 80;
-        let height = 40;
-        let mut tiles = vec![vec![TileType::Grass; width as usize]; height as usize];
-        
-        // Add mountains
-        for y in 5..10 {
-            for x in 20..30 {
-                tiles[y][x] = TileType::Mountain;
-            }
-        }
-        
-        // Add forests
-        for y in 15..25 {
-            for x in 10..20 {
-                tiles[y][x] = TileType::Forest;
-            }
-        }
-        
-        // Add water
-        for y in 30..35 {
-            for x in 40..60 {
-                tiles[y][x] = TileType::Water;
-            }
-        }
-        
-        // Place town entrances
-        tiles[10][15] = TileType::Town;
-        tiles[25][50] = TileType::Town;
-        
-        // Place dungeon entrances
-        tiles[8][40] = TileType::Dungeon;
-        tiles[30][25] = TileType::Dungeon;
-        
-        GameMap {
-            width,
-            height,
-            tiles,
-            items: HashMap::new(),
-            map_type: MapType::WorldMap,
-            name: "Wasteland".to_string(),
-        }
+/// Quest id: clear the guards of Dungeon #1 for the Townfolk
+const QUEST_CLEAR_GUARDS: usize = 0;
+/// Quest id: bring the Town Supply crate to the Blacksmith
+const QUEST_BLACKSMITH_SUPPLIES: usize = 1;
+
+impl Quest {
+    /// Look up a quest definition by id
+    fn by_id(id: usize) -> Option<Quest> {
+        let (title, description, objective, reward) = match id {
+            QUEST_CLEAR_GUARDS => (
+                "Guard Duty",
+                "The Townfolk want the guards of Dungeon #1 gone.",
+                QuestObjective::KillNpc {
+                    name: "Dungeon Guard".to_string(),
+                    map: "Dungeon #1".to_string(),
+                    count: 2,
+                },
+                QuestReward {
+                    gold: 100,
+                    xp: 150,
+                    item: Some(Item {
+                        name: "Leather Armor".to_string(),
+                        char: "[",
+                        item_type: ItemType::Armor { defense: 4 },
+                        durability: Some((ARMOR_DURABILITY, ARMOR_DURABILITY)),
+                        quantity: 1,
+                    }),
+                },
+            ),
+            QUEST_BLACKSMITH_SUPPLIES => (
+                "Special Delivery",
+                "The Blacksmith's supply crate is sitting by the town well.",
+                QuestObjective::DeliverItem {
+                    item: "Town Supply".to_string(),
+                    to: "Blacksmith".to_string(),
+                },
+                QuestReward {
+                    gold: 30,
+                    xp: 75,
+                    item: Some(Item {
+                        name: "Machete".to_string(),
+                        char: "/",
+                        item_type: ItemType::Weapon { damage: 20 },
+                        durability: Some((WEAPON_DURABILITY, WEAPON_DURABILITY)),
+                        quantity: 1,
+                    }),
+                },
+            ),
+            _ => return None,
+        };
+        Some(Quest {
+            id,
+            title: title.to_string(),
+            description: description.to_string(),
+            objective,
+            progress: 0,
+            reward,
+            state: QuestState::Active,
+        })
     }
     
-    /// Create town map
-    fn new_town_map(town_id: usize) -> Self {
-        let width = 40;
-        let height = 30;
-        let mut tiles = vec![vec![TileType::Floor; width as usize]; height as usize];
-        
-        // Create boundary walls
-        for y in 0..height {
-            for x in 0..width {
-                if x == 0 || x == width - 1 || y == 0 || y == height - 1 {
-                    tiles[y as usize][x as usize] = TileType::Wall;
-                }
-            }
-        }
-        
-        // Create buildings (rooms)
-        for x in 5..15 {
-            for y in 5..12 {
-                tiles[y][x] = TileType::Wall;
-            }
-        }
-        tiles[8][10] = TileType::Door;  // Door
-        
-        for x in 20..30 {
-            for y in 15..22 {
-                tiles[y][x] = TileType::Wall;
+    /// Human-readable objective with progress
+    fn objective_text(&self) -> String {
+        match &self.objective {
+            QuestObjective::KillNpc { name, map, count } => {
+                format!("Defeat {} in {} ({}/{})", name, map, self.progress.min(*count), count)
             }
-        }
-        tiles[18][25] = TileType::Door;
-        
-        // Add decorative water (well or fountain)
-        tiles[15][10] = TileType::Water;
-        
-        let mut items = HashMap::new();
-        items.insert((10, 15), Item {
-            name: "Town Supply".to_string(),
-            char: "$",
-            item_type: ItemType::Consumable { heal: 30 },
-        });
-        
-        GameMap {
-            width,
-            height,
-            tiles,
-            items,
-            map_type: MapType::Town,
-            name: format!("Town #{}", town_id + 1),
+            QuestObjective::DeliverItem { item, to } => format!("Bring {} to {}", item, to),
         }
     }
+}
+
+/// Game events that can advance quest objectives
+enum QuestEvent {
+    Killed(String),   // An NPC with this name was defeated
+    PickedUp(String), // An item with this name was picked up
+    Talked(String),   // A conversation with this NPC ended
+}
+
+/// A single line of conversation kept for later re-reading
+#[derive(Clone)]
+struct TranscriptEntry {
+    speaker: String,        // Who said it (NPC name)
+    text: String,           // What was said
+    chosen: Option<String>, // Player's reply (None if the conversation ended here)
+}
+
+/// Whether an enemy knows the player is around
+#[derive(Clone, Copy, PartialEq)]
+enum AlertState {
+    Unaware,  // Following its patrol route
+    Alerted,  // Spotted the player and closing in
+}
+
+/// Inventory category tabs, switched with Left/Right
+#[derive(Clone, Copy, PartialEq)]
+enum InventoryTab {
+    All,
+    Weapons,
+    Armor,
+    Consumables, // Anything used up: food, drink, medicine, torches and throwables
+    Quest,
+}
+
+impl InventoryTab {
+    const ALL: [InventoryTab; 5] = [
+        InventoryTab::All, InventoryTab::Weapons, InventoryTab::Armor, InventoryTab::Consumables, InventoryTab::Quest,
+    ];
     
-    /// Create dungeon map
-    fn new_dungeon_map(dungeon_id: usize) -> Self {
-        let width = 40;
-        let height = 30;
-        let mut tiles = vec![vec![TileType::Floor; width as usize]; height as usize];
-        
-        // Create maze-like dungeon layout
-        for y in 0..height {
-            for x in 0..width {
-                if x == 0 || x == width - 1 || y == 0 || y == height - 1 {
-                    tiles[y as usize][x as usize] = TileType::Wall;
-                }
-            }
-        }
-        
-        // Add interior walls to create corridors
-        for x in 10..15 {
-            tiles[5][x] = TileType::Wall;
-        }
-        tiles[5][12] = TileType::Door;
-        
-        for y in 10..20 {
-            tiles[y][20] = TileType::Wall;
-        }
-        tiles[15][20] = TileType::Door;
-        
-        // Add water/lava
-        for x in 25..30 {
-            for y in 8..12 {
-                tiles[y][x] = TileType::Water;
-            }
-        }
-        
-        let mut items = HashMap::new();
-        items.insert((5, 5), Item {
-            name: "Treasure Chest".to_string(),
-            char: "☐",
-            item_type: ItemType::Weapon { damage: 25 },
-        });
-        
-        GameMap {
-            width,
-            height,
-            tiles,
-            items,
-            map_type: MapType::Dungeon,
-            name: format!("Dungeon #{}", dungeon_id + 1),
+    fn name(&self) -> &'static str {
+        match self {
+            InventoryTab::All => "All",
+            InventoryTab::Weapons => "Weapons",
+            InventoryTab::Armor => "Armor",
+            InventoryTab::Consumables => "Consumables",
+            InventoryTab::Quest => "Quest",
         }
     }
     
-    /// Check if the specified coordinates are walkable
-    /// 
-    /// # Arguments
-    /// * `x` - X coordinate
-    /// * `y` - Y coordinate
-    /// 
-    /// # Returns
-    /// true if the position is walkable, false if not (wall, water, or out of bounds)
-    fn is_walkable(&self, x: i32, y: i32) -> bool {
-        // Check if coordinates are within map bounds
-        if x < 0 || x >= self.width || y < 0 || y >= self.height {
-            return false;
+    /// Whether the item is listed under this tab
+    fn contains(&self, item: &Item) -> bool {
+        match self {
+            InventoryTab::All => true,
+            InventoryTab::Weapons => matches!(item.item_type, ItemType::Weapon { .. }),
+            InventoryTab::Armor => matches!(item.item_type, ItemType::Armor { .. }),
+            InventoryTab::Consumables => matches!(item.item_type,
+                ItemType::Consumable { .. } | ItemType::Potion { .. } | ItemType::Torch { .. } | ItemType::Throwable { .. }),
+            InventoryTab::Quest => matches!(item.item_type, ItemType::Quest | ItemType::Note { .. }),
         }
-        // Check if the tile type at this position is passable
-        self.tiles[y as usize][x as usize].is_walkable()
+    }
+    
+    /// The tab `step` places to the right, wrapping around
+    fn cycled(&self, step: i32) -> InventoryTab {
+        let idx = InventoryTab::ALL.iter().position(|tab| tab == self).unwrap_or(0) as i32;
+        InventoryTab::ALL[(idx + step).rem_euclid(InventoryTab::ALL.len() as i32) as usize]
     }
 }
 
-/// Game state enumeration
-/// Defines which mode the game is currently in
-enum GameState {
-    Playing,           // Normal gameplay state (movement, exploration)
-    Inventory,         // Inventory interface
-    Dialogue(usize, usize, usize),   // Dialogue state (NPC index, current node index, selected option index)
-    Combat(usize),     // Combat state (enemy NPC index)
+/// Groups of NPCs that remember how the player treats their members
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Faction {
+    Townsfolk,  // People living in the towns
+    Wanderers,  // Travelers and everyone else out in the wasteland
 }
 
-/// Map location record
-/// Used to save player position when switching between maps
+/// NPC (Non-Player Character) structure
 #[derive(Clone)]
-struct MapLocation {
-    map_type: MapType,   // Map type
-    map_id: usize,       // Map ID (to distinguish different towns/dungeons)
-    x: i32,              // X coordinate when entering
-    y: i32,              // Y coordinate when entering
+struct Npc {
+    name: String,           // NPC name
+    char: &'static str,     // Character displayed on map
+    x: i32,                 // NPC X coordinate
+    y: i32,                 // NPC Y coordinate
+    hp: i32,                // Current health
+    max_hp: i32,            // Maximum health
+    hostile: bool,          // Whether hostile (true = enemy, false = friendly)
+    agility: i32,           // Agility - decides who strikes first in combat
+    damage: i32,            // Damage dealt per hit before armor
+    xp: i32,                // Experience awarded for defeating this NPC
+    dialogue: Vec<DialogueNode>,  // Branching dialogue tree
+    met: bool,              // Whether the player has talked to this NPC
+    patrol: Vec<(i32, i32)>, // Waypoints walked in a loop (empty = stands still)
+    patrol_step: usize,     // Index of the waypoint being walked to
+    home: (i32, i32),       // Where a friendly NPC wanders around
+    schedule: Vec<ScheduleEntry>, // Where it is at set times of day (none = wanders all day)
+    facing: (i32, i32),     // Direction of the last step, which is where it looks
+    alert_state: AlertState, // Whether it has noticed the player
+    aggro_radius: i32,      // Tiles within which it notices the player
+    leash: i32,             // Tiles away at which it gives up the chase and goes back to its rounds
+    last_seen: Option<u64>, // Turn it last saw the player within its leash (None = hasn't while alerted)
+    inventory: Vec<Item>,   // Items carried, which can be pickpocketed
+    gold_carried: i32,      // Caps carried, which can be pickpocketed
+    drops: Vec<Item>,       // Items only left behind on death, on top of what it carries
+    portrait: &'static [&'static str], // ASCII art shown beside its dialogue (empty = none)
+    faction: Option<Faction>, // Group that remembers how it was treated (None = wild enemy)
 }
 
-/// Main game structure
-/// Contains all game data and state
-struct Game {
-    player: Player,              // Player data
-    current_map: GameMap,        // Current map
-    world_map: GameMap,          // World map (cached)
-    town_maps: Vec<GameMap>,     // Town map list
-    dungeon_maps: Vec<GameMap>,  // Dungeon map list
-    npcs: Vec<NPC>,              // NPC list for current map
-    state: GameState,            // Current game state
-    messages: Vec<String>,       // Message log (max 5 messages)
-    camera_x: i32,               // Camera X coordinate (for map scrolling)
-    camera_y: i32,               // Camera Y coordinate (for map scrolling)
-    previous_location: Option<MapLocation>,  // Position before entering small map
+/// One stretch of an NPC's day
+#[derive(Clone)]
+struct ScheduleEntry {
+    start: f32,                  // Time of day it begins (0.0 = midnight)
+    end: f32,                    // Time of day it ends; before `start` when it runs past midnight
+    anchor: Option<(i32, i32)>,  // Where the NPC heads to (None = gone from the map)
+    asleep: bool,                // Whether the NPC sleeps there
 }
 
-impl Game {
-    /// Create new game instance
-    /// Initialize player, maps, NPCs and all game elements
-    fn new() -> Self {
-        // Create player character, initial position at world map (40, 20)
-        let player = Player {
-            x: 40,
-            y: 20,
-            hp: 100,
-            max_hp: 100,
-            inventory: vec![],  // Initial inventory is empty
-            stats: PlayerStats {
-                // Initial stat points all set to 5
-                strength: 5,
-                perception: 5,
-                endurance: 5,
-                charisma: 5,
-                intelligence: 5,
-                agility: 5,
-                luck: 5,
-            },
-        };
-        
-        // Create world map
-        let world_map = GameMap::new_world_map();
-        
-        // Pre-generate town maps
-        let town_maps = vec![
-            GameMap::new_town_map(0),
-            GameMap::new_town_map(1),
-        ];
-        
-        // Pre-generate dungeon maps
-        let dungeon_maps = vec![
-            GameMap::new_dungeon_map(0),
-            GameMap::new_dungeon_map(1),
-        ];
-        
-        // Current map initially is world map
-        let current_map = world_map.clone();
-        
-        // Create NPC list (NPCs on world map)
-        let npcs = vec![
-            NPC {
-                name: "Traveling Merchant".to_string(),
-                char: "♥",
-                x: 35,
-                y: 20,
-                hp: 50,
-                max_hp: 50,
-                hostile: false,
-                dialogue: vec![
-                    DialogueNode {
-                        text: "Howdy, stranger! What brings you to these parts?".to_string(),
-                        options: vec![
-                            DialogueOption { text: "I'm here for adventure!".to_string(), next_node: Some(1) },
-                            DialogueOption { text: "Just passing by.".to_string(), next_node: Some(2) },
-                            DialogueOption { text: "None of your business.".to_string(), next_node: None },
-                        ],
-                    },
-                    DialogueNode {
-                        text: "Adventure, eh? Well, watch out for demonic cows!".to_string(),
-                        options: vec![
-                            DialogueOption { text: "Thanks for the tip!".to_string(), next_node: None },
-                        ],
-                    },
-                    DialogueNode {
-                        text: "Safe travels, partner!".to_string(),
-                        options: vec![
-                            DialogueOption { text: "See ya!".to_string(), next_node: None },
-                        ],
-                    },
-                ],
-            },
-        ];
-        
-        Game {
-            player,
-            current_map,
-            world_map,
-            town_maps,
-            dungeon_maps,
-            npcs,
-            state: GameState::Playing,
-            messages: vec!["Welcome to the Wasteland! Press SPACE to enter towns/dungeons, ESC to return.".to_string()],
-            camera_x: 0,
-            camera_y: 0,
-            previous_location: None,
+impl ScheduleEntry {
+    /// Whether the entry is in effect at `time_of_day`
+    fn covers(&self, time_of_day: f32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&time_of_day)
+        } else {
+            time_of_day >= self.start || time_of_day < self.end
         }
     }
+}
+
+/// Time of day night falls
+const DUSK: f32 = 0.8;
+/// Time of day night ends
+const DAWN: f32 = 0.2;
+
+/// Portrait of a trader in a wide-brimmed hat
+const MERCHANT_PORTRAIT: &[&str] = &[
+    r"  _|___|_ ",
+    r" '-------'",
+    r"   (o o)  ",
+    r"    \-/   ",
+];
+/// Portrait of the smith, hammer in hand
+const BLACKSMITH_PORTRAIT: &[&str] = &[
+    r" .---.  [#]",
+    r"( o o )  | ",
+    r" \ = /   | ",
+    r" /|_|\--'  ",
+];
+/// Portrait of a friendly local
+const TOWNFOLK_PORTRAIT: &[&str] = &[
+    r"  .---. ",
+    r" ( o o )",
+    r" ( \_/ )",
+    r"  '---' ",
+];
+/// Portrait of the mapmaker, spectacles on and a scroll under one arm
+const CARTOGRAPHER_PORTRAIT: &[&str] = &[
+    r"  .---.   ",
+    r" (o-o  )  ",
+    r"  \_ / ==@",
+    r"  /| |\   ",
+];
+/// Portrait of a guard under a visored helmet
+const GUARD_PORTRAIT: &[&str] = &[
+    r"  _____  ",
+    r" /_____\ ",
+    r" |[o o]| ",
+    r"  \_-_/  ",
+];
+/// Portrait shared by hostiles without one of their own
+const SKULL_PORTRAIT: &[&str] = &[
+    r"  .---. ",
+    r" / x x \",
+    r" \  ^  /",
+    r"  |vvv| ",
+];
+
+/// Intelligence at or below which dialogue switches to low-intelligence variants
+const LOW_INT_THRESHOLD: i32 = 3;
+
+impl Npc {
+    /// Dialogue tree as seen by a character of the given intelligence mode and class
+    /// Options meant for another class are left out
+    fn dialogue_for(&self, low_int: bool, class: PlayerClass) -> Vec<DialogueNode> {
+        self.dialogue
+            .iter()
+            .map(|node| DialogueNode {
+                text: node.text.clone(),
+                options: node.options.iter()
+                    .filter(|opt| opt.requires_class.is_none_or(|required| required == class))
+                    .map(|opt| opt.resolved(low_int))
+                    .collect(),
+            })
+            .collect()
+    }
     
-    /// Add message to message log
-    /// Automatically removes oldest message if exceeds 5 messages
-    fn add_message(&mut self, msg: String) {
-        self.messages.push(msg);
-        if self.messages.len() > 5 {
-            self.messages.remove(0);  // Remove first (oldest) message
+    /// What the NPC is doing right now, by its schedule
+    fn scheduled(&self, time_of_day: f32) -> Option<&ScheduleEntry> {
+        self.schedule.iter().find(|entry| entry.covers(time_of_day))
+    }
+    
+    /// Whether the schedule has the NPC off the map right now
+    fn is_away(&self, time_of_day: f32) -> bool {
+        self.scheduled(time_of_day).is_some_and(|entry| entry.anchor.is_none())
+    }
+    
+    /// Whether the schedule has the NPC asleep right now
+    fn is_asleep(&self, time_of_day: f32) -> bool {
+        self.scheduled(time_of_day).is_some_and(|entry| entry.asleep)
+    }
+    
+    /// Build an enemy from its template, standing at (x, y)
+    /// It carries the template's loot and caps, and leaves those and its drops where it falls
+    fn spawn_enemy(template: &EnemyTemplate, x: i32, y: i32) -> Npc {
+        Npc {
+            name: template.name.to_string(),
+            char: template.char,
+            x,
+            y,
+            hp: template.hp,
+            max_hp: template.hp,
+            hostile: true,
+            agility: template.agility,
+            damage: template.damage,
+            xp: template.xp,
+            dialogue: vec![
+                DialogueNode {
+                    text: template.cry.to_string(),
+                    options: vec![
+                        DialogueOption::new(template.reply, None),
+                    ],
+                },
+            ],
+            met: false,
+            patrol: Vec::new(),
+            patrol_step: 0,
+            home: (x, y),
+            schedule: Vec::new(),
+            facing: (0, 1),
+            alert_state: AlertState::Unaware,
+            aggro_radius: template.aggro_radius,
+            leash: template.aggro_radius,
+            last_seen: None,
+            inventory: template.loot.iter()
+                .map(|(name, char, item_type)| Item {
+                    name: name.to_string(),
+                    char,
+                    item_type: item_type.clone(),
+                    durability: item_type.full_durability(),
+                    quantity: 1,
+                })
+                .collect(),
+            gold_carried: template.gold,
+            drops: template.drops.iter()
+                .map(|(name, char, item_type)| Item {
+                    name: name.to_string(),
+                    char,
+                    item_type: item_type.clone(),
+                    durability: item_type.full_durability(),
+                    quantity: 1,
+                })
+                .collect(),
+            portrait: template.portrait,
+            faction: None,
+        }
+    }
+}
+
+/// Stat block shared by every enemy of one kind
+struct EnemyTemplate {
+    name: &'static str,     // Name shown in combat and matched by quests
+    char: &'static str,     // Character displayed on map
+    hp: i32,                // Starting and maximum health, before difficulty scaling
+    damage: i32,            // Damage dealt per hit before armor
+    xp: i32,                // Experience awarded for a kill
+    agility: i32,           // Decides who strikes first in combat
+    aggro_radius: i32,      // Tiles within which it notices and keeps chasing the player
+    cry: &'static str,      // What it says when bumped into
+    reply: &'static str,    // The player's only answer
+    loot: &'static [(&'static str, &'static str, ItemType)], // Items carried (name, glyph, type)
+    drops: &'static [(&'static str, &'static str, ItemType)], // Items only left behind on death
+    gold: i32,              // Caps carried
+    portrait: &'static [&'static str], // ASCII art shown beside its dialogue
+    lore: &'static str,     // Bestiary description, revealed after the first kill
+}
+
+const RADSCORPION: EnemyTemplate = EnemyTemplate {
+    name: "Radscorpion", char: "S", hp: 60, damage: 9, xp: 30, agility: 5, aggro_radius: 6,
+    cry: "*click click*", reply: "Fight!", loot: &[], drops: &[], gold: 0,
+    portrait: SKULL_PORTRAIT,
+    lore: "Irradiated scorpion the size of a dog. Its stinger carries a nasty venom.",
+};
+const RAIDER: EnemyTemplate = EnemyTemplate {
+    name: "Raider", char: "R", hp: 40, damage: 8, xp: 20, agility: 6, aggro_radius: 8,
+    cry: "Your caps or your life!", reply: "Fight!",
+    loot: &[("Nuka-Cola", "!", ItemType::Consumable { heal: 10, hunger_restore: 0, thirst_restore: 25 })],
+    drops: &[("Pipe Wrench", "/", ItemType::Weapon { damage: 12 })],
+    gold: 8,
+    portrait: SKULL_PORTRAIT,
+    lore: "Wasteland scum who take what they want. Usually carry a few caps.",
+};
+const FERAL_DOG: EnemyTemplate = EnemyTemplate {
+    name: "Feral Dog", char: "d", hp: 25, damage: 6, xp: 12, agility: 8, aggro_radius: 10,
+    cry: "Grrrr...", reply: "Fight!", loot: &[], drops: &[], gold: 0,
+    portrait: SKULL_PORTRAIT,
+    lore: "Starving pack hounds gone wild. Quick to bite, quick to flee.",
+};
+const GIANT_MOLE_RAT: EnemyTemplate = EnemyTemplate {
+    name: "Giant Mole Rat", char: "r", hp: 20, damage: 4, xp: 10, agility: 4, aggro_radius: 4,
+    cry: "*squeak*", reply: "Fight!", loot: &[], drops: &[], gold: 0,
+    portrait: SKULL_PORTRAIT,
+    lore: "Burrowing rodent grown huge on radiation. More nuisance than threat.",
+};
+const MUTANT_BEAST: EnemyTemplate = EnemyTemplate {
+    name: "Mutant Beast", char: "M", hp: 100, damage: 12, xp: 50, agility: 7, aggro_radius: 5,
+    cry: "Hssssss...", reply: "Back away slowly...", loot: &[], drops: &[], gold: 0,
+    portrait: SKULL_PORTRAIT,
+    lore: "Hulking horror of fused muscle and scales. Best avoided when alone.",
+};
+const DUNGEON_GUARD: EnemyTemplate = EnemyTemplate {
+    name: "Dungeon Guard", char: "G", hp: 80, damage: 10, xp: 40, agility: 6, aggro_radius: 7,
+    cry: "Intruders must die!", reply: "Fight!",
+    loot: &[("Stimpak", "+", ItemType::Consumable { heal: 30, hunger_restore: 0, thirst_restore: 0 })],
+    drops: &[("Guard's Baton", "/", ItemType::Weapon { damage: 18 })],
+    gold: 25,
+    portrait: GUARD_PORTRAIT,
+    lore: "Armored sentry posted in the old vaults. Fights to the last.",
+};
+
+/// Watchman who turns out to run off anyone a town has come to hate
+/// Kept out of ENEMY_TEMPLATES so it never turns up in random encounters
+const TOWN_GUARD: EnemyTemplate = EnemyTemplate {
+    name: "Town Guard", char: "G", hp: 90, damage: 12, xp: 45, agility: 6, aggro_radius: 12,
+    cry: "We don't want your kind here!", reply: "Make me.",
+    loot: &[], drops: &[("Guard's Baton", "/", ItemType::Weapon { damage: 18 })],
+    gold: 15,
+    portrait: GUARD_PORTRAIT,
+    lore: "Townsfolk with a badge and a grudge, out to run troublemakers off.",
+};
+
+/// Every enemy kind, for looking a template up by name
+const ENEMY_TEMPLATES: [&EnemyTemplate; 6] = [
+    &RADSCORPION, &RAIDER, &FERAL_DOG, &GIANT_MOLE_RAT, &MUTANT_BEAST, &DUNGEON_GUARD,
+];
+
+/// Bestiary description of the enemy called `name`, if it has one
+fn enemy_lore(name: &str) -> &'static str {
+    ENEMY_TEMPLATES.iter().find(|t| t.name == name).map_or("", |t| t.lore)
+}
+
+/// What the player knows about one kind of enemy
+struct BestiaryEntry {
+    name: String,        // Enemy name, also the bestiary key
+    first_map: String,   // Map it was first fought on
+    kills: u32,          // How many have been defeated
+    description: String, // Lore, only shown once one has been killed
+}
+
+/// Who ambushes travelers on a world map tile, with relative weights
+fn encounter_table(tile: TileType) -> &'static [(&'static EnemyTemplate, u32)] {
+    match tile {
+        TileType::Forest => &[(&FERAL_DOG, 40), (&RAIDER, 30), (&RADSCORPION, 20), (&MUTANT_BEAST, 10)],
+        _ => &[(&GIANT_MOLE_RAT, 35), (&RAIDER, 30), (&FERAL_DOG, 20), (&RADSCORPION, 15)],
+    }
+}
+
+/// Who lurks in a dungeon at the given depth (1 is the shallowest), with relative weights
+/// Deeper dungeons shift weight from guards and raiders toward beasts and scorpions
+fn dungeon_enemy_table(depth: u32) -> Vec<(&'static EnemyTemplate, u32)> {
+    let deep = depth.saturating_sub(1) * 10;
+    vec![
+        (&DUNGEON_GUARD, 40u32.saturating_sub(deep)),
+        (&RAIDER, 20u32.saturating_sub(deep)),
+        (&RADSCORPION, 20 + deep),
+        (&MUTANT_BEAST, 20 + deep),
+    ]
+}
+
+/// Pick a template by cumulative weight, given a function returning a number below the total
+fn pick_enemy(table: &[(&'static EnemyTemplate, u32)], pick: impl FnOnce(u32) -> u32) -> &'static EnemyTemplate {
+    let total: u32 = table.iter().map(|&(_, weight)| weight).sum();
+    let mut pick = pick(total.max(1));
+    for &(template, weight) in table {
+        if pick < weight {
+            return template;
         }
+        pick -= weight;
+    }
+    table[0].0
+}
+
+/// Player structure
+struct Player {
+    name: String,                // Character name
+    class: PlayerClass,          // Class picked at the start of the run
+    x: i32,                      // Player X coordinate
+    y: i32,                      // Player Y coordinate
+    hp: i32,                     // Current health
+    max_hp: i32,                 // Maximum health
+    inventory: Vec<Item>,        // Inventory item list
+    stats: PlayerStats,          // Player attributes
+    skills: Skills,              // Player skills
+    level: i32,                  // Character level
+    xp: i32,                     // Experience points towards the next level
+    skill_points: i32,           // Unspent points for raising stats
+    pending_perks: i32,          // Perk choices earned but not yet made
+    perks: Vec<Perk>,            // Perks taken so far
+    gold: i32,                   // Currency carried
+    equipped_weapon: Option<usize>, // Inventory index of the equipped weapon
+    equipped_armor: Option<usize>,  // Inventory index of the equipped armor
+    hotbar: [Option<usize>; HOTBAR_SLOTS], // Inventory indices used by the number keys 1-5
+    torch_turns: i32,            // Turns left on the lit torch (0 when unlit)
+    hunger: i32,                 // Fullness, 0 (starving) to MAX_NEED
+    thirst: i32,                 // Hydration, 0 (parched) to MAX_NEED
+    regen_accumulator: u64,      // Turns toward the next point of passive regeneration
+    is_sneaking: bool,           // Moving slowly and quietly
+    sneak_move_delay: f32,       // Seconds before the next sneaking step is allowed
+    detection_level: f32,        // How close enemies are to noticing the player, 0.0 to 1.0
+    facing: (i32, i32),          // Direction of the last step taken or tried (only drawn)
+}
+
+/// Highest value any SPECIAL stat can reach
+const MAX_STAT: i32 = 10;
+/// Lowest a SPECIAL stat can be lowered to at character creation
+const MIN_STAT: i32 = 1;
+/// Spare points handed out at character creation, on top of the class's stats
+const CREATION_POINTS: i32 = 5;
+
+impl Player {
+    /// Experience needed to advance from the current level
+    fn xp_to_next_level(&self) -> i32 {
+        self.level * 100
     }
     
-    /// Move player
-    /// 
-    /// # Arguments
-    /// * `dx` - X axis movement delta (-1 left, 1 right)
-    /// * `dy` - Y axis movement delta (-1 up, 1 down)
-    fn move_player(&mut self, dx: i32, dy: i32) {
-        let new_x = self.player.x + dx;
-        let new_y = self.player.y + dy;
-        
-        // Check if there's an NPC at target position
-        if let Some(npc_idx) = self.npcs.iter().position(|n| n.x == new_x && n.y == new_y) {
-            // Trigger combat or dialogue based on NPC hostility
-            if self.npcs[npc_idx].hostile {
-                self.state = GameState::Combat(npc_idx);
-                self.add_message(format!("Combat with {}!", self.npcs[npc_idx].name));
-            } else {
-                self.state = GameState::Dialogue(npc_idx, 0, 0); // Start from node 0, option 0 selected
-            }
-            return;  // Don't move player position
+    /// Carry capacity derived from strength
+    fn carry_capacity(&self) -> i32 {
+        25 + self.stats.strength * 5
+    }
+    
+    /// Total weight of everything in the inventory
+    fn carry_weight(&self) -> i32 {
+        self.inventory.iter().map(|item| item.weight() * item.quantity as i32).sum()
+    }
+    
+    /// Chance in percent that an attack is a critical hit, derived from luck
+    fn crit_chance(&self) -> i32 {
+        4 + self.stats.luck * 2
+    }
+    
+    /// Score used for speech checks: charisma supplemented by the speech skill
+    fn speech_score(&self) -> i32 {
+        self.stats.charisma * 5 + self.skills.speech
+    }
+    
+    /// Price after barter skill: up to 30% off at 100 barter
+    fn barter_price(&self, base: i32) -> i32 {
+        (base * (100 - self.skills.barter * 3 / 10) / 100).max(1)
+    }
+    
+    /// Bonus experience in percent, derived from intelligence
+    fn xp_bonus(&self) -> i32 {
+        self.stats.intelligence * 5
+    }
+    
+    /// Damage dealt by a basic attack (equipped weapon, or fists)
+    fn attack_damage(&self) -> i32 {
+        match self.equipped_weapon.map(|idx| &self.inventory[idx].item_type) {
+            Some(ItemType::Weapon { damage }) => *damage,
+            _ => 15,
         }
-        
-        // Check map collision (walls, water, etc.)
-        if self.current_map.is_walkable(new_x, new_y) {
-            // Update player position
-            self.player.x = new_x;
-            self.player.y = new_y;
-            
-            // Check if there's an item to pick up
-            if let Some(item) = self.current_map.items.remove(&(new_x, new_y)) {
-                self.add_message(format!("Picked up {}", item.name));
-                self.player.inventory.push(item);  // Add item to inventory
-            }
+    }
+    
+    /// Damage absorbed by equipped armor
+    fn defense(&self) -> i32 {
+        match self.equipped_armor.map(|idx| &self.inventory[idx].item_type) {
+            Some(ItemType::Armor { defense }) => *defense,
+            _ => 0,
         }
     }
     
-    /// Try to enter town or dungeon
-    fn try_enter_location(&mut self) {
-        let x = self.player.x;
-        let y = self.player.y;
-        
-        // Can only enter towns/dungeons from world map
-        if self.current_map.map_type != MapType::WorldMap {
-            return;
+    /// Inventory indices of everything that can be thrown in combat
+    fn throwables(&self) -> Vec<usize> {
+        (0..self.inventory.len())
+            .filter(|&idx| matches!(self.inventory[idx].item_type, ItemType::Throwable { .. }))
+            .collect()
+    }
+    
+    /// Inventory indices of the items listed under `tab`, in inventory order
+    fn tab_items(&self, tab: InventoryTab) -> Vec<usize> {
+        (0..self.inventory.len()).filter(|&idx| tab.contains(&self.inventory[idx])).collect()
+    }
+    
+    /// Inventory index of the first item with this name
+    fn item_index(&self, name: &str) -> Option<usize> {
+        self.inventory.iter().position(|item| item.name == name)
+    }
+    
+    /// Wear down the inventory item at `idx` by one use
+    /// Items without durability never wear; returns the item's name if it broke and was thrown away
+    fn wear_item(&mut self, idx: usize) -> Option<String> {
+        let (current, _) = self.inventory[idx].durability.as_mut()?;
+        *current -= 1;
+        if *current > 0 {
+            return None;
         }
-        
-        let tile = self.current_map.tiles[y as usize][x as usize];
-        if !tile.is_enterable() {
-            return;
+        Some(self.remove_item(idx).name)
+    }
+    
+    /// Add an item to the inventory, topping up a stack of the same consumable if there is one
+    fn add_item(&mut self, item: Item) {
+        let stack = self.inventory.iter_mut()
+            .find(|held| item.stacks() && held.stacks() && held.name == item.name);
+        match stack {
+            Some(held) => held.quantity += item.quantity,
+            None => self.inventory.push(item),
         }
-        
-        // Save current position
-        self.previous_location = Some(MapLocation {
-            map_type: MapType::WorldMap,
-            map_id: 0,
-            x,
-            y,
-        });
-        
-        // Enter different maps based on tile type
-        match tile {
-            TileType::Town => {
-                // Determine which town to enter based on position
-                let town_id = if (x, y) == (15, 10) { 0 } else { 1 };
-                self.current_map = self.town_maps[town_id].clone();
-                self.player.x = 20;
-                self.player.y = 15;
-                self.load_town_npcs(town_id);
-                self.add_message(format!("Entered {}", self.current_map.name));
-            }
-            TileType::Dungeon => {
-                // Determine which dungeon to enter based on position
-                let dungeon_id = if (x, y) == (40, 8) { 0 } else { 1 };
-                self.current_map = self.dungeon_maps[dungeon_id].clone();
-                self.player.x = 5;
-                self.player.y = 5;
-                self.load_dungeon_npcs(dungeon_id);
-                self.add_message(format!("Entered {}", self.current_map.name));
+    }
+    
+    /// Take a single item from the stack at `idx`, removing the slot once it is empty
+    fn take_one(&mut self, idx: usize) -> Item {
+        let held = &mut self.inventory[idx];
+        if held.quantity > 1 {
+            held.quantity -= 1;
+            Item { quantity: 1, ..held.clone() }
+        } else {
+            self.remove_item(idx)
+        }
+    }
+    
+    /// Remove an item from the inventory, keeping equipment and hotbar indices valid
+    fn remove_item(&mut self, idx: usize) -> Item {
+        for slot in [&mut self.equipped_weapon, &mut self.equipped_armor].into_iter().chain(self.hotbar.iter_mut()) {
+            match *slot {
+                Some(i) if i == idx => *slot = None,
+                Some(i) if i > idx => *slot = Some(i - 1),
+                _ => {}
             }
-            _ => {}
         }
+        self.inventory.remove(idx)
     }
     
-    /// Return to world map
-    fn return_to_world_map(&mut self) {
-        if self.current_map.map_type == MapType::WorldMap {
-            return;  // Already on world map
+    /// Use or equip the inventory item at `idx`
+    /// Returns a message describing what happened
+    fn use_item(&mut self, idx: usize) -> String {
+        let item = &self.inventory[idx];
+        match item.item_type {
+            ItemType::Weapon { .. } => {
+                if self.equipped_weapon == Some(idx) {
+                    self.equipped_weapon = None;
+                    format!("Unequipped {}", item.name)
+                } else {
+                    self.equipped_weapon = Some(idx);
+                    format!("Equipped {}", item.name)
+                }
+            }
+            ItemType::Armor { .. } => {
+                if self.equipped_armor == Some(idx) {
+                    self.equipped_armor = None;
+                    format!("Took off {}", item.name)
+                } else {
+                    self.equipped_armor = Some(idx);
+                    format!("Put on {}", item.name)
+                }
+            }
+            ItemType::Consumable { heal, hunger_restore, thirst_restore } => {
+                let healed = heal.min(self.max_hp - self.hp);
+                self.hp += healed;
+                self.hunger = (self.hunger + hunger_restore).min(MAX_NEED);
+                self.thirst = (self.thirst + thirst_restore).min(MAX_NEED);
+                let item = self.take_one(idx);
+                let mut effects = vec![format!("+{} HP", healed)];
+                if hunger_restore > 0 {
+                    effects.push(format!("+{} food", hunger_restore));
+                }
+                if thirst_restore > 0 {
+                    effects.push(format!("+{} water", thirst_restore));
+                }
+                format!("Used {} ({})", item.name, effects.join(", "))
+            }
+            ItemType::Potion { heal_pct } => {
+                let healed = (self.max_hp * heal_pct / 100).min(self.max_hp - self.hp);
+                self.hp += healed;
+                let item = self.take_one(idx);
+                format!("Used {} (+{} HP)", item.name, healed)
+            }
+            ItemType::Torch { turns } => {
+                self.torch_turns = turns;
+                let item = self.remove_item(idx);
+                format!("Lit a {} ({} turns)", item.name, turns)
+            }
+            ItemType::Quest => format!("{} can't be used", item.name),
+            ItemType::Material => format!("{} can't be used on its own", item.name),
+            ItemType::Gold { .. } => format!("{} can't be used", item.name),
+            ItemType::Throwable { .. } => format!("{} can only be thrown in combat", item.name),
+            ItemType::Note { .. } => format!("Press R to read the {}", item.name),
         }
-        
-        if let Some(prev_loc) = &self.previous_location {
-            self.current_map = self.world_map.clone();
-            self.player.x = prev_loc.x;
-            self.player.y = prev_loc.y;
-            self.previous_location = None;
-            
-            // Load world map NPCs
-            self.load_world_npcs();
-            self.add_message("Returned to world map".to_string());
+    }
+    
+    /// How many tiles around the player are lit in dungeons
+    fn light_radius(&self) -> i32 {
+        if self.torch_turns > 0 { TORCHLIT_RADIUS } else { DARK_RADIUS }
+    }
+    
+    /// Equipment slot the inventory item at `idx` goes into, with the slot's name
+    /// None for items that aren't gear
+    fn gear_slot(&self, idx: usize) -> Option<(Option<usize>, &'static str)> {
+        match self.inventory[idx].item_type {
+            ItemType::Weapon { .. } => Some((self.equipped_weapon, "Weapon")),
+            ItemType::Armor { .. } => Some((self.equipped_armor, "Armor")),
+            _ => None,
         }
     }
     
-    /// Load world map NPCs
-    fn load_world_npcs(&mut self) {
-        self.npcs = vec![
-            NPC {
-                name: "Traveling Merchant".to_string(),
-                char: "♥",
-                x: 35,
-                y: 20,
-                hp: 50,
-                max_hp: 50,
-                hostile: false,
-                dialogue: vec![
-                    DialogueNode {
-                        text: "Howdy, stranger! What brings you to these parts?".to_string(),
-                        options: vec![
-                            DialogueOption { text: "I'm here for adventure!".to_string(), next_node: Some(1) },
-                            DialogueOption { text: "Just passing by.".to_string(), next_node: Some(2) },
-                            DialogueOption { text: "None of your business.".to_string(), next_node: None },
-                        ],
-                    },
-                    DialogueNode {
-                        text: "Adventure, eh? Well, watch out for demonic cows!".to_string(),
-                        options: vec![
-                            DialogueOption { text: "Thanks for the tip!".to_string(), next_node: None },
-                        ],
-                    },
-                    DialogueNode {
-                        text: "Safe travels, partner!".to_string(),
-                        options: vec![
-                            DialogueOption { text: "See ya!".to_string(), next_node: None },
-                        ],
-                    },
-                ],
-            },
-        ];
+    /// Display name of an equipped item, or "None"
+    fn equipped_name(&self, slot: Option<usize>) -> &str {
+        slot.map_or("None", |idx| self.inventory[idx].name.as_str())
     }
     
-    /// Load town NPCs
-    fn load_town_npcs(&mut self, _town_id: usize) {
-        self.npcs = vec![
-            NPC {
-                name: "Townfolk".to_string(),
-                char: "☺",
-                x: 15,
-                y: 15,
-                hp: 50,
-                max_hp: 50,
-                hostile: false,
-                dialogue: vec![
-                    DialogueNode {
-                        text: "Welcome to our town! Are you lost or just weird?".to_string(),
-                        options: vec![
-                            DialogueOption { text: "A bit of both, honestly.".to_string(), next_node: Some(1) },
-                            DialogueOption { text: "I'm looking for work.".to_string(), next_node: Some(2) },
-                        ],
-                    },
-                    DialogueNode {
-                        text: "That's the spirit! You'll fit right in.".to_string(),
-                        options: vec![
-                            DialogueOption { text: "Thanks?".to_string(), next_node: None },
+    /// Max HP from endurance and level alone, before perks
+    fn base_max_hp(endurance: i32, level: i32) -> i32 {
+        50 + endurance * 10 + (level - 1) * 10
+    }
+    
+    /// Recalculate values derived from SPECIAL stats and level
+    /// Raising max HP also heals by the same amount
+    fn update_derived_stats(&mut self) {
+        let perk_hp: i32 = self.perks.iter()
+            .map(|perk| match perk.effect {
+                PerkEffect::MaxHpBoost(amount) => amount,
+                _ => 0,
+            })
+            .sum();
+        let new_max_hp = Player::base_max_hp(self.stats.endurance, self.level) + perk_hp;
+        self.hp = (self.hp + new_max_hp - self.max_hp).clamp(1, new_max_hp);
+        self.max_hp = new_max_hp;
+    }
+    
+    /// Award experience, levelling up as many times as it allows
+    /// Returns true if at least one level was gained
+    fn gain_xp(&mut self, amount: i32) -> bool {
+        self.xp += amount;
+        let mut leveled = false;
+        while self.xp >= self.xp_to_next_level() {
+            self.xp -= self.xp_to_next_level();
+            self.level_up();
+            leveled = true;
+        }
+        leveled
+    }
+    
+    /// Advance one level, granting skill points based on intelligence
+    fn level_up(&mut self) {
+        self.level += 1;
+        self.skill_points += 1 + self.stats.intelligence / 4;
+        self.pending_perks += 1;
+        self.update_derived_stats();
+    }
+    
+    /// Whether a perk has unlocked the named ability
+    fn has_ability(&self, ability: &str) -> bool {
+        self.perks.iter().any(|perk| matches!(perk.effect, PerkEffect::UnlockAbility(name) if name == ability))
+    }
+    
+    /// Take a perk and apply its effect immediately
+    fn add_perk(&mut self, perk: Perk) {
+        match perk.effect {
+            PerkEffect::StatBoost(stat, amount) => {
+                let value = self.stats.get_mut(stat);
+                *value = (*value + amount).min(MAX_STAT);
+            }
+            PerkEffect::SkillBoost(skill, amount) => {
+                let value = self.skills.get_mut(skill);
+                *value = (*value + amount).min(MAX_SKILL);
+            }
+            PerkEffect::MaxHpBoost(_) | PerkEffect::UnlockAbility(_) => {}
+        }
+        self.perks.push(perk);
+        self.update_derived_stats();
+    }
+    
+    /// Three random perks to choose from, skipping abilities already unlocked
+    fn roll_perk_choices(&self) -> Vec<Perk> {
+        let available: Vec<Perk> = PERKS.iter()
+            .filter(|perk| match perk.effect {
+                PerkEffect::UnlockAbility(name) => !self.has_ability(name),
+                _ => true,
+            })
+            .copied()
+            .collect();
+        available.choose_multiple(3).copied().collect()
+    }
+}
+
+/// Perk granted on level up
+#[derive(Clone, Copy)]
+struct Perk {
+    name: &'static str,
+    description: &'static str,
+    effect: PerkEffect,
+}
+
+/// What a perk does when taken
+#[derive(Clone, Copy)]
+enum PerkEffect {
+    StatBoost(StatType, i32),      // Raise a SPECIAL stat
+    SkillBoost(SkillType, i32),    // Raise a skill
+    MaxHpBoost(i32),               // Raise maximum health
+    UnlockAbility(&'static str),   // Unlock a named ability
+}
+
+/// Ability unlocked by the Bloody Mess perk: enemies die more spectacularly
+const ABILITY_BLOODY_MESS: &str = "Bloody Mess";
+
+/// Ability unlocked by the Outdoorsman perk: wandering hostiles never ambush the player
+const ABILITY_OUTDOORSMAN: &str = "Outdoorsman";
+
+/// Library of perks offered on level up
+const PERKS: [Perk; 13] = [
+    Perk { name: "Strong Back", description: "+1 Strength", effect: PerkEffect::StatBoost(StatType::Strength, 1) },
+    Perk { name: "Eagle Eye", description: "+1 Perception", effect: PerkEffect::StatBoost(StatType::Perception, 1) },
+    Perk { name: "Fast Learner", description: "+1 Intelligence", effect: PerkEffect::StatBoost(StatType::Intelligence, 1) },
+    Perk { name: "Lady Luck", description: "+1 Luck", effect: PerkEffect::StatBoost(StatType::Luck, 1) },
+    Perk { name: "Toughness", description: "+20 max HP", effect: PerkEffect::MaxHpBoost(20) },
+    Perk { name: "Life Giver", description: "+30 max HP", effect: PerkEffect::MaxHpBoost(30) },
+    Perk { name: "Smooth Talker", description: "+15 Speech", effect: PerkEffect::SkillBoost(SkillType::Speech, 15) },
+    Perk { name: "Locksmith", description: "+15 Lockpick", effect: PerkEffect::SkillBoost(SkillType::Lockpick, 15) },
+    Perk { name: "Shadow Step", description: "+15 Sneak", effect: PerkEffect::SkillBoost(SkillType::Sneak, 15) },
+    Perk { name: "Field Medic", description: "+15 First Aid", effect: PerkEffect::SkillBoost(SkillType::FirstAid, 15) },
+    Perk { name: "Brawler", description: "+15 Melee Weapons", effect: PerkEffect::SkillBoost(SkillType::MeleeWeapons, 15) },
+    Perk { name: "Bloody Mess", description: "Enemies die in spectacular fashion", effect: PerkEffect::UnlockAbility(ABILITY_BLOODY_MESS) },
+    Perk { name: "Outdoorsman", description: "No random encounters in the wasteland", effect: PerkEffect::UnlockAbility(ABILITY_OUTDOORSMAN) },
+];
+
+/// Player stats structure
+/// Mimics Fallout series SPECIAL system
+struct PlayerStats {
+    strength: i32,      // Strength - affects melee damage and carry weight
+    perception: i32,    // Perception - affects ranged accuracy
+    endurance: i32,     // Endurance - affects health and resistance
+    charisma: i32,      // Charisma - affects dialogue options
+    intelligence: i32,  // Intelligence - affects skill points
+    agility: i32,       // Agility - affects action points
+    luck: i32,          // Luck - affects critical hit rate
+}
+
+/// Player skills
+/// Start out derived from SPECIAL and improve through use and level-ups
+struct Skills {
+    lockpick: i32,       // Picking locks without a key
+    speech: i32,         // Persuasion in dialogue checks (with charisma)
+    sneak: i32,          // Moving unnoticed
+    first_aid: i32,      // Healing
+    small_guns: i32,     // Ranged weapons
+    melee_weapons: i32,  // Close combat weapons
+    barter: i32,         // Shop prices
+}
+
+/// Subtracted from perception x 10 when rolling to spot a trap within two tiles
+const TRAP_SPOT_DIFFICULTY: i32 = 40;
+/// Subtracted from agility x 10 (plus half the lockpick skill) when rolling to disarm a trap
+const TRAP_DISARM_DIFFICULTY: i32 = 20;
+/// Subtracted from perception x 10 when rolling to notice a hidden door
+const SECRET_DOOR_DIFFICULTY: i32 = 40;
+/// Added to the hidden door roll when searching on purpose; enough that perception 10 never misses
+const SEARCH_BONUS: i32 = 40;
+/// Chance in percent that a generated dungeon hides a secret room
+const SECRET_ROOM_CHANCE: i32 = 40;
+/// Chance in percent that a generated dungeon has a treasure room behind a lever-worked gate
+const LEVER_ROOM_CHANCE: i32 = 30;
+/// Floor area from which a guard walks the edge of its room instead of pacing across it
+const PATROL_LOOP_AREA: i32 = 24;
+/// Full hunger and thirst
+const MAX_NEED: i32 = 100;
+/// Turns per point of hunger lost
+const HUNGER_INTERVAL: u64 = 30;
+/// Turns per point of thirst lost
+const THIRST_INTERVAL: u64 = 20;
+/// Turns between 1 HP losses while starving or parched
+const STARVATION_INTERVAL: u64 = 1;
+/// Seconds the +1 regeneration popup stays on screen
+const REGEN_POPUP_DURATION: f32 = 0.8;
+/// Seconds between steps while sneaking
+const SNEAK_MOVE_DELAY: f32 = 0.6;
+/// Tiles within which a watching enemy raises the detection level
+const DETECTION_RANGE: i32 = 6;
+/// Seconds a step onto ordinary ground takes before the next one can start
+const BASE_MOVE_INTERVAL: f32 = 0.08;
+/// Aggro radius of townsfolk, should they ever turn on the player
+const AGGRO_RADIUS: i32 = 8;
+/// Most enemies that will take up a chase on sight at once
+const MAX_CHASERS: usize = 3;
+/// Turns a chasing enemy keeps on after losing sight of the player past its leash
+const LOSE_TRACK_TURNS: u64 = 3;
+/// Enemies roaming the world map
+const WORLD_ENEMIES: usize = 6;
+/// Tiles within which a roaming world map enemy notices the player
+const WORLD_AGGRO_RADIUS: i32 = 6;
+/// Tiles away at which a roaming world map enemy gives up the chase
+const WORLD_LEASH: i32 = 12;
+/// Closest a roaming enemy starts to the player, in tiles
+const WORLD_ENEMY_MIN_DISTANCE: i32 = 12;
+/// Percent chance a friendly NPC takes a step each turn
+const WANDER_CHANCE: i32 = 30;
+/// Farthest a friendly NPC strays from home, in tiles
+const WANDER_RADIUS: i32 = 5;
+/// How much faster the player is noticed when not sneaking
+const NOISY_DETECTION_FACTOR: f32 = 4.0;
+/// Detection lost per second while nobody is watching
+const DETECTION_DECAY: f32 = 0.05;
+/// Strength needed to smash through a weak wall
+const MIN_SMASH_STRENGTH: i32 = 7;
+/// World map steps after an encounter before another can happen
+const ENCOUNTER_SAFE_STEPS: u32 = 15;
+/// Agility needed to be offered a pickpocket attempt
+const MIN_PICKPOCKET_AGILITY: i32 = 5;
+/// Reputation lost with an NPC's faction when caught pickpocketing
+const PICKPOCKET_REPUTATION_LOSS: i32 = 20;
+/// Reputation lost with a faction when one of its members is killed
+const MURDER_REPUTATION_LOSS: i32 = 50;
+/// Reputation gained with the towns for each quest completed
+const QUEST_REPUTATION_GAIN: i32 = 15;
+/// Reputation from which a faction greets the player warmly and gives a discount
+const REPUTATION_FRIENDLY: i32 = 25;
+/// Reputation at which a faction refuses to talk, charges extra and sends town guards
+const REPUTATION_HOSTILE: i32 = -25;
+/// Town guards waiting at the entrance of a town that hates the player
+const TOWN_GUARDS: usize = 2;
+/// Karma lost for killing someone who was not a wild enemy
+const MURDER_KARMA: i32 = 50;
+/// Karma lost for every successful theft
+const THEFT_KARMA: i32 = 5;
+/// Karma gained for each quest completed
+const QUEST_KARMA: i32 = 10;
+/// Karma titles from the lowest up, each with the karma it takes to earn it
+const KARMA_TIERS: [(i32, &str); 7] = [
+    (i32::MIN, "Scourge"),
+    (-99, "Villain"),
+    (-39, "Shady"),
+    (-9, "Neutral"),
+    (10, "Decent"),
+    (40, "Hero"),
+    (100, "Saint"),
+];
+
+/// One-word title for a karma score
+fn karma_tier(karma: i32) -> &'static str {
+    KARMA_TIERS.iter().rev().find(|&&(min, _)| karma >= min).map_or("Neutral", |&(_, name)| name)
+}
+/// Thirst restored by drinking from open water
+const DRINK_RESTORE: i32 = 30;
+/// Dungeon light radius without a torch
+const DARK_RADIUS: i32 = 2;
+/// Dungeon light radius while a torch is lit
+const TORCHLIT_RADIUS: i32 = 6;
+/// Dungeon turns a fresh torch burns for
+const TORCH_TURNS: i32 = 150;
+/// Turns left when the torch starts flickering
+const TORCH_WARNING_TURNS: i32 = 10;
+/// Quick-use slots on the hotbar, bound to the number keys from 1
+const HOTBAR_SLOTS: usize = 5;
+/// Number keys for the hotbar slots, in order
+const HOTBAR_KEYS: [KeyCode; HOTBAR_SLOTS] = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5];
+
+/// Base price of a torch at the town merchant
+const TORCH_PRICE: i32 = 15;
+/// Base price of a grenade at the town merchant
+const GRENADE_PRICE: i32 = 40;
+/// Highest value any skill can reach
+const MAX_SKILL: i32 = 100;
+/// Skill increase bought with one skill point
+const SKILL_POINT_VALUE: i32 = 5;
+
+impl Skills {
+    /// Initial skill values derived from SPECIAL stats
+    fn from_stats(stats: &PlayerStats) -> Self {
+        Skills {
+            lockpick: stats.perception * 3 + stats.agility * 2,
+            speech: stats.charisma * 4,
+            sneak: stats.agility * 3 + stats.luck,
+            first_aid: stats.endurance * 2 + stats.intelligence * 2,
+            small_guns: stats.agility * 2 + stats.perception * 2,
+            melee_weapons: stats.strength * 3 + stats.agility,
+            barter: stats.charisma * 3 + stats.intelligence,
+        }
+    }
+    
+    /// Read a skill by type
+    fn get(&self, skill: SkillType) -> i32 {
+        match skill {
+            SkillType::Lockpick => self.lockpick,
+            SkillType::Speech => self.speech,
+            SkillType::Sneak => self.sneak,
+            SkillType::FirstAid => self.first_aid,
+            SkillType::SmallGuns => self.small_guns,
+            SkillType::MeleeWeapons => self.melee_weapons,
+            SkillType::Barter => self.barter,
+        }
+    }
+    
+    /// Mutable access to a skill by type
+    fn get_mut(&mut self, skill: SkillType) -> &mut i32 {
+        match skill {
+            SkillType::Lockpick => &mut self.lockpick,
+            SkillType::Speech => &mut self.speech,
+            SkillType::Sneak => &mut self.sneak,
+            SkillType::FirstAid => &mut self.first_aid,
+            SkillType::SmallGuns => &mut self.small_guns,
+            SkillType::MeleeWeapons => &mut self.melee_weapons,
+            SkillType::Barter => &mut self.barter,
+        }
+    }
+    
+    /// Improve a skill through use, up to the cap
+    fn practice(&mut self, skill: SkillType) {
+        let value = self.get_mut(skill);
+        *value = (*value + 1).min(MAX_SKILL);
+    }
+}
+
+/// Skill identifiers
+#[derive(Clone, Copy, PartialEq)]
+enum SkillType {
+    Lockpick,
+    Speech,
+    Sneak,
+    FirstAid,
+    SmallGuns,
+    MeleeWeapons,
+    Barter,
+}
+
+impl SkillType {
+    /// All skills in display order
+    const ALL: [SkillType; 7] = [
+        SkillType::Lockpick,
+        SkillType::Speech,
+        SkillType::Sneak,
+        SkillType::FirstAid,
+        SkillType::SmallGuns,
+        SkillType::MeleeWeapons,
+        SkillType::Barter,
+    ];
+    
+    /// Display name of the skill
+    fn name(&self) -> &'static str {
+        match self {
+            SkillType::Lockpick => "Lockpick",
+            SkillType::Speech => "Speech",
+            SkillType::Sneak => "Sneak",
+            SkillType::FirstAid => "First Aid",
+            SkillType::SmallGuns => "Small Guns",
+            SkillType::MeleeWeapons => "Melee Weapons",
+            SkillType::Barter => "Barter",
+        }
+    }
+}
+
+/// SPECIAL stat identifiers
+#[derive(Clone, Copy, PartialEq)]
+enum StatType {
+    Strength,
+    Perception,
+    Endurance,
+    Charisma,
+    Intelligence,
+    Agility,
+    Luck,
+}
+
+impl StatType {
+    /// All stats in SPECIAL order
+    const ALL: [StatType; 7] = [
+        StatType::Strength,
+        StatType::Perception,
+        StatType::Endurance,
+        StatType::Charisma,
+        StatType::Intelligence,
+        StatType::Agility,
+        StatType::Luck,
+    ];
+    
+    /// Display name of the stat
+    fn name(&self) -> &'static str {
+        match self {
+            StatType::Strength => "Strength",
+            StatType::Perception => "Perception",
+            StatType::Endurance => "Endurance",
+            StatType::Charisma => "Charisma",
+            StatType::Intelligence => "Intelligence",
+            StatType::Agility => "Agility",
+            StatType::Luck => "Luck",
+        }
+    }
+}
+
+impl PlayerStats {
+    /// Read a stat by type
+    fn get(&self, stat: StatType) -> i32 {
+        match stat {
+            StatType::Strength => self.strength,
+            StatType::Perception => self.perception,
+            StatType::Endurance => self.endurance,
+            StatType::Charisma => self.charisma,
+            StatType::Intelligence => self.intelligence,
+            StatType::Agility => self.agility,
+            StatType::Luck => self.luck,
+        }
+    }
+    
+    /// Mutable access to a stat by type
+    fn get_mut(&mut self, stat: StatType) -> &mut i32 {
+        match stat {
+            StatType::Strength => &mut self.strength,
+            StatType::Perception => &mut self.perception,
+            StatType::Endurance => &mut self.endurance,
+            StatType::Charisma => &mut self.charisma,
+            StatType::Intelligence => &mut self.intelligence,
+            StatType::Agility => &mut self.agility,
+            StatType::Luck => &mut self.luck,
+        }
+    }
+}
+
+/// What a trap does to whoever sets it off
+#[derive(Clone, Copy, PartialEq)]
+enum TrapKind {
+    Spikes,   // Spikes spring up from a pressure plate
+    Darts,    // A hidden launcher fires darts
+    Tripwire, // A wire pulls the pin on a rigged explosive
+}
+
+impl TrapKind {
+    const ALL: [TrapKind; 3] = [TrapKind::Spikes, TrapKind::Darts, TrapKind::Tripwire];
+    
+    fn name(&self) -> &'static str {
+        match self {
+            TrapKind::Spikes => "spike trap",
+            TrapKind::Darts => "dart trap",
+            TrapKind::Tripwire => "tripwire",
+        }
+    }
+    
+    /// Damage on the first dungeon floor; deeper floors add to it
+    fn base_damage(&self) -> i32 {
+        match self {
+            TrapKind::Spikes => 12,
+            TrapKind::Darts => 8,
+            TrapKind::Tripwire => 18,
+        }
+    }
+}
+
+/// A trap in the floor; it goes off once, then stays where it was as a harmless marker
+#[derive(Clone)]
+struct Trap {
+    kind: TrapKind,
+    damage: i32,     // Damage dealt when it goes off
+    detected: bool,  // Whether the player knows it is there (it is then drawn and stepped around)
+    sprung: bool,    // Whether it has already gone off
+}
+
+impl Trap {
+    /// A hidden, armed trap of a random kind, deadlier deeper down
+    fn roll(rng: &rand::RandGenerator, depth: u32) -> Trap {
+        let kind = TrapKind::ALL[rng.gen_range(0, TrapKind::ALL.len())];
+        Trap { kind, damage: kind.base_damage() + 3 * depth.saturating_sub(1) as i32, detected: false, sprung: false }
+    }
+    
+    /// Whether stepping here would set it off
+    fn armed(&self) -> bool {
+        !self.sprung
+    }
+}
+
+/// Something on the map that holds items, opened with Space from a neighboring tile
+/// It blocks movement, and its contents stay put until taken
+#[derive(Clone)]
+struct Container {
+    name: String,
+    locked: bool,       // Needs Lockpicks before it can be opened
+    contents: Vec<Item>,
+    opened: bool,       // Whether the player has looked inside
+}
+
+impl Container {
+    fn new(name: &str, contents: Vec<Item>) -> Self {
+        Container { name: name.to_string(), locked: false, contents, opened: false }
+    }
+}
+
+/// Wall tiles that a lever or pressure plate turns into open doorways and back
+#[derive(Clone)]
+struct Link {
+    targets: Vec<(i32, i32)>, // Tiles that are walls while shut and open doors while open
+    open: bool,               // Whether the gate tiles currently stand open
+    lever_open: bool,         // The lever is thrown to open
+    plate_held: bool,         // Someone stands on the pressure plate
+}
+
+/// Whether a door stands open, and what holds it shut if not
+/// Door tiles without an entry in `GameMap::doors` are open
+#[derive(Clone, Debug, PartialEq)]
+enum DoorState {
+    Open,                                            // Walkable and see-through
+    Closed,                                          // Opens when bumped
+    Locked { key: Option<String>, difficulty: i32 }, // Opens for the named key, or a pick beating the difficulty
+}
+
+/// Lock difficulty of a locked door drawn into a map without a recorded lock
+const DEFAULT_LOCK_DIFFICULTY: i32 = 30;
+/// Lock difficulty of a dungeon vault door
+const VAULT_LOCK_DIFFICULTY: i32 = 45;
+/// Lock difficulty of the town storehouse door
+const STOREHOUSE_LOCK_DIFFICULTY: i32 = 70;
+/// Name of the key to every town storehouse, hidden in the first dungeon
+const STOREHOUSE_KEY: &str = "Storehouse Key";
+
+/// Game map structure
+/// The default value is an empty placeholder left behind when a map is moved out of storage
+#[derive(Clone, Default)]
+struct GameMap {
+    width: i32,                          // Map width
+    height: i32,                         // Map height
+    tiles: Vec<Vec<TileType>>,           // 2D tile array
+    items: HashMap<(i32, i32), Item>,    // Item position mapping (coordinates -> item)
+    map_type: MapType,                   // Map type
+    name: String,                        // Map name
+    entry_point: (i32, i32),             // Where the player appears when entering
+    entrances: HashMap<(i32, i32), (MapType, usize)>, // Entrance tile -> destination map
+    traps: HashMap<(i32, i32), Trap>,    // Traps hidden in the floor, by position
+    containers: HashMap<(i32, i32), Container>, // Chests, barrels and shelves, by position
+    links: Vec<Link>,                    // Gates worked by levers and pressure plates, by link id
+    switches: HashMap<(i32, i32), usize>, // Lever and pressure plate positions, with the link each works
+    signs: HashMap<(i32, i32), String>,  // What each signpost says, by position
+    explored: Vec<Vec<bool>>,            // Tiles that have ever been in the player's field of view
+    spawns: Vec<Npc>,                    // Enemies placed at generation, moved into play on the first visit
+    markers: Vec<(String, (i32, i32))>,  // Named spots from a text map: NPC spawns, loot rolls, containers
+    doors: HashMap<(i32, i32), DoorState>, // Open, closed or locked state of each door tile
+}
+
+impl GameMap {
+    /// Create the classic hand-made world map
+    fn new_world_map() -> Self {
+        let width = 80;
+        let height = 40;
+        let mut tiles = vec![vec![TileType::Grass; width as usize]; height as usize];
+        
+        // Add mountains
+        for row in &mut tiles[5..10] {
+            row[20..30].fill(TileType::Mountain);
+        }
+        
+        // Add forests
+        for row in &mut tiles[15..25] {
+            row[10..20].fill(TileType::Forest);
+        }
+        
+        // Add water
+        for row in &mut tiles[30..35] {
+            row[40..60].fill(TileType::Water);
+        }
+        
+        // Place town and dungeon entrances, registering where each one leads
+        let mut entrances = HashMap::new();
+        for (x, y, map_type, map_id) in [
+            (15, 10, MapType::Town, 0),
+            (50, 25, MapType::Town, 1),
+            (40, 8, MapType::Dungeon, 0),
+            (25, 30, MapType::Dungeon, 1),
+        ] {
+            tiles[y as usize][x as usize] = match map_type {
+                MapType::Town => TileType::Town,
+                _ => TileType::Dungeon,
+            };
+            entrances.insert((x, y), (map_type, map_id));
+        }
+        
+        GameMap {
+            width,
+            height,
+            tiles,
+            items: HashMap::new(),
+            map_type: MapType::WorldMap,
+            name: "Wasteland".to_string(),
+            entry_point: (40, 20),
+            entrances,
+            traps: HashMap::new(),
+            containers: HashMap::new(),
+            links: Vec::new(),
+            switches: HashMap::new(),
+            signs: HashMap::new(),
+            explored: vec![vec![false; width as usize]; height as usize],
+            spawns: Vec::new(),
+            markers: Vec::new(),
+            doors: HashMap::new(),
+        }
+    }
+    
+    /// Generate a world map from noise
+    /// 
+    /// Two smoothed value-noise layers decide the terrain: low elevation floods into
+    /// water, high elevation rises into mountains, and damp lowland grows forest.
+    /// Towns, dungeon entrances and the player's spawn all go on the largest walkable
+    /// region at least 10 tiles apart, so every entrance can be walked to from the start.
+    fn generate_world(seed: u64, width: i32, height: i32) -> GameMap {
+        let rng = rand::RandGenerator::new();
+        rng.srand(seed);
+        let (w, h) = (width as usize, height as usize);
+        
+        let coarse_elevation = value_noise(&rng, width, height, 12);
+        let fine_elevation = value_noise(&rng, width, height, 5);
+        let moisture = value_noise(&rng, width, height, 8);
+        let mut tiles = vec![vec![TileType::Grass; w]; h];
+        for y in 0..h {
+            for x in 0..w {
+                let elevation = 0.65 * coarse_elevation[y][x] + 0.35 * fine_elevation[y][x];
+                tiles[y][x] = if elevation < 0.32 {
+                    TileType::Water
+                } else if elevation > 0.68 {
+                    TileType::Mountain
+                } else if moisture[y][x] > 0.6 {
+                    TileType::Forest
+                } else {
+                    TileType::Grass
+                };
+            }
+        }
+        
+        // Sites are the spawn point followed by every town and dungeon entrance
+        let site_count = 1 + WORLD_TOWNS + WORLD_DUNGEONS;
+        let mut sites: Vec<(i32, i32)> = Vec::new();
+        for _ in 0..2 {
+            // Largest walkable region, found by flood filling from every unvisited walkable tile
+            let mut visited = vec![vec![false; w]; h];
+            let mut region: Vec<(i32, i32)> = Vec::new();
+            for y in 0..height {
+                for x in 0..width {
+                    if visited[y as usize][x as usize] || !tiles[y as usize][x as usize].is_walkable() {
+                        continue;
+                    }
+                    let reached = flood_fill(&tiles, (x, y), |tile| tile.is_walkable());
+                    let cells: Vec<(i32, i32)> = (0..height)
+                        .flat_map(|ry| (0..width).map(move |rx| (rx, ry)))
+                        .filter(|&(rx, ry)| reached[ry as usize][rx as usize])
+                        .collect();
+                    for &(rx, ry) in &cells {
+                        visited[ry as usize][rx as usize] = true;
+                    }
+                    if cells.len() > region.len() {
+                        region = cells;
+                    }
+                }
+            }
+            
+            sites.clear();
+            for _ in 0..1000 {
+                if sites.len() == site_count || region.is_empty() {
+                    break;
+                }
+                let (x, y) = region[rng.gen_range(0, region.len())];
+                let far_enough = sites.iter().all(|&(sx, sy)| {
+                    let (dx, dy) = ((x - sx) as f32, (y - sy) as f32);
+                    (dx * dx + dy * dy).sqrt() >= 10.0
+                });
+                if far_enough {
+                    sites.push((x, y));
+                }
+            }
+            if sites.len() == site_count {
+                break;
+            }
+            
+            // Degenerate seed with too little open ground: fall back to open grassland
+            tiles = vec![vec![TileType::Grass; w]; h];
+        }
+        
+        let mut entrances = HashMap::new();
+        let destinations = (0..WORLD_TOWNS).map(|id| (MapType::Town, id))
+            .chain((0..WORLD_DUNGEONS).map(|id| (MapType::Dungeon, id)));
+        for (&(x, y), (map_type, map_id)) in sites[1..].iter().zip(destinations) {
+            tiles[y as usize][x as usize] = match map_type {
+                MapType::Town => TileType::Town,
+                _ => TileType::Dungeon,
+            };
+            entrances.insert((x, y), (map_type, map_id));
+        }
+        
+        GameMap {
+            width,
+            height,
+            tiles,
+            items: HashMap::new(),
+            map_type: MapType::WorldMap,
+            name: "Wasteland".to_string(),
+            entry_point: sites[0],
+            entrances,
+            traps: HashMap::new(),
+            containers: HashMap::new(),
+            links: Vec::new(),
+            switches: HashMap::new(),
+            signs: HashMap::new(),
+            explored: vec![vec![false; w]; h],
+            spawns: Vec::new(),
+            markers: Vec::new(),
+            doors: HashMap::new(),
+        }
+    }
+    
+    /// Create town map from the shared town layout
+    /// Cellar hatches lead down to this town's house and store cellars, in reading order.
+    /// Signs fill in `{town}` with the town's name and `{dungeon}` with the way to the
+    /// nearest dungeon on the `world` map
+    fn new_town_map(town_id: usize, layout: &GameMap, world: &GameMap, difficulty: Difficulty) -> Self {
+        let mut map = layout.clone();
+        map.name = format!("Town #{}", town_id + 1);
+        let gate = world.entrances.iter().find(|&(_, &dest)| dest == (MapType::Town, town_id)).map(|(&pos, _)| pos);
+        let nearest_dungeon = gate.and_then(|(tx, ty)| {
+            world.entrances.iter()
+                .filter(|&(_, &(map_type, _))| map_type == MapType::Dungeon)
+                .map(|(&(x, y), &(_, id))| (id, x - tx, y - ty))
+                .min_by_key(|&(id, dx, dy)| (dx.abs().max(dy.abs()), id))
+        });
+        let dungeon = match nearest_dungeon {
+            Some((id, dx, dy)) => format!(
+                "Dungeon #{} lies {} paces {}",
+                id + 1,
+                dx.abs().max(dy.abs()),
+                compass_direction(dx, dy),
+            ),
+            None => "Dungeons lie out in the wastes".to_string(),
+        };
+        for text in map.signs.values_mut() {
+            *text = text.replace("{town}", &map.name).replace("{dungeon}", &dungeon);
+        }
+        
+        let hatches: Vec<(i32, i32)> = (0..map.height)
+            .flat_map(|y| (0..map.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| map.tiles[y as usize][x as usize] == TileType::Dungeon)
+            .take(2)
+            .collect();
+        for (i, pos) in hatches.into_iter().enumerate() {
+            map.entrances.insert(pos, (MapType::Interior, town_id * 2 + i));
+        }
+        
+        // The second building's door is locked; its key lies in the first dungeon
+        let door = (0..map.height)
+            .flat_map(|y| (0..map.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| map.tiles[y as usize][x as usize] == TileType::Door)
+            .nth(1);
+        if let Some(pos) = door {
+            map.doors.insert(pos, DoorState::Locked {
+                key: Some(STOREHOUSE_KEY.to_string()),
+                difficulty: STOREHOUSE_LOCK_DIFFICULTY,
+            });
+        }
+        
+        // Roll the town table on every loot spot along the streets and in the house
+        let loot = town_loot_table();
+        let stores: Vec<(String, (i32, i32))> = map.markers.iter()
+            .filter_map(|(name, pos)| Some((name.strip_prefix("container ")?.to_string(), *pos)))
+            .collect();
+        for (name, pos) in stores {
+            let contents = (0..2).filter_map(|_| difficulty.roll_loot(&loot)).collect();
+            map.containers.insert(pos, Container::new(&name, contents));
+        }
+        map.scatter_loot(&loot, difficulty);
+        
+        map
+    }
+    
+    /// Roll `loot` on every `loot` marker, leaving whatever comes up lying there
+    fn scatter_loot(&mut self, loot: &LootTable, difficulty: Difficulty) {
+        let spots: Vec<(i32, i32)> = self.markers.iter()
+            .filter(|(name, _)| name == "loot")
+            .map(|&(_, pos)| pos)
+            .collect();
+        for pos in spots {
+            if let Some(item) = difficulty.roll_loot(loot) {
+                self.items.insert(pos, item);
+            }
+        }
+    }
+    
+    /// Positions of the hidden doors not yet found, in reading order
+    fn secret_doors(&self) -> Vec<(i32, i32)> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.tiles[y as usize][x as usize] == TileType::SecretDoor)
+            .collect()
+    }
+    
+    /// Bare floor tile next to (x, y), if any
+    fn free_tile_near(&self, (x, y): (i32, i32)) -> Option<(i32, i32)> {
+        (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(|&pos| pos != (x, y))
+            .find(|&(nx, ny)| {
+                self.is_walkable(nx, ny)
+                    && self.tiles[ny as usize][nx as usize] == TileType::Floor
+                    && !self.items.contains_key(&(nx, ny))
+                    && !self.traps.contains_key(&(nx, ny))
+            })
+    }
+    
+    /// Bare floor tile farthest (in steps on the grid) from the entry point
+    fn farthest_free_tile(&self) -> Option<(i32, i32)> {
+        let (ex, ey) = self.entry_point;
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.is_walkable(x, y) && self.tiles[y as usize][x as usize] == TileType::Floor && !self.items.contains_key(&(x, y)))
+            .max_by_key(|&(x, y)| (x - ex).abs() + (y - ey).abs())
+    }
+    
+    /// Parse a map from its plain-text form
+    /// 
+    /// Header lines before `---` place items (`$ 11,15 Consumable heal=30 Town Supply`)
+    /// and named spots (`@ 15,15 Townfolk`); the spot named `entry` is where the player
+    /// appears, spots named `sign <text>` become signposts, and `#` starts a comment.
+    /// Below `---` every character is one tile, drawn as `TileType::as_char` draws it.
+    /// Short lines and spaces are filled with walls.
+    /// Spots named after an enemy (`@ 12,4 Raider`) are where that enemy spawns.
+    /// Errors give the line and column of the first thing that couldn't be read.
+    fn from_text(name: &str, map_type: MapType, text: &str) -> Result<GameMap, String> {
+        let lines: Vec<&str> = text.lines().collect();
+        let Some(split) = lines.iter().position(|line| line.trim() == "---") else {
+            return Err(format!("line {}: missing `---` line before the tile grid", lines.len() + 1));
+        };
+        
+        // Header: items and markers, checked against the grid size once it is known
+        let mut items = Vec::new();
+        let mut markers = Vec::new();
+        for (i, line) in lines[..split].iter().enumerate() {
+            let tokens = text_tokens(line);
+            let Some(&(_, glyph)) = tokens.first() else { continue };
+            if glyph.starts_with('#') {
+                continue;
+            }
+            let at = |column: usize, message: String| format!("line {}, column {}: {}", i + 1, column, message);
+            let Some(&(pos_column, pos_text)) = tokens.get(1) else {
+                return Err(at(line.chars().count() + 1, "expected a position like 10,15".to_string()));
+            };
+            let pos = pos_text.split_once(',')
+                .and_then(|(x, y)| Some((x.parse::<i32>().ok()?, y.parse::<i32>().ok()?)))
+                .ok_or_else(|| at(pos_column, format!("expected a position like 10,15, found `{}`", pos_text)))?;
+            
+            if glyph == "@" {
+                let label: Vec<&str> = tokens[2..].iter().map(|&(_, token)| token).collect();
+                if label.is_empty() {
+                    return Err(at(line.chars().count() + 1, "expected a name for the marker".to_string()));
+                }
+                markers.push((i + 1, pos_column, label.join(" "), pos));
+                continue;
+            }
+            
+            let Some(&(kind_column, kind)) = tokens.get(2) else {
+                return Err(at(line.chars().count() + 1, "expected an item type".to_string()));
+            };
+            let mut stats: HashMap<&str, i32> = HashMap::new();
+            let mut rest = tokens[3..].iter().peekable();
+            while let Some(&&(column, token)) = rest.peek() {
+                let Some((key, value)) = token.split_once('=') else { break };
+                let value = value.parse().map_err(|_| at(column, format!("`{}` is not a number", value)))?;
+                stats.insert(key, value);
+                rest.next();
+            }
+            let item_name: Vec<&str> = rest.map(|&(_, token)| token).collect();
+            let stat = |key: &str| stats.get(key).copied().unwrap_or(0);
+            let (item_type, allowed): (ItemType, &[&str]) = match kind {
+                "Weapon" => (ItemType::Weapon { damage: stat("damage") }, &["damage"]),
+                "Armor" => (ItemType::Armor { defense: stat("defense") }, &["defense"]),
+                "Consumable" => (
+                    ItemType::Consumable { heal: stat("heal"), hunger_restore: stat("hunger"), thirst_restore: stat("thirst") },
+                    &["heal", "hunger", "thirst"],
+                ),
+                "Potion" => (ItemType::Potion { heal_pct: stat("pct") }, &["pct"]),
+                "Torch" => (ItemType::Torch { turns: stats.get("turns").copied().unwrap_or(TORCH_TURNS) }, &["turns"]),
+                "Quest" => (ItemType::Quest, &[]),
+                "Material" => (ItemType::Material, &[]),
+                "Gold" => (ItemType::Gold { amount: stat("amount") }, &["amount"]),
+                "Throwable" => (ItemType::Throwable { damage: stat("damage"), radius: stat("radius") }, &["damage", "radius"]),
+                _ => return Err(at(kind_column, format!("unknown item type `{}`", kind))),
+            };
+            if let Some(&(column, token)) = tokens[3..].iter()
+                .find(|(_, token)| token.split_once('=').is_some_and(|(key, _)| !allowed.contains(&key)))
+            {
+                return Err(at(column, format!("{} items have no stat `{}`", kind, token.split('=').next().unwrap_or(token))));
+            }
+            let item = Item {
+                name: if item_name.is_empty() { kind.to_string() } else { item_name.join(" ") },
+                char: intern_glyph(glyph),
+                durability: item_type.full_durability(),
+                quantity: 1,
+                item_type,
+            };
+            items.push((i + 1, pos_column, pos, item));
+        }
+        
+        // Tile grid, ignoring blank lines at the end of the file
+        let mut rows = &lines[split + 1..];
+        while let Some((last, rest)) = rows.split_last() && last.trim().is_empty() {
+            rows = rest;
+        }
+        if rows.is_empty() {
+            return Err(format!("line {}: no tile grid after `---`", split + 2));
+        }
+        let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0) as i32;
+        let height = rows.len() as i32;
+        let mut tiles = vec![vec![TileType::Wall; width as usize]; height as usize];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == ' ' {
+                    continue;
+                }
+                tiles[y][x] = TileType::from_char(c).ok_or_else(|| {
+                    format!("line {}, column {}: unknown tile `{}`", split + y + 2, x + 1, c)
+                })?;
+            }
+        }
+        
+        let in_bounds = |(x, y): (i32, i32)| x >= 0 && y >= 0 && x < width && y < height;
+        let outside = |line: usize, column: usize, (x, y): (i32, i32)| {
+            format!("line {}, column {}: {},{} is outside the {}x{} map", line, column, x, y, width, height)
+        };
+        let mut placed = HashMap::new();
+        for (line, column, pos, item) in items {
+            if !in_bounds(pos) {
+                return Err(outside(line, column, pos));
+            }
+            placed.insert(pos, item);
+        }
+        let mut named = Vec::new();
+        for (line, column, label, pos) in markers {
+            if !in_bounds(pos) {
+                return Err(outside(line, column, pos));
+            }
+            named.push((label, pos));
+        }
+        let entry_point = named.iter()
+            .find(|(label, _)| label == "entry")
+            .map_or((width / 2, height / 2), |&(_, pos)| pos);
+        
+        // Spots named `sign <text>` are signposts saying that text
+        let mut signs = HashMap::new();
+        for (label, (x, y)) in &named {
+            if let Some(text) = label.strip_prefix("sign ") {
+                tiles[*y as usize][*x as usize] = TileType::Sign;
+                signs.insert((*x, *y), text.to_string());
+            }
+        }
+        
+        // Spots named after an enemy are where one waits
+        let mut spawns = Vec::new();
+        named.retain(|(label, (x, y))| match ENEMY_TEMPLATES.iter().find(|template| template.name == label) {
+            Some(template) => {
+                spawns.push(Npc::spawn_enemy(template, *x, *y));
+                false
+            }
+            None => true,
+        });
+        
+        let mut map = GameMap {
+            width,
+            height,
+            tiles,
+            items: placed,
+            map_type,
+            name: name.to_string(),
+            entry_point,
+            entrances: HashMap::new(),
+            traps: HashMap::new(),
+            containers: HashMap::new(),
+            links: Vec::new(),
+            switches: HashMap::new(),
+            signs,
+            explored: vec![vec![false; width as usize]; height as usize],
+            spawns,
+            markers: named,
+            doors: HashMap::new(),
+        };
+        map.track_doors();
+        Ok(map)
+    }
+    
+    /// Tile at (x, y) as a text map writes it: doors show whether they are shut,
+    /// while hidden doors and traps stay hidden
+    fn written_tile(&self, x: i32, y: i32) -> TileType {
+        match self.doors.get(&(x, y)) {
+            Some(DoorState::Closed) => TileType::DoorClosed,
+            Some(DoorState::Locked { .. }) => TileType::DoorLocked,
+            _ => self.tiles[y as usize][x as usize],
+        }
+    }
+    
+    /// Write the map in the plain-text form `from_text` reads, with `npcs` as named spots
+    /// Notes, traps and hidden differences between look-alike tiles (weak walls, locks) are not kept
+    fn to_text(&self, npcs: &[Npc]) -> String {
+        let mut text = format!("# {}, saved from the map editor\n", self.name);
+        let mut items: Vec<(&(i32, i32), &Item)> = self.items.iter().collect();
+        items.sort_by_key(|((x, y), _)| (*y, *x));
+        for (&(x, y), item) in items {
+            let (kind, stats) = match &item.item_type {
+                ItemType::Weapon { damage } => ("Weapon", format!(" damage={}", damage)),
+                ItemType::Armor { defense } => ("Armor", format!(" defense={}", defense)),
+                ItemType::Consumable { heal, hunger_restore, thirst_restore } => {
+                    ("Consumable", format!(" heal={} hunger={} thirst={}", heal, hunger_restore, thirst_restore))
+                }
+                ItemType::Potion { heal_pct } => ("Potion", format!(" pct={}", heal_pct)),
+                ItemType::Torch { turns } => ("Torch", format!(" turns={}", turns)),
+                ItemType::Quest => ("Quest", String::new()),
+                ItemType::Material => ("Material", String::new()),
+                ItemType::Gold { amount } => ("Gold", format!(" amount={}", amount)),
+                ItemType::Throwable { damage, radius } => ("Throwable", format!(" damage={} radius={}", damage, radius)),
+                ItemType::Note { .. } => continue,
+            };
+            text += &format!("{} {},{} {}{} {}\n", item.char, x, y, kind, stats, item.name);
+        }
+        
+        text += &format!("@ {},{} entry\n", self.entry_point.0, self.entry_point.1);
+        for npc in npcs {
+            text += &format!("@ {},{} {}\n", npc.x, npc.y, npc.name);
+        }
+        for (name, (x, y)) in self.markers.iter().filter(|(name, _)| name == "loot") {
+            text += &format!("@ {},{} {}\n", x, y, name);
+        }
+        for (&(x, y), container) in &self.containers {
+            text += &format!("@ {},{} container {}\n", x, y, container.name);
+        }
+        for (&(x, y), sign) in &self.signs {
+            text += &format!("@ {},{} sign {}\n", x, y, sign);
+        }
+        
+        text += "---\n";
+        for y in 0..self.height {
+            text += &(0..self.width).map(|x| self.written_tile(x, y).as_char()).collect::<String>();
+            text += "\n";
+        }
+        text
+    }
+    
+    /// Create a building's cellar from the shared cellar layout
+    /// Interiors are numbered two per town: even ids are under houses, odd ids under stores
+    fn new_interior_map(interior_id: usize, layout: &GameMap, difficulty: Difficulty) -> Self {
+        let mut map = layout.clone();
+        let kind = if interior_id.is_multiple_of(2) { "House" } else { "Store" };
+        map.name = format!("Town #{} {} Cellar", interior_id / 2 + 1, kind);
+        map.scatter_loot(&town_loot_table(), difficulty);
+        map
+    }
+    
+    /// Generate a rooms-and-corridors dungeon
+    /// 
+    /// Up to eight non-overlapping rooms are joined in order by L-shaped corridors,
+    /// so every room is reachable from the entrance room. Doorways where a corridor
+    /// meets a room wall get closed doors. The room farthest from the entrance is
+    /// locked as a vault when that doesn't cut off the rest of the dungeon, and its
+    /// key is left in another room. Loot and enemies grow stronger with `depth`
+    /// (1 is the shallowest), and `difficulty` decides how many loot rolls come up empty.
+    fn generate_dungeon(seed: u64, depth: u32, difficulty: Difficulty) -> GameMap {
+        let rng = rand::RandGenerator::new();
+        rng.srand(seed);
+        let (width, height) = (40, 30);
+        let mut tiles = vec![vec![TileType::Wall; width as usize]; height as usize];
+        
+        // Rooms are (x, y, w, h) floor rectangles with at least one wall between them
+        // The first two are kept to opposite thirds of the map so there is always a far room
+        let mut rooms: Vec<(i32, i32, i32, i32)> = Vec::new();
+        for attempt in 0..200 {
+            if rooms.len() >= 8 {
+                break;
+            }
+            let w = rng.gen_range(4, 10);
+            let h = rng.gen_range(3, 7);
+            let (min_x, max_x) = match attempt {
+                0 => (1, width / 3 - w),
+                1 => (width * 2 / 3, width - 1 - w),
+                _ => (1, width - 1 - w),
+            };
+            let x = rng.gen_range(min_x, max_x + 1);
+            let y = rng.gen_range(1, height - h);
+            let overlaps = rooms.iter().any(|&(rx, ry, rw, rh)| x <= rx + rw && rx <= x + w && y <= ry + rh && ry <= y + h);
+            if overlaps {
+                continue;
+            }
+            for ty in y..y + h {
+                for tx in x..x + w {
+                    tiles[ty as usize][tx as usize] = TileType::Floor;
+                }
+            }
+            rooms.push((x, y, w, h));
+        }
+        let center = |&(x, y, w, h): &(i32, i32, i32, i32)| (x + w / 2, y + h / 2);
+        let room_cells = |&(x, y, w, h): &(i32, i32, i32, i32)| -> Vec<(i32, i32)> {
+            (y..y + h).flat_map(|cy| (x..x + w).map(move |cx| (cx, cy))).collect()
+        };
+        let in_room = |(cx, cy): (i32, i32), &(x, y, w, h): &(i32, i32, i32, i32)| {
+            cx >= x && cx < x + w && cy >= y && cy < y + h
+        };
+        
+        // Join each room to the previous one with an L-shaped corridor
+        for pair in rooms.windows(2) {
+            let (x1, y1) = center(&pair[0]);
+            let (x2, y2) = center(&pair[1]);
+            let corner = if rng.gen_range(0, 2) == 0 { (x2, y1) } else { (x1, y2) };
+            for ((ax, ay), (bx, by)) in [((x1, y1), corner), (corner, (x2, y2))] {
+                for ty in ay.min(by)..=ay.max(by) {
+                    for tx in ax.min(bx)..=ax.max(bx) {
+                        tiles[ty as usize][tx as usize] = TileType::Floor;
+                    }
+                }
+            }
+        }
+        
+        // A corridor cell in a room's wall ring with wall on both sides along the ring is a doorway
+        let mut room_doors = vec![Vec::new(); rooms.len()];
+        for (i, &(x, y, w, h)) in rooms.iter().enumerate() {
+            let horizontal = (x..x + w).flat_map(|cx| [(cx, y - 1), (cx, y + h)]).map(|pos| (pos, (1, 0)));
+            let vertical = (y..y + h).flat_map(|cy| [(x - 1, cy), (x + w, cy)]).map(|pos| (pos, (0, 1)));
+            for ((cx, cy), (dx, dy)) in horizontal.chain(vertical) {
+                let tile = |tx: i32, ty: i32| tiles[ty as usize][tx as usize];
+                if tile(cx, cy) != TileType::Wall
+                    && tile(cx - dx, cy - dy) == TileType::Wall
+                    && tile(cx + dx, cy + dy) == TileType::Wall
+                {
+                    tiles[cy as usize][cx as usize] = TileType::DoorClosed;
+                    room_doors[i].push((cx, cy));
+                }
+            }
+        }
+        
+        // Lock the room farthest from the entrance, unless that would cut anything else off
+        let entry_point = center(&rooms[0]);
+        let far = (1..rooms.len())
+            .max_by_key(|&i| {
+                let (cx, cy) = center(&rooms[i]);
+                (cx - entry_point.0).abs() + (cy - entry_point.1).abs()
+            })
+            .unwrap_or(0);
+        for &(dx, dy) in &room_doors[far] {
+            tiles[dy as usize][dx as usize] = TileType::DoorLocked;
+        }
+        let mut doors = HashMap::new();
+        for &pos in &room_doors[far] {
+            doors.insert(pos, DoorState::Locked { key: Some("Key".to_string()), difficulty: VAULT_LOCK_DIFFICULTY });
+        }
+        let reached = flood_fill(&tiles, entry_point, |tile| tile.is_walkable() || tile == TileType::DoorClosed);
+        let sealed = far != 0 && !room_doors[far].is_empty() && (0..height).all(|ty| {
+            (0..width).all(|tx| {
+                let floor = tiles[ty as usize][tx as usize] == TileType::Floor;
+                !floor || reached[ty as usize][tx as usize] != in_room((tx, ty), &rooms[far])
+            })
+        });
+        if !sealed {
+            for &(dx, dy) in &room_doors[far] {
+                tiles[dy as usize][dx as usize] = TileType::DoorClosed;
+            }
+            doors.clear();
+        }
+        
+        // Weak walls open alternate paths for strong characters: the shortest run of wall
+        // (up to six tiles) from the vault out to the reachable dungeon, and a couple of
+        // thin walls elsewhere
+        let inside = |tx: i32, ty: i32| tx > 0 && ty > 0 && tx < width - 1 && ty < height - 1;
+        if sealed {
+            let (x, y, w, h) = rooms[far];
+            let ring = (x..x + w).flat_map(|cx| [((cx, y - 1), (0, -1)), ((cx, y + h), (0, 1))])
+                .chain((y..y + h).flat_map(|cy| [((x - 1, cy), (-1, 0)), ((x + w, cy), (1, 0))]));
+            let mut breach: Option<Vec<(i32, i32)>> = None;
+            for ((cx, cy), (dx, dy)) in ring {
+                let mut path = Vec::new();
+                let (mut tx, mut ty) = (cx, cy);
+                while inside(tx, ty) && tiles[ty as usize][tx as usize] == TileType::Wall && path.len() < 6 {
+                    path.push((tx, ty));
+                    (tx, ty) = (tx + dx, ty + dy);
+                }
+                let opens = inside(tx, ty) && reached[ty as usize][tx as usize] && tiles[ty as usize][tx as usize].is_walkable();
+                if opens && !path.is_empty() && breach.as_ref().is_none_or(|best| path.len() < best.len()) {
+                    breach = Some(path);
+                }
+            }
+            for (tx, ty) in breach.unwrap_or_default() {
+                tiles[ty as usize][tx as usize] = TileType::WeakWall;
+            }
+        }
+        let thin_walls: Vec<(i32, i32)> = (1..height - 1)
+            .flat_map(|ty| (1..width - 1).map(move |tx| (tx, ty)))
+            .filter(|&(tx, ty)| {
+                let floor = |(fx, fy): (i32, i32)| tiles[fy as usize][fx as usize] == TileType::Floor;
+                tiles[ty as usize][tx as usize] == TileType::Wall
+                    && ((floor((tx - 1, ty)) && floor((tx + 1, ty))) || (floor((tx, ty - 1)) && floor((tx, ty + 1))))
+            })
+            .collect();
+        for _ in 0..2 {
+            if !thin_walls.is_empty() {
+                let (tx, ty) = thin_walls[rng.gen_range(0, thin_walls.len())];
+                tiles[ty as usize][tx as usize] = TileType::WeakWall;
+            }
+        }
+        
+        // Items, traps and enemies each get a tile of their own, never the entry point;
+        // a few random picks are tried before settling for the first free tile in order
+        let mut used = HashSet::from([entry_point]);
+        let mut take_cell = |cells: &[(i32, i32)]| {
+            if cells.is_empty() {
+                return None;
+            }
+            for _ in 0..20 {
+                let pos = cells[rng.gen_range(0, cells.len())];
+                if used.insert(pos) {
+                    return Some(pos);
+                }
+            }
+            cells.iter().copied().find(|&pos| used.insert(pos))
+        };
+        let entrance_cells = room_cells(&rooms[0]);
+        let far_cells = room_cells(&rooms[far]);
+        let other_cells: Vec<(i32, i32)> = rooms.iter().enumerate()
+            .filter(|&(i, _)| i != 0 && i != far)
+            .flat_map(|(_, room)| room_cells(room))
+            .collect();
+        let inner_cells = if other_cells.is_empty() { &entrance_cells } else { &other_cells };
+        let corridor_cells: Vec<(i32, i32)> = (0..height)
+            .flat_map(|ty| (0..width).map(move |tx| (tx, ty)))
+            .filter(|&(tx, ty)| tiles[ty as usize][tx as usize] == TileType::Floor && !rooms.iter().any(|room| in_room((tx, ty), room)))
+            .collect();
+        
+        let mut items = HashMap::new();
+        if let Some(pos) = take_cell(&entrance_cells) {
+            items.insert(pos, Item::torch());
+        }
+        // The far room's chest holds the stash and a roll of the loot table; it is locked
+        // unless the vault door already keeps it safe
+        // Chests get five tries in their own room, then any free inner room or corridor tile,
+        // so their contents are never lost
+        let loot = default_dungeon_loot_table(depth.saturating_sub(1) as usize);
+        let mut containers = HashMap::new();
+        let spare_cells: Vec<(i32, i32)> = inner_cells.iter().chain(&corridor_cells).copied().collect();
+        for attempt in 0..5 + spare_cells.len() {
+            let cells = if attempt < 5 { &far_cells } else { &spare_cells };
+            let Some(pos) = take_cell(cells) else {
+                continue;
+            };
+            if is_chokepoint(&tiles, entry_point, pos) {
+                continue;
+            }
+            let mut contents = vec![Item {
+                name: "Vault Stash".to_string(),
+                char: "$",
+                item_type: ItemType::Consumable { heal: 50, hunger_restore: 30, thirst_restore: 0 },
+                durability: None,
+                quantity: 1,
+            }];
+            contents.extend(difficulty.roll_loot_with(&loot, &rng));
+            let mut chest = Container::new("Chest", contents);
+            chest.locked = !sealed;
+            containers.insert(pos, chest);
+            break;
+        }
+        if sealed && let Some(pos) = take_cell(inner_cells) {
+            items.insert(pos, Item {
+                name: "Key".to_string(),
+                char: "k",
+                item_type: ItemType::Quest,
+                durability: None,
+                quantity: 1,
+            });
+        }
+        // The rest of the treasure shares a second, unlocked chest in one of the inner rooms
+        let contents: Vec<Item> = (0..rng.gen_range(2, 5)).filter_map(|_| difficulty.roll_loot_with(&loot, &rng)).collect();
+        for attempt in 0..5 + spare_cells.len() {
+            if contents.is_empty() {
+                break;
+            }
+            let cells = if attempt < 5 { inner_cells } else { &spare_cells };
+            if let Some(pos) = take_cell(cells)
+                && !is_chokepoint(&tiles, entry_point, pos)
+            {
+                containers.insert(pos, Container::new("Chest", contents));
+                break;
+            }
+        }
+        
+        // Small piles of caps, richer the deeper the dungeon
+        for _ in 0..rng.gen_range(2, 5) {
+            if let Some(pos) = take_cell(&corridor_cells) {
+                items.insert(pos, Item::caps(rng.gen_range(5, 16) * depth as i32));
+            }
+        }
+        
+        // Two to four traps in the corridors and outer rooms, none where they would cut
+        // the dungeon in two once spotted (spotted traps are stepped around)
+        let mut traps = HashMap::new();
+        let trap_cells: Vec<(i32, i32)> = corridor_cells.iter().chain(&other_cells).copied().collect();
+        let wanted = rng.gen_range(2, 5);
+        for _ in 0..20 {
+            if traps.len() == wanted {
+                break;
+            }
+            if let Some(pos) = take_cell(&trap_cells)
+                && pos != entry_point
+                && !is_chokepoint(&tiles, entry_point, pos)
+            {
+                traps.insert(pos, Trap::roll(&rng, depth));
+            }
+        }
+        
+        // Two guards always stand watch; the rest come from the depth's enemy table
+        let enemies = dungeon_enemy_table(depth);
+        let mut spawns = Vec::new();
+        for i in 0..rng.gen_range(2, 6) {
+            let Some((x, y)) = take_cell(inner_cells) else {
+                continue;
+            };
+            let template = if i < 2 {
+                &DUNGEON_GUARD
+            } else {
+                pick_enemy(&enemies, |total| rng.gen_range(0, total))
+            };
+            let mut npc = Npc::spawn_enemy(template, x, y);
+            // Guards walk the edge of a large room, or pace between their post and
+            // the opposite side of a small one
+            if template.name == DUNGEON_GUARD.name
+                && let Some(&(rx, ry, rw, rh)) = rooms.iter().find(|room| in_room((x, y), room))
+            {
+                npc.patrol = if rw * rh >= PATROL_LOOP_AREA {
+                    vec![(rx, ry), (rx + rw - 1, ry), (rx + rw - 1, ry + rh - 1), (rx, ry + rh - 1)]
+                } else {
+                    vec![(x, y), (2 * rx + rw - 1 - x, 2 * ry + rh - 1 - y)]
+                };
+            }
+            npc.max_hp = npc.max_hp * (3 + depth as i32) / 4;
+            npc.hp = npc.max_hp;
+            spawns.push(npc);
+        }
+        
+        let mut map = GameMap {
+            width,
+            height,
+            tiles,
+            items,
+            map_type: MapType::Dungeon,
+            name: "Dungeon".to_string(),
+            entry_point,
+            entrances: HashMap::new(),
+            traps,
+            containers,
+            links: Vec::new(),
+            switches: HashMap::new(),
+            signs: HashMap::new(),
+            explored: vec![vec![false; width as usize]; height as usize],
+            spawns,
+            markers: Vec::new(),
+            doors,
+        };
+        map.track_doors();
+        if rng.gen_range(0, 100) < SECRET_ROOM_CHANCE {
+            map.add_secret_room(&rng, depth, difficulty);
+        }
+        if rng.gen_range(0, 100) < LEVER_ROOM_CHANCE {
+            map.add_lever_room(&rng, depth, false, difficulty);
+        }
+        map
+    }
+    
+    /// Generate a cave dungeon with cellular automata
+    /// 
+    /// Tiles start as 55% random walls and are smoothed five times; only the
+    /// largest connected cave region is kept so every floor tile is reachable.
+    /// The player enters at the tile the flood fill of that region started from.
+    fn generate_cave_map(seed: u64, width: i32, height: i32) -> GameMap {
+        let rng = rand::RandGenerator::new();
+        rng.srand(seed);
+        let (w, h) = (width as usize, height as usize);
+        
+        // Random initial fill; borders are always walls
+        let mut tiles = vec![vec![TileType::Wall; w]; h];
+        for row in &mut tiles[1..h - 1] {
+            for tile in &mut row[1..w - 1] {
+                if rng.gen_range(0, 100) >= 55 {
+                    *tile = TileType::Floor;
+                }
+            }
+        }
+        
+        // Smoothing: a tile becomes wall when 5 or more of its 8 neighbors are walls
+        for _ in 0..5 {
+            let mut next = tiles.clone();
+            for y in 1..h - 1 {
+                for x in 1..w - 1 {
+                    let mut walls = 0;
+                    for dy in 0..3 {
+                        for dx in 0..3 {
+                            if (dx, dy) != (1, 1) && tiles[y + dy - 1][x + dx - 1] == TileType::Wall {
+                                walls += 1;
+                            }
+                        }
+                    }
+                    next[y][x] = if walls >= 5 { TileType::Wall } else { TileType::Floor };
+                }
+            }
+            tiles = next;
+        }
+        
+        // Flood fill every region and keep the largest one
+        // The first cell of each region is the tile its fill started from
+        let mut region_of = vec![vec![usize::MAX; w]; h];
+        let mut regions: Vec<Vec<(i32, i32)>> = Vec::new();
+        for y in 0..h {
+            for x in 0..w {
+                if tiles[y][x] != TileType::Floor || region_of[y][x] != usize::MAX {
+                    continue;
+                }
+                let id = regions.len();
+                let mut cells = Vec::new();
+                let mut stack = vec![(x as i32, y as i32)];
+                region_of[y][x] = id;
+                while let Some((cx, cy)) = stack.pop() {
+                    cells.push((cx, cy));
+                    for (nx, ny) in [(cx + 1, cy), (cx - 1, cy), (cx, cy + 1), (cx, cy - 1)] {
+                        let (ux, uy) = (nx as usize, ny as usize);
+                        if tiles[uy][ux] == TileType::Floor && region_of[uy][ux] == usize::MAX {
+                            region_of[uy][ux] = id;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+                regions.push(cells);
+            }
+        }
+        
+        let largest = regions.iter().enumerate().max_by_key(|(_, cells)| cells.len()).map(|(id, _)| id);
+        let cave_cells = match largest {
+            Some(id) => regions.swap_remove(id),
+            None => {
+                // Degenerate seed with no floor at all: carve a single chamber
+                for row in &mut tiles[1..h - 1] {
+                    row[1..w - 1].fill(TileType::Floor);
+                }
+                vec![(1, 1)]
+            }
+        };
+        let entry_point = cave_cells[0];
+        
+        // Fill disconnected pockets back in
+        for cells in &regions {
+            for &(x, y) in cells {
+                tiles[y as usize][x as usize] = TileType::Wall;
+            }
+        }
+        
+        // Scatter items on cave floor tiles away from the entrance
+        let loot = [
+            ("Cave Mushroom", "♣", ItemType::Consumable { heal: 20, hunger_restore: 25, thirst_restore: 0 }),
+            ("Glowing Moss", "♣", ItemType::Consumable { heal: 35, hunger_restore: 10, thirst_restore: 0 }),
+            ("Rusty Pipe", "/", ItemType::Weapon { damage: 18 }),
+            ("Bone Plating", "[", ItemType::Armor { defense: 2 }),
+            ("Torch", "i", ItemType::Torch { turns: TORCH_TURNS }),
+        ];
+        let mut items = HashMap::new();
+        let mut traps = HashMap::new();
+        let candidates: Vec<(i32, i32)> = cave_cells.iter()
+            .copied()
+            .filter(|&(x, y)| (x - entry_point.0).abs() + (y - entry_point.1).abs() > 5)
+            .collect();
+        if !candidates.is_empty() {
+            for (name, char, item_type) in loot {
+                let pos = candidates[rng.gen_range(0, candidates.len())];
+                items.insert(pos, Item {
+                    name: name.to_string(),
+                    char,
+                    durability: item_type.full_durability(),
+                    quantity: 1,
+                    item_type,
+                });
+            }
+            
+            // A few small piles of caps
+            for _ in 0..rng.gen_range(2, 4) {
+                let pos = candidates[rng.gen_range(0, candidates.len())];
+                items.entry(pos).or_insert_with(|| Item::caps(rng.gen_range(5, 16)));
+            }
+            
+            // Two to four traps on open floor, away from the narrow passages
+            let wanted = rng.gen_range(2, 5);
+            for _ in 0..20 {
+                if traps.len() == wanted {
+                    break;
+                }
+                let pos = candidates[rng.gen_range(0, candidates.len())];
+                if !items.contains_key(&pos) && !is_chokepoint(&tiles, entry_point, pos) {
+                    traps.insert(pos, Trap::roll(&rng, 1));
+                }
+            }
+        }
+        
+        GameMap {
+            width,
+            height,
+            tiles,
+            items,
+            map_type: MapType::Dungeon,
+            name: "Cave".to_string(),
+            entry_point,
+            entrances: HashMap::new(),
+            traps,
+            containers: HashMap::new(),
+            links: Vec::new(),
+            switches: HashMap::new(),
+            signs: HashMap::new(),
+            explored: vec![vec![false; width as usize]; height as usize],
+            spawns: Vec::new(),
+            markers: Vec::new(),
+            doors: HashMap::new(),
+        }
+    }
+    
+    /// Shortest walkable path from `start` to `goal`, ignoring whoever stands in the way
+    /// On the world map it only crosses land the player has explored
+    /// The path leaves out `start` and ends on `goal`; None if it can't be reached
+    fn find_path(&self, start: (i32, i32), goal: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+        let unexplored: HashSet<(i32, i32)> = if self.map_type == MapType::WorldMap {
+            (0..self.height)
+                .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+                .filter(|&(x, y)| !self.explored[y as usize][x as usize] && (x, y) != goal)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        astar(self, start, goal, &unexplored)
+    }
+    
+    /// Find the walkable tile closest to (x, y), searching outward ring by ring
+    /// Returns (x, y) unchanged if nothing walkable exists
+    fn nearest_walkable(&self, x: i32, y: i32) -> (i32, i32) {
+        for radius in 0..self.width.max(self.height) {
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    if dx.abs().max(dy.abs()) == radius && self.is_walkable(x + dx, y + dy) {
+                        return (x + dx, y + dy);
+                    }
+                }
+            }
+        }
+        (x, y)
+    }
+    
+    /// Check if the specified coordinates are walkable
+    /// 
+    /// # Arguments
+    /// * `x` - X coordinate
+    /// * `y` - Y coordinate
+    /// 
+    /// # Returns
+    /// true if the position is walkable, false if not (wall, water, or out of bounds)
+    fn is_walkable(&self, x: i32, y: i32) -> bool {
+        // Check if coordinates are within map bounds
+        if x < 0 || x >= self.width || y < 0 || y >= self.height {
+            return false;
+        }
+        // Check if the tile type at this position is passable and nothing stands on it
+        self.displayed_tile(x, y).is_walkable() && !self.containers.contains_key(&(x, y))
+    }
+    
+    /// Whether (x, y) lies on the map
+    fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && x < self.width && y >= 0 && y < self.height
+    }
+    
+    /// Tile as the player perceives it: traps look like floor until they are known,
+    /// and doors show whether they are open, closed or locked
+    fn displayed_tile(&self, x: i32, y: i32) -> TileType {
+        let tile = self.tiles[y as usize][x as usize];
+        match tile {
+            _ if self.traps.get(&(x, y)).is_some_and(|trap| trap.detected) => TileType::Trap,
+            TileType::SecretDoor => TileType::Wall,
+            TileType::Door => match self.doors.get(&(x, y)) {
+                Some(DoorState::Closed) => TileType::DoorClosed,
+                Some(DoorState::Locked { .. }) => TileType::DoorLocked,
+                _ => TileType::Door,
+            },
+            _ => tile,
+        }
+    }
+    
+    /// Wall off a small room behind a hidden door, with better loot than the dungeon around it
+    /// The room goes in solid rock next to any floor tile that has space for it: a 3x3 room
+    /// if there is room anywhere, otherwise a single-tile closet
+    /// Returns false if there wasn't even space for the closet
+    fn add_secret_room(&mut self, rng: &rand::RandGenerator, depth: u32, difficulty: Difficulty) -> bool {
+        let solid = |x: i32, y: i32| self.in_bounds(x, y) && self.tiles[y as usize][x as usize] == TileType::Wall;
+        for r in [1, 0] {
+            let mut spots = Vec::new();
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    if self.tiles[y as usize][x as usize] != TileType::Floor {
+                        continue;
+                    }
+                    for (dx, dy) in [(0, -1), (0, 1), (-1, 0), (1, 0)] {
+                        // The room just past the door, with rock all around it
+                        let (cx, cy) = (x + dx * (r + 2), y + dy * (r + 2));
+                        if (-r - 1..=r + 1).all(|oy| (-r - 1..=r + 1).all(|ox| solid(cx + ox, cy + oy))) {
+                            spots.push(((x + dx, y + dy), (cx, cy)));
+                        }
+                    }
+                }
+            }
+            if spots.is_empty() {
+                continue;
+            }
+            
+            let ((door_x, door_y), (cx, cy)) = spots[rng.gen_range(0, spots.len())];
+            self.tiles[door_y as usize][door_x as usize] = TileType::SecretDoor;
+            for y in cy - r..=cy + r {
+                for x in cx - r..=cx + r {
+                    self.tiles[y as usize][x as usize] = TileType::Floor;
+                }
+            }
+            let loot = default_dungeon_loot_table(depth as usize + 1);
+            for pos in [(cx - r, cy - r), (cx + r, cy + r)] {
+                if let Some(item) = difficulty.roll_loot_with(&loot, rng) {
+                    self.items.insert(pos, item);
+                }
+            }
+            self.items.entry((cx, cy)).or_insert_with(|| Item::caps(rng.gen_range(20, 41) * depth as i32));
+            return true;
+        }
+        false
+    }
+    
+    /// Wall off a treasure room behind a gate, worked by a lever as far from it as possible
+    /// Like a secret room it is 3x3 where the rock allows, else a single-tile closet.
+    /// Its chest holds two rolls of the next depth's loot table and a pile of caps.
+    /// With `plate`, a pressure plate two tiles out from the gate holds it open while stood
+    /// on, letting the player see the prize before hunting for the lever
+    /// Returns false if there was no rock to carve the room from
+    fn add_lever_room(&mut self, rng: &rand::RandGenerator, depth: u32, plate: bool, difficulty: Difficulty) -> bool {
+        let solid = |x: i32, y: i32| self.in_bounds(x, y) && self.tiles[y as usize][x as usize] == TileType::Wall;
+        let mut spots = Vec::new();
+        for r in [1, 0] {
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    if self.tiles[y as usize][x as usize] != TileType::Floor {
+                        continue;
+                    }
+                    for (dx, dy) in [(0, -1), (0, 1), (-1, 0), (1, 0)] {
+                        // The room just past the gate, with rock all around it
+                        let (cx, cy) = (x + dx * (r + 2), y + dy * (r + 2));
+                        if (-r - 1..=r + 1).all(|oy| (-r - 1..=r + 1).all(|ox| solid(cx + ox, cy + oy))) {
+                            spots.push((r, (x, y), (dx, dy), (cx, cy)));
+                        }
+                    }
+                }
+            }
+        }
+        // A plate needs open floor to sit on, so gates with some in front of them come first,
+        // then the bigger rooms
+        let floor = |x: i32, y: i32| self.in_bounds(x, y) && self.tiles[y as usize][x as usize] == TileType::Floor;
+        if plate && spots.iter().any(|&(_, (x, y), (dx, dy), _)| floor(x - dx, y - dy)) {
+            spots.retain(|&(_, (x, y), (dx, dy), _)| floor(x - dx, y - dy));
+        }
+        let Some(biggest) = spots.iter().map(|&(r, ..)| r).max() else {
+            return false;
+        };
+        spots.retain(|&(r, ..)| r == biggest);
+        
+        let (r, (x, y), (dx, dy), (cx, cy)) = spots[rng.gen_range(0, spots.len())];
+        for ry in cy - r..=cy + r {
+            for rx in cx - r..=cx + r {
+                self.tiles[ry as usize][rx as usize] = TileType::Floor;
+            }
+        }
+        let loot = default_dungeon_loot_table(depth as usize + 1);
+        let mut contents: Vec<Item> = (0..2).filter_map(|_| difficulty.roll_loot_with(&loot, rng)).collect();
+        contents.push(Item::caps(rng.gen_range(30, 61) * depth as i32));
+        self.containers.insert((cx, cy), Container::new("Chest", contents));
+        let gate = (x + dx, y + dy);
+        let link = self.links.len();
+        self.links.push(Link { targets: vec![gate], open: false, lever_open: false, plate_held: false });
+        
+        // The lever goes on the farthest floor tile from the gate that nothing needs to pass
+        let mut candidates: Vec<(i32, i32)> = (0..self.height)
+            .flat_map(|ly| (0..self.width).map(move |lx| (lx, ly)))
+            .filter(|&pos| {
+                self.tiles[pos.1 as usize][pos.0 as usize] == TileType::Floor
+                    && pos != self.entry_point
+                    && !self.items.contains_key(&pos)
+                    && !self.containers.contains_key(&pos)
+                    && !self.traps.contains_key(&pos)
+                    && (pos.0 - x).abs().max((pos.1 - y).abs()) > 2
+            })
+            .collect();
+        candidates.sort_by_key(|&(lx, ly)| std::cmp::Reverse((lx - gate.0).abs() + (ly - gate.1).abs()));
+        if let Some(&lever) = candidates.iter().find(|&&pos| !is_chokepoint(&self.tiles, self.entry_point, pos)) {
+            self.tiles[lever.1 as usize][lever.0 as usize] = TileType::Lever;
+            self.switches.insert(lever, link);
+        }
+        
+        let plate_pos = (x - dx, y - dy);
+        if plate
+            && self.in_bounds(plate_pos.0, plate_pos.1)
+            && self.tiles[plate_pos.1 as usize][plate_pos.0 as usize] == TileType::Floor
+        {
+            self.tiles[plate_pos.1 as usize][plate_pos.0 as usize] = TileType::PressurePlate;
+            self.traps.remove(&plate_pos);
+            self.switches.insert(plate_pos, link);
+        }
+        true
+    }
+    
+    /// Throw a link's lever open or shut
+    /// Returns whether its gate moved; a held plate keeps it open either way
+    fn set_lever(&mut self, link: usize, open: bool, blocked: &[(i32, i32)]) -> bool {
+        if let Some(gate) = self.links.get_mut(link) {
+            gate.lever_open = open;
+        }
+        self.refresh_link(link, blocked)
+    }
+    
+    /// Press or release a link's pressure plate
+    /// Returns whether its gate moved; a thrown lever keeps it open either way
+    fn set_plate(&mut self, link: usize, held: bool, blocked: &[(i32, i32)]) -> bool {
+        if let Some(gate) = self.links.get_mut(link) {
+            gate.plate_held = held;
+        }
+        self.refresh_link(link, blocked)
+    }
+    
+    /// Open a link's gate tiles while its lever or plate holds them, else shut them,
+    /// leaving any listed in `blocked` as they are
+    /// Returns false if the gate was already that way
+    fn refresh_link(&mut self, link: usize, blocked: &[(i32, i32)]) -> bool {
+        let Some(gate) = self.links.get_mut(link) else {
+            return false;
+        };
+        let open = gate.lever_open || gate.plate_held;
+        if gate.open == open {
+            return false;
+        }
+        gate.open = open;
+        for (x, y) in gate.targets.clone() {
+            if blocked.contains(&(x, y)) {
+                continue;
+            }
+            if open {
+                self.tiles[y as usize][x as usize] = TileType::Door;
+                self.doors.insert((x, y), DoorState::Open);
+            } else {
+                self.tiles[y as usize][x as usize] = TileType::Wall;
+                self.doors.remove(&(x, y));
+            }
+        }
+        true
+    }
+    
+    /// Turn doors drawn closed or locked into `Door` tiles with their state in `doors`
+    /// Locks already recorded keep their key and difficulty
+    fn track_doors(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let state = match self.tiles[y as usize][x as usize] {
+                    TileType::DoorClosed => DoorState::Closed,
+                    TileType::DoorLocked => DoorState::Locked { key: None, difficulty: DEFAULT_LOCK_DIFFICULTY },
+                    _ => continue,
+                };
+                self.tiles[y as usize][x as usize] = TileType::Door;
+                self.doors.entry((x, y)).or_insert(state);
+            }
+        }
+    }
+    
+    /// Check if the specified coordinates block sight (out of bounds counts as blocking)
+    fn blocks_sight(&self, x: i32, y: i32) -> bool {
+        if x < 0 || x >= self.width || y < 0 || y >= self.height {
+            return true;
+        }
+        self.displayed_tile(x, y).blocks_sight()
+    }
+    
+    /// Tiles visible from (x, y) within `radius`, using recursive shadowcasting
+    /// Walls are visible themselves but hide everything behind them
+    fn field_of_view(&self, x: i32, y: i32, radius: i32) -> HashSet<(i32, i32)> {
+        // Transforms mapping each of the eight octants onto the first one
+        const OCTANTS: [(i32, i32, i32, i32); 8] = [
+            (1, 0, 0, 1), (0, 1, 1, 0), (0, -1, 1, 0), (-1, 0, 0, 1),
+            (-1, 0, 0, -1), (0, -1, -1, 0), (0, 1, -1, 0), (1, 0, 0, -1),
+        ];
+        let mut visible = HashSet::new();
+        visible.insert((x, y));
+        for transform in OCTANTS {
+            self.cast_light(&mut visible, (x, y), radius, 1, 1.0, 0.0, transform);
+        }
+        visible
+    }
+    
+    /// Scan one octant row by row, recursing into the gaps left by blocking tiles
+    /// `start` and `end` are the slopes bounding the part of the octant still lit
+    #[allow(clippy::too_many_arguments)]
+    fn cast_light(
+        &self,
+        visible: &mut HashSet<(i32, i32)>,
+        origin: (i32, i32),
+        radius: i32,
+        row: i32,
+        mut start: f32,
+        end: f32,
+        (xx, xy, yx, yy): (i32, i32, i32, i32),
+    ) {
+        if start < end {
+            return;
+        }
+        for distance in row..=radius {
+            let dy = -distance;
+            let mut blocked = false;
+            let mut next_start = start;
+            for dx in -distance..=0 {
+                let map_x = origin.0 + dx * xx + dy * xy;
+                let map_y = origin.1 + dx * yx + dy * yy;
+                let left_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+                let right_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+                if start < right_slope {
+                    continue;
+                }
+                if end > left_slope {
+                    break;
+                }
+                
+                if dx * dx + dy * dy <= radius * radius {
+                    visible.insert((map_x, map_y));
+                }
+                
+                let opaque = self.blocks_sight(map_x, map_y);
+                if blocked {
+                    if opaque {
+                        next_start = right_slope;
+                    } else {
+                        blocked = false;
+                        start = next_start;
+                    }
+                } else if opaque && distance < radius {
+                    // Light past this wall continues in a narrower cone on the next row
+                    blocked = true;
+                    self.cast_light(visible, origin, radius, distance + 1, start, left_slope, (xx, xy, yx, yy));
+                    next_start = right_slope;
+                }
+            }
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+/// Difficulty chosen when starting a new game
+#[derive(Clone, Copy, PartialEq)]
+enum Difficulty {
+    Easy,    // Weaker enemies, more loot, slow HP regeneration
+    Normal,  // Baseline balance
+    Hard,    // Tougher enemies, scarcer loot
+}
+
+impl Difficulty {
+    const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+    
+    fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+    
+    /// Percentage applied to damage enemies deal
+    fn enemy_damage_percent(&self) -> i32 {
+        match self {
+            Difficulty::Easy => 50,
+            Difficulty::Normal => 100,
+            Difficulty::Hard => 150,
+        }
+    }
+    
+    /// Percentage applied to enemy health
+    fn enemy_hp_percent(&self) -> i32 {
+        match self {
+            Difficulty::Easy => 75,
+            Difficulty::Normal => 100,
+            Difficulty::Hard => 150,
+        }
+    }
+    
+    /// Chance (out of 100) that a loot spot actually holds an item
+    fn loot_chance(&self) -> i32 {
+        match self {
+            Difficulty::Easy => 100,
+            Difficulty::Normal => 85,
+            Difficulty::Hard => 60,
+        }
+    }
+    
+    /// Number of turns between regenerating 1 HP, if the player regenerates at all
+    fn regen_interval(&self) -> Option<u32> {
+        match self {
+            Difficulty::Easy => Some(5),
+            _ => None,
+        }
+    }
+    
+    /// Roll a loot table, skipping the roll entirely as often as this difficulty dictates
+    fn roll_loot(&self, table: &LootTable) -> Option<Item> {
+        if rand::gen_range(0, 100) < self.loot_chance() {
+            table.roll()
+        } else {
+            None
+        }
+    }
+    
+    /// Like `roll_loot`, but rolling with a map generator's own RNG
+    fn roll_loot_with(&self, table: &LootTable, rng: &rand::RandGenerator) -> Option<Item> {
+        if rng.gen_range(0, 100) < self.loot_chance() {
+            table.roll_with(rng)
+        } else {
+            None
+        }
+    }
+    
+    /// Scale a freshly spawned enemy's health
+    fn scale_enemy(&self, npc: &mut Npc) {
+        npc.max_hp = (npc.max_hp * self.enemy_hp_percent() / 100).max(1);
+        npc.hp = npc.max_hp;
+    }
+}
+
+/// Weather over the outdoor maps (world map and towns)
+#[derive(Clone, Copy, PartialEq)]
+enum Weather {
+    Clear,  // No effects
+    Rain,   // Falling rain; muddy grass slows movement
+    Storm,  // Rain and fog with lightning flashes
+    Fog,    // Gray haze that shortens sight
+}
+
+impl Weather {
+    const ALL: [Weather; 4] = [Weather::Clear, Weather::Rain, Weather::Storm, Weather::Fog];
+    
+    fn name(&self) -> &'static str {
+        match self {
+            Weather::Clear => "Clear",
+            Weather::Rain => "Rain",
+            Weather::Storm => "Storm",
+            Weather::Fog => "Fog",
+        }
+    }
+    
+    /// Whether rain is falling
+    fn is_wet(&self) -> bool {
+        matches!(self, Weather::Rain | Weather::Storm)
+    }
+    
+    /// Whether the air is hazy enough to shorten sight
+    fn is_foggy(&self) -> bool {
+        matches!(self, Weather::Fog | Weather::Storm)
+    }
+    
+    /// Message posted when this weather sets in
+    fn announcement(&self) -> &'static str {
+        match self {
+            Weather::Clear => "The sky clears up.",
+            Weather::Rain => "It starts to rain. Muddy grass slows you down.",
+            Weather::Storm => "A storm rolls in.",
+            Weather::Fog => "A thick fog settles over the land.",
+        }
+    }
+}
+
+/// Seconds between weather changes
+const WEATHER_CHANGE_INTERVAL: f32 = 300.0;
+/// Seconds between lightning flashes during a storm
+const STORM_FLASH_INTERVAL: f32 = 5.0;
+/// Seconds a lightning flash stays on screen
+const STORM_FLASH_DURATION: f32 = 0.15;
+/// Number of rain drops drawn while it rains
+const RAIN_PARTICLES: usize = 150;
+/// Tiles of sight lost in fog
+const FOG_SIGHT_PENALTY: i32 = 2;
+/// Tiles of sight lost on the world map at night
+const NIGHT_SIGHT_PENALTY: i32 = 3;
+
+/// Active conversation state
+struct DialogueState {
+    npc_idx: usize,                    // Index of the NPC being talked to
+    node_idx: usize,                   // Current dialogue node index
+    selected: usize,                   // Currently selected option index
+    nodes: Vec<DialogueNode>,          // Dialogue tree resolved for the player's intelligence
+    transcript: Vec<TranscriptEntry>,  // Everything said so far in this conversation
+    history_scroll: usize,             // Lines scrolled back from the newest transcript line
+    page: usize,                       // Page of the current node's text on screen
+    quest_count: usize,                // Journal size when the conversation began
+}
+
+/// Level-up screen state
+/// Rows are the seven SPECIAL stats followed by the seven skills
+struct LevelUpState {
+    selected: usize,           // Selected row
+    stat_allocated: [i32; 7],  // Points assigned to each stat but not yet confirmed
+    skill_allocated: [i32; 7], // Points assigned to each skill but not yet confirmed
+}
+
+impl LevelUpState {
+    fn new() -> Self {
+        LevelUpState { selected: 0, stat_allocated: [0; 7], skill_allocated: [0; 7] }
+    }
+    
+    /// Total points assigned on this screen
+    fn spent(&self) -> i32 {
+        self.stat_allocated.iter().sum::<i32>() + self.skill_allocated.iter().sum::<i32>()
+    }
+}
+
+/// Character creation screen state
+/// Stats start at the class's values and can be traded against each other freely
+struct CreationState {
+    selected: usize, // Selected stat row
+    stats: [i32; 7], // Stat values in `StatType::ALL` order
+    points: i32,     // Points still to spend
+}
+
+impl CreationState {
+    fn new(stats: &PlayerStats) -> Self {
+        CreationState { selected: 0, stats: StatType::ALL.map(|stat| stats.get(stat)), points: CREATION_POINTS }
+    }
+}
+
+/// Game state enumeration
+/// Defines which mode the game is currently in
+enum GameState {
+    Playing,                 // Normal gameplay state (movement, exploration)
+    Inventory(usize, InventoryTab), // Inventory interface (selected row within the tab, tab)
+    Dialogue(DialogueState), // Dialogue state (NPC, node, selection and transcript)
+    DialogueLog(usize),      // Dialogue log screen (lines scrolled back from newest)
+    Combat(usize),           // Combat state (enemy NPC index)
+    Throwing(usize, usize),  // Picking something to throw in combat (enemy NPC index, selected throwable)
+    LevelUp(LevelUpState),   // Spending skill points on stats
+    PerkSelection(Vec<Perk>, usize), // Choosing a perk (available perks, selected index)
+    CharacterSheet,          // Character sheet screen
+    Journal,                 // Quest journal screen
+    FastTravel(usize),       // Fast travel menu (selected entry in the discovered town list)
+    MapView(MapViewState),   // Full-screen map (zoom and pan)
+    GameOver,                // Player has died
+    NewGame(NewGameState),   // New game screen (difficulty and seed entry)
+    CharacterCreation(CreationState), // Distributing SPECIAL points before the run starts
+    Paused(usize),           // Pause menu (selected option index)
+    Crafting(usize, Option<usize>), // Workbench (inventory cursor, first ingredient picked)
+    Looting((i32, i32), usize), // Looking inside a container (its position, selected item)
+    Reading(ReadingState),   // Reading a signpost or note
+    Bestiary(usize),         // Bestiary screen (selected entry)
+    Editor(EditorState),     // Developer map editor (F4)
+    Statistics,              // Run statistics screen
+    ConfirmAttack(usize),    // Asking whether to really attack a friendly NPC (its index)
+}
+
+impl GameState {
+    /// Variant name, shown by the debug overlay
+    fn name(&self) -> &'static str {
+        match self {
+            GameState::Playing => "Playing",
+            GameState::Inventory(..) => "Inventory",
+            GameState::Dialogue(_) => "Dialogue",
+            GameState::DialogueLog(_) => "DialogueLog",
+            GameState::Combat(_) => "Combat",
+            GameState::Throwing(..) => "Throwing",
+            GameState::LevelUp(_) => "LevelUp",
+            GameState::PerkSelection(..) => "PerkSelection",
+            GameState::CharacterSheet => "CharacterSheet",
+            GameState::Journal => "Journal",
+            GameState::FastTravel(_) => "FastTravel",
+            GameState::MapView(_) => "MapView",
+            GameState::GameOver => "GameOver",
+            GameState::NewGame(_) => "NewGame",
+            GameState::CharacterCreation(_) => "CharacterCreation",
+            GameState::Paused(_) => "Paused",
+            GameState::Crafting(..) => "Crafting",
+            GameState::Looting(..) => "Looting",
+            GameState::Reading(_) => "Reading",
+            GameState::Bestiary(_) => "Bestiary",
+            GameState::Editor(_) => "Editor",
+            GameState::Statistics => "Statistics",
+            GameState::ConfirmAttack(_) => "ConfirmAttack",
+        }
+    }
+}
+
+/// Tallies of the current run, shown on the statistics screen and at game over
+#[derive(Default)]
+struct RunStats {
+    total_time_secs: f32,          // Time spent exploring, not counting menus
+    enemies_killed: u32,           // Enemies defeated
+    items_collected: u32,          // Items picked up or taken from containers
+    gold_earned: u32,              // Caps gained from any source
+    maps_visited: HashSet<String>, // Names of the maps entered
+    steps_taken: u64,              // Successful moves made
+    damage_dealt: u32,             // Damage done to enemies
+    damage_taken: u32,             // Damage suffered from any source
+}
+
+impl RunStats {
+    /// One line per tally, for the statistics screen and the game over panel
+    fn lines(&self) -> Vec<String> {
+        let secs = self.total_time_secs as u32;
+        vec![
+            format!("Time played: {}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60),
+            format!("Enemies killed: {}", self.enemies_killed),
+            format!("Items collected: {}", self.items_collected),
+            format!("Caps earned: {}", self.gold_earned),
+            format!("Maps visited: {}", self.maps_visited.len()),
+            format!("Steps taken: {}", self.steps_taken),
+            format!("Damage dealt: {}", self.damage_dealt),
+            format!("Damage taken: {}", self.damage_taken),
+        ]
+    }
+}
+
+/// A milestone of the run, announced once when first reached
+struct Achievement {
+    id: &'static str,          // Key the game unlocks it by
+    name: &'static str,        // Title shown in the unlock popup
+    description: &'static str, // What it takes to earn it
+}
+
+/// Every achievement there is to unlock
+const ACHIEVEMENTS: [Achievement; 22] = [
+    Achievement { id: "first_blood", name: "First Blood", description: "Win your first fight" },
+    Achievement { id: "slayer", name: "Slayer", description: "Defeat 25 enemies" },
+    Achievement { id: "exterminator", name: "Exterminator", description: "Defeat 100 enemies" },
+    Achievement { id: "naturalist", name: "Naturalist", description: "Fight every kind of creature in the wastes" },
+    Achievement { id: "hoarder", name: "Hoarder", description: "Collect 20 items" },
+    Achievement { id: "bookworm", name: "Bookworm", description: "Read a note found on your travels" },
+    Achievement { id: "globetrotter", name: "Globetrotter", description: "Visit every town" },
+    Achievement { id: "delver", name: "Delver", description: "Descend into a dungeon" },
+    Achievement { id: "down_the_hatch", name: "Down the Hatch", description: "Climb down into a cellar" },
+    Achievement { id: "well_off", name: "Well Off", description: "Carry 500 caps at once" },
+    Achievement { id: "tycoon", name: "Tycoon", description: "Earn 2000 caps in one run" },
+    Achievement { id: "seasoned", name: "Seasoned", description: "Reach level 5" },
+    Achievement { id: "veteran", name: "Veteran", description: "Reach level 10" },
+    Achievement { id: "helping_hand", name: "Helping Hand", description: "Complete a quest" },
+    Achievement { id: "silver_tongue", name: "Silver Tongue", description: "Pass a speech check" },
+    Achievement { id: "light_fingers", name: "Light Fingers", description: "Pick a pocket without being caught" },
+    Achievement { id: "nimble_fingers", name: "Nimble Fingers", description: "Pick a lock" },
+    Achievement { id: "steady_hands", name: "Steady Hands", description: "Disarm a trap" },
+    Achievement { id: "keen_eye", name: "Keen Eye", description: "Spot a hidden door" },
+    Achievement { id: "tinkerer", name: "Tinkerer", description: "Craft an item at a workbench" },
+    Achievement { id: "wanderer", name: "Wanderer", description: "Take 1000 steps" },
+    Achievement { id: "survivor", name: "Survivor", description: "Live to see day 7" },
+];
+
+/// Seconds an achievement unlock popup stays on screen
+const ACHIEVEMENT_POPUP_DURATION: f32 = 3.0;
+
+/// File unlocked achievements are kept in, next to the key bindings
+const ACHIEVEMENTS_PATH: &str = "achievements.txt";
+
+/// Read the ids of achievements unlocked in earlier runs, one per line
+/// Ids that no longer exist are dropped; a missing file means none yet
+fn load_achievements() -> HashSet<&'static str> {
+    let text = std::fs::read_to_string(ACHIEVEMENTS_PATH).unwrap_or_default();
+    text.lines()
+        .filter_map(|line| ACHIEVEMENTS.iter().find(|achievement| achievement.id == line.trim()))
+        .map(|achievement| achievement.id)
+        .collect()
+}
+
+/// Write the unlocked achievement ids to `path`, one per line
+fn save_achievements(path: &str, unlocked: &HashSet<&'static str>) -> std::io::Result<()> {
+    let text: String = ACHIEVEMENTS.iter()
+        .filter(|achievement| unlocked.contains(achievement.id))
+        .map(|achievement| format!("{}\n", achievement.id))
+        .collect();
+    std::fs::write(path, text)
+}
+
+/// Unlock the achievement `id` and announce it, unless it was already unlocked
+/// Newly unlocked achievements are saved when the game has somewhere to save them.
+/// Returns whether it was newly unlocked
+fn check_achievement(game: &mut Game, id: &'static str) -> bool {
+    let achievement = ACHIEVEMENTS.iter().find(|achievement| achievement.id == id);
+    debug_assert!(achievement.is_some(), "unknown achievement id {:?}", id);
+    let Some(achievement) = achievement else {
+        return false;
+    };
+    if !game.unlocked_achievements.insert(achievement.id) {
+        return false;
+    }
+    if let Some(path) = game.achievements_path
+        && let Err(err) = save_achievements(path, &game.unlocked_achievements)
+    {
+        game.add_warning(format!("Could not save {}: {}", path, err));
+    }
+    game.add_message(format!("Achievement unlocked: {} - {}", achievement.name, achievement.description));
+    game.achievement_popup = Some((achievement.name.to_string(), ACHIEVEMENT_POPUP_DURATION));
+    true
+}
+
+/// Damage number floating up from a line of the combat panel and fading out
+struct DamageFloater {
+    pos: (f32, f32), // Where it started, relative to the combat panel's top-left corner
+    value: i32,      // Damage shown
+    color: Color,    // White for the player's hits, yellow for crits, red for damage taken
+    age: f32,        // Seconds since it appeared
+}
+
+/// Seconds a damage number stays on screen
+const DAMAGE_FLOATER_DURATION: f32 = 0.6;
+/// Pixels a damage number rises over its lifetime
+const DAMAGE_FLOATER_RISE: f32 = 24.0;
+
+/// Sign or note being read
+struct ReadingState {
+    title: String,  // Shown at the top of the panel
+    text: String,   // Wrapped to the panel when drawn
+    scroll: usize,  // Wrapped lines scrolled past
+    parchment: bool, // Drawn as a paper note rather than a signboard
+}
+
+impl ReadingState {
+    fn new(title: &str, text: &str, parchment: bool) -> Self {
+        ReadingState { title: title.to_string(), text: text.to_string(), scroll: 0, parchment }
+    }
+}
+
+/// Map editor state
+struct EditorState {
+    cursor: (i32, i32), // Tile being edited
+    template: usize,    // Index into ENEMY_TEMPLATES of the enemy E places
+}
+
+/// Whether the map editor can be opened: debug builds, or a release build started with `--editor`
+fn editor_enabled() -> bool {
+    cfg!(debug_assertions) || std::env::args().any(|arg| arg == "--editor")
+}
+
+/// Full-screen map view state
+struct MapViewState {
+    zoom: f32,   // Scale relative to fitting the whole map in the window
+    pan_x: f32,  // Leftmost map column shown when zoomed past the window
+    pan_y: f32,  // Topmost map row shown when zoomed past the window
+}
+
+/// Largest map view zoom factor
+const MAX_MAP_ZOOM: f32 = 4.0;
+/// Map view panning speed in tiles per second
+const MAP_PAN_SPEED: f32 = 20.0;
+
+impl MapViewState {
+    fn new() -> Self {
+        MapViewState { zoom: 1.0, pan_x: 0.0, pan_y: 0.0 }
+    }
+}
+
+/// Options listed in the pause menu
+const PAUSE_OPTIONS: [&str; 3] = ["Resume", "Auto-pickup", "New Game"];
+
+/// Character class picked on the new game screen
+/// Each adds two points to one SPECIAL stat, hands out a starting kit and unlocks
+/// dialogue options only it can choose
+#[derive(Clone, Copy, PartialEq)]
+enum PlayerClass {
+    Warrior,  // +2 Strength, starts with a machete
+    Scout,    // +2 Agility, starts with Lockpicks
+    Medic,    // +2 Endurance, starts with three Stimpaks
+    Diplomat, // +2 Charisma, starts with 50 extra caps
+}
+
+impl PlayerClass {
+    const ALL: [PlayerClass; 4] = [PlayerClass::Warrior, PlayerClass::Scout, PlayerClass::Medic, PlayerClass::Diplomat];
+    
+    fn name(&self) -> &'static str {
+        match self {
+            PlayerClass::Warrior => "Warrior",
+            PlayerClass::Scout => "Scout",
+            PlayerClass::Medic => "Medic",
+            PlayerClass::Diplomat => "Diplomat",
+        }
+    }
+    
+    /// One-line summary for the new game screen
+    fn description(&self) -> &'static str {
+        match self {
+            PlayerClass::Warrior => "+2 Strength, starts with a Machete",
+            PlayerClass::Scout => "+2 Agility, starts with Lockpicks",
+            PlayerClass::Medic => "+2 Endurance, starts with 3 Stimpaks",
+            PlayerClass::Diplomat => "+2 Charisma, starts with 50 extra caps",
+        }
+    }
+    
+    /// Raise the class's stat on a fresh character
+    fn apply(&self, stats: &mut PlayerStats) {
+        let stat = match self {
+            PlayerClass::Warrior => &mut stats.strength,
+            PlayerClass::Scout => &mut stats.agility,
+            PlayerClass::Medic => &mut stats.endurance,
+            PlayerClass::Diplomat => &mut stats.charisma,
+        };
+        *stat = (*stat + 2).min(MAX_STAT);
+    }
+    
+    /// Items the class starts out carrying
+    fn starting_items(&self) -> Vec<Item> {
+        let item = |name: &str, char, item_type: ItemType, quantity| Item {
+            name: name.to_string(),
+            char,
+            durability: item_type.full_durability(),
+            item_type,
+            quantity,
+        };
+        match self {
+            PlayerClass::Warrior => vec![item("Machete", "/", ItemType::Weapon { damage: 15 }, 1)],
+            PlayerClass::Scout => vec![item("Lockpicks", "-", ItemType::Material, 1)],
+            PlayerClass::Medic => vec![item("Stimpak", "+", ItemType::Consumable { heal: 30, hunger_restore: 0, thirst_restore: 0 }, 3)],
+            PlayerClass::Diplomat => Vec::new(),
+        }
+    }
+    
+    /// Caps the class starts out with
+    fn starting_gold(&self) -> i32 {
+        match self {
+            PlayerClass::Diplomat => 50,
+            _ => 0,
+        }
+    }
+}
+
+/// New game screen state
+struct NewGameState {
+    selected: usize,     // Selected difficulty index
+    class: usize,        // Selected index into `PlayerClass::ALL`
+    seed_input: String,  // Digits typed for a custom seed (empty = random)
+    classic_map: bool,   // Play on the hand-made world map instead of a generated one
+}
+
+impl NewGameState {
+    /// Longest seed that always fits in a u64
+    const MAX_SEED_DIGITS: usize = 19;
+    
+    fn new() -> Self {
+        // Drop keys typed during play so they don't land in the seed field
+        clear_input_queue();
+        NewGameState { selected: 1, class: 0, seed_input: String::new(), classic_map: false }
+    }
+    
+    /// Seed to start the run with: the typed one, or a fresh random one
+    fn seed(&self) -> u64 {
+        self.seed_input.parse().unwrap_or_else(|_| random_seed())
+    }
+}
+
+/// Maximum number of entries kept in the persistent dialogue log
+const MAX_DIALOGUE_LOG: usize = 300;
+
+/// Map location record
+/// Used to save player position when switching between maps
+#[derive(Clone)]
+struct MapLocation {
+    map_type: MapType,   // Map type
+    map_id: usize,       // Map ID (to distinguish different towns/dungeons)
+    x: i32,              // X coordinate when entering
+    y: i32,              // Y coordinate when entering
+}
+
+/// Smooth random values in [0, 1) for every tile of a width x height grid
+/// Random values on a lattice `cell` tiles apart are blended with smoothstep interpolation
+fn value_noise(rng: &rand::RandGenerator, width: i32, height: i32, cell: i32) -> Vec<Vec<f32>> {
+    let lattice_w = (width / cell + 2) as usize;
+    let lattice_h = (height / cell + 2) as usize;
+    let lattice: Vec<Vec<f32>> = (0..lattice_h)
+        .map(|_| (0..lattice_w).map(|_| rng.gen_range(0.0, 1.0)).collect())
+        .collect();
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let (gx, gy) = (x as f32 / cell as f32, y as f32 / cell as f32);
+                    let (ix, iy) = (gx as usize, gy as usize);
+                    let (tx, ty) = (smooth(gx.fract()), smooth(gy.fract()));
+                    let top = lattice[iy][ix] + (lattice[iy][ix + 1] - lattice[iy][ix]) * tx;
+                    let bottom = lattice[iy + 1][ix] + (lattice[iy + 1][ix + 1] - lattice[iy + 1][ix]) * tx;
+                    top + (bottom - top) * ty
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Try to steal from a non-hostile NPC
+/// Succeeds on `agility * 8 + sneak` percent, taking the NPC's first item or some of their caps.
+/// A caught thief turns the NPC hostile and loses standing with its faction.
+/// Returns whether the attempt went unnoticed
+fn attempt_pickpocket(game: &mut Game, npc_idx: usize) -> bool {
+    let chance = game.player.stats.agility * 8 + game.player.skills.sneak;
+    let npc = &mut game.npcs[npc_idx];
+    if rand::gen_range(0, 100) >= chance {
+        npc.hostile = true;
+        npc.alert_state = AlertState::Alerted;
+        let msg = format!("{} catches you red-handed!", npc.name);
+        let faction = game.current_faction();
+        game.adjust_reputation(faction, -PICKPOCKET_REPUTATION_LOSS);
+        game.add_message(msg);
+        return false;
+    }
+    
+    let msg = if !npc.inventory.is_empty() {
+        let item = npc.inventory.remove(0);
+        let msg = format!("You lift a {} from {}.", item.name, npc.name);
+        game.player.add_item(item);
+        msg
+    } else if npc.gold_carried > 0 {
+        let caps = rand::gen_range(1, npc.gold_carried + 1);
+        npc.gold_carried -= caps;
+        let msg = format!("You lift {} caps from {}.", caps, npc.name);
+        game.earn_gold(caps);
+        msg
+    } else {
+        format!("{} has nothing worth taking.", npc.name)
+    };
+    game.player.skills.practice(SkillType::Sneak);
+    game.karma -= THEFT_KARMA;
+    game.add_message(msg);
+    check_achievement(game, "light_fingers");
+    true
+}
+
+/// Knock a weak wall down to floor
+fn smash_wall(map: &mut GameMap, x: i32, y: i32) {
+    map.tiles[y as usize][x as usize] = TileType::Floor;
+}
+
+/// Compass direction of the offset (dx, dy), e.g. "north-east"; y grows southward
+fn compass_direction(dx: i32, dy: i32) -> &'static str {
+    // Only count an axis if it is at least half the other, so (5, 1) reads as plain east
+    let vertical = if dy.abs() * 2 < dx.abs() { 0 } else { dy.signum() };
+    let horizontal = if dx.abs() * 2 < dy.abs() { 0 } else { dx.signum() };
+    match (vertical, horizontal) {
+        (-1, -1) => "north-west",
+        (-1, 0) => "north",
+        (-1, 1) => "north-east",
+        (0, -1) => "west",
+        (0, 1) => "east",
+        (1, -1) => "south-west",
+        (1, 0) => "south",
+        (1, 1) => "south-east",
+        _ => "around",
+    }
+}
+
+/// Whether walling off (x, y) would cut any tile reachable from `start` off from it
+fn is_chokepoint(tiles: &[Vec<TileType>], start: (i32, i32), (x, y): (i32, i32)) -> bool {
+    let passable = |tile: TileType| tile.is_walkable() || tile == TileType::DoorClosed;
+    let count = |reached: Vec<Vec<bool>>| reached.into_iter().flatten().filter(|&r| r).count();
+    let before = flood_fill(tiles, start, passable);
+    let was_reached = before[y as usize][x as usize];
+    let mut blocked = tiles.to_vec();
+    blocked[y as usize][x as usize] = TileType::Wall;
+    count(flood_fill(&blocked, start, passable)) + usize::from(was_reached) < count(before)
+}
+
+/// Tiles reachable from `start` by orthogonal steps over tiles where `passable` holds
+fn flood_fill(tiles: &[Vec<TileType>], start: (i32, i32), passable: impl Fn(TileType) -> bool) -> Vec<Vec<bool>> {
+    let mut reached: Vec<Vec<bool>> = tiles.iter().map(|row| vec![false; row.len()]).collect();
+    let mut stack = vec![start];
+    reached[start.1 as usize][start.0 as usize] = true;
+    while let Some((x, y)) = stack.pop() {
+        for (nx, ny) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+            if ny < 0 || ny as usize >= tiles.len() || nx < 0 || nx as usize >= tiles[ny as usize].len() {
+                continue;
+            }
+            let (ux, uy) = (nx as usize, ny as usize);
+            if !reached[uy][ux] && passable(tiles[uy][ux]) {
+                reached[uy][ux] = true;
+                stack.push((nx, ny));
+            }
+        }
+    }
+    reached
+}
+
+/// Number of towns in a world
+const WORLD_TOWNS: usize = 2;
+/// Number of dungeons in a world
+const WORLD_DUNGEONS: usize = 2;
+
+/// How each dungeon came to be, found on a journal page deep inside it
+const DUNGEON_HISTORIES: [&str; WORLD_DUNGEONS] = [
+    "Day 12. The vault doors never opened for us, so we dug our own shelter around them. The foreman swears the old tunnels go deeper than the maps say. Water's bad. Nobody sleeps well down here.",
+    "These caves held the town's stores before the raiders came. We sealed the good stuff behind a gate and hid the lever where only we would find it. If you're reading this, we didn't make it back.",
+];
+
+/// Town layout built into the game, used when `assets/maps/town.txt` can't be read
+const TOWN_MAP: &str = include_str!("../assets/maps/town.txt");
+
+/// Cellar layout built into the game, used when `assets/maps/cellar.txt` can't be read
+const CELLAR_MAP: &str = include_str!("../assets/maps/cellar.txt");
+
+/// Item glyphs read from map files, shared by every item drawn with them
+static GLYPHS: std::sync::Mutex<std::collections::BTreeSet<&'static str>> =
+    std::sync::Mutex::new(std::collections::BTreeSet::new());
+
+/// The shared copy of an item glyph read from a map file
+/// Each distinct glyph is allocated once, however often maps are loaded or saved
+fn intern_glyph(glyph: &str) -> &'static str {
+    let mut glyphs = GLYPHS.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(&known) = glyphs.get(glyph) {
+        return known;
+    }
+    let glyph: &'static str = Box::leak(glyph.into());
+    glyphs.insert(glyph);
+    glyph
+}
+
+/// Split a line of a text map into its words, each with the column it starts at
+fn text_tokens(line: &str) -> Vec<(usize, &str)> {
+    line.split_whitespace()
+        .map(|token| {
+            let offset = token.as_ptr() as usize - line.as_ptr() as usize;
+            (line[..offset].chars().count() + 1, token)
+        })
+        .collect()
+}
+
+/// File a map is saved to by the editor and read back from: its name in lowercase
+/// with runs of other characters turned into `_` (`Dungeon #1` → `assets/maps/dungeon_1.txt`)
+fn map_file_path(name: &str) -> String {
+    let slug: String = name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { ' ' })
+        .collect();
+    format!("assets/maps/{}.txt", slug.split_whitespace().collect::<Vec<_>>().join("_"))
+}
+
+/// Map files read at startup, shared by every run
+struct MapLayouts {
+    town: GameMap,                               // Layout every town is built on
+    cellar: GameMap,                             // Layout every building cellar is built on
+    saved: HashMap<(MapType, usize), GameMap>,  // Towns and dungeons saved from the editor
+}
+
+impl MapLayouts {
+    /// Read the town and cellar layouts and any towns or dungeons saved from the editor
+    async fn load() -> Self {
+        let town = load_map_file("assets/maps/town.txt", "Town", MapType::Town, TOWN_MAP).await;
+        let cellar = load_map_file("assets/maps/cellar.txt", "Cellar", MapType::Interior, CELLAR_MAP).await;
+        let mut saved = HashMap::new();
+        let names = (0..WORLD_TOWNS).map(|id| (MapType::Town, id, format!("Town #{}", id + 1)))
+            .chain((0..WORLD_DUNGEONS).map(|id| (MapType::Dungeon, id, format!("Dungeon #{}", id + 1))));
+        for (map_type, map_id, name) in names {
+            if let Some(map) = load_saved_map(&name, map_type).await {
+                saved.insert((map_type, map_id), map);
+            }
+        }
+        MapLayouts { town, cellar, saved }
+    }
+}
+
+/// Read a map saved from the editor, if there is one
+/// A broken file is reported and left out
+async fn load_saved_map(name: &str, map_type: MapType) -> Option<GameMap> {
+    let path = map_file_path(name);
+    let text = load_string(&path).await.ok()?;
+    GameMap::from_text(name, map_type, &text)
+        .map_err(|err| error!("{}: {}; ignoring the saved map", path, err))
+        .ok()
+}
+
+/// Read and parse a map file shipped next to the game
+/// A missing or broken file is reported and the built-in copy is used instead
+async fn load_map_file(path: &str, name: &str, map_type: MapType, builtin: &str) -> GameMap {
+    let text = match load_string(path).await {
+        Ok(text) => text,
+        Err(err) => {
+            error!("Could not read {}: {}; using the built-in map", path, err);
+            builtin.to_string()
+        }
+    };
+    GameMap::from_text(name, map_type, &text).unwrap_or_else(|err| {
+        error!("{}: {}; using the built-in map", path, err);
+        GameMap::from_text(name, map_type, builtin).expect("built-in map should parse")
+    })
+}
+
+/// Seed for generating one map, derived from the run seed, the map's type and its id
+fn map_seed(seed: u64, map_type: MapType, map_id: usize) -> u64 {
+    let type_offset = match map_type {
+        MapType::WorldMap => 0,
+        MapType::Town => 1000,
+        MapType::Dungeon => 2000,
+        MapType::Interior => 3000,
+    };
+    seed.wrapping_add(type_offset + map_id as u64)
+}
+
+/// Seed for a run when the player doesn't enter one: the current time in milliseconds
+fn random_seed() -> u64 {
+    (macroquad::miniquad::date::now() * 1000.0) as u64
+}
+
+/// File the key bindings are saved to, next to the executable's working directory
+const CONFIG_PATH: &str = "keybindings.cfg";
+
+/// Keys that can be named in the key binding file
+const BINDABLE_KEYS: [KeyCode; 45] = [
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+    KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right,
+    KeyCode::Space, KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace, KeyCode::Escape,
+];
+
+/// Player-configurable key bindings and movement settings
+/// The arrow keys always work for movement and menus alongside the movement bindings
+#[derive(Clone)]
+struct Config {
+    move_up: KeyCode,        // Move up / select previous entry
+    move_down: KeyCode,      // Move down / select next entry
+    move_left: KeyCode,      // Move left / decrease
+    move_right: KeyCode,     // Move right / increase
+    open_inventory: KeyCode, // Open and close the inventory
+    interact: KeyCode,       // Enter towns, dungeons and buildings
+    open_map: KeyCode,       // Open the fast travel map
+    open_quest_log: KeyCode, // Open and close the quest journal
+    hold_to_move: bool,      // Keep walking while a direction key is held
+    move_repeat_delay: f32,  // Seconds a direction is held before it starts repeating
+    move_repeat_interval: f32, // Seconds between repeated steps while held
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            move_up: KeyCode::W,
+            move_down: KeyCode::S,
+            move_left: KeyCode::A,
+            move_right: KeyCode::D,
+            open_inventory: KeyCode::I,
+            interact: KeyCode::Space,
+            open_map: KeyCode::T,
+            open_quest_log: KeyCode::J,
+            hold_to_move: true,
+            move_repeat_delay: 0.2,
+            move_repeat_interval: 0.08,
+        }
+    }
+}
+
+impl Config {
+    fn up_pressed(&self) -> bool {
+        is_key_pressed(self.move_up) || is_key_pressed(KeyCode::Up)
+    }
+    
+    fn down_pressed(&self) -> bool {
+        is_key_pressed(self.move_down) || is_key_pressed(KeyCode::Down)
+    }
+    
+    fn left_pressed(&self) -> bool {
+        is_key_pressed(self.move_left) || is_key_pressed(KeyCode::Left)
+    }
+    
+    fn right_pressed(&self) -> bool {
+        is_key_pressed(self.move_right) || is_key_pressed(KeyCode::Right)
+    }
+    
+    /// Direction of the movement key currently held down, if any
+    fn held_direction(&self) -> Option<(i32, i32)> {
+        [
+            (self.move_up, KeyCode::Up, (0, -1)),
+            (self.move_down, KeyCode::Down, (0, 1)),
+            (self.move_left, KeyCode::Left, (-1, 0)),
+            (self.move_right, KeyCode::Right, (1, 0)),
+        ]
+        .into_iter()
+        .find(|&(key, arrow, _)| is_key_down(key) || is_key_down(arrow))
+        .map(|(_, _, dir)| dir)
+    }
+    
+    /// Binding names as written in the config file, paired with their fields
+    fn bindings_mut(&mut self) -> [(&'static str, &mut KeyCode); 8] {
+        [
+            ("move_up", &mut self.move_up),
+            ("move_down", &mut self.move_down),
+            ("move_left", &mut self.move_left),
+            ("move_right", &mut self.move_right),
+            ("open_inventory", &mut self.open_inventory),
+            ("interact", &mut self.interact),
+            ("open_map", &mut self.open_map),
+            ("open_quest_log", &mut self.open_quest_log),
+        ]
+    }
+    
+    /// Write the bindings as `name = Key` lines, followed by the movement settings
+    fn save_to_file(&self) -> std::io::Result<()> {
+        let mut copy = self.clone();
+        let mut text: String = copy.bindings_mut()
+            .into_iter()
+            .map(|(name, key)| format!("{} = {:?}\n", name, key))
+            .collect();
+        text += &format!("hold_to_move = {}\n", self.hold_to_move);
+        text += &format!("move_repeat_delay = {}\n", self.move_repeat_delay);
+        text += &format!("move_repeat_interval = {}\n", self.move_repeat_interval);
+        std::fs::write(CONFIG_PATH, text)
+    }
+    
+    /// Read bindings saved by `save_to_file`
+    /// Unknown lines and key names are ignored; a missing file is created with the defaults
+    fn load_from_file() -> Self {
+        let mut config = Config::default();
+        let Ok(text) = std::fs::read_to_string(CONFIG_PATH) else {
+            let _ = config.save_to_file();
+            return config;
+        };
+        for line in text.lines() {
+            let Some((name, key_name)) = line.split_once('=') else {
+                continue;
+            };
+            let value = key_name.trim();
+            match name.trim() {
+                "hold_to_move" => config.hold_to_move = value.parse().unwrap_or(config.hold_to_move),
+                "move_repeat_delay" => config.move_repeat_delay = value.parse().unwrap_or(config.move_repeat_delay),
+                "move_repeat_interval" => config.move_repeat_interval = value.parse().unwrap_or(config.move_repeat_interval),
+                _ => {}
+            }
+            let key = BINDABLE_KEYS.iter().find(|key| format!("{:?}", key) == key_name.trim());
+            if let Some(&key) = key
+                && let Some((_, slot)) = config.bindings_mut().into_iter().find(|(n, _)| *n == name.trim())
+            {
+                *slot = key;
+            }
+        }
+        config
+    }
+}
+
+/// Real-time seconds in one in-game day
+const DAY_LENGTH: f32 = 600.0;
+/// Time of day a new run starts at (09:00)
+const DAY_START: f32 = 0.375;
+
+/// Main game structure
+/// Contains all game data and state
+struct Game {
+    player: Player,              // Player data
+    current_map: GameMap,        // Current map (moved out of its storage slot while active)
+    world_map: GameMap,          // World map storage (placeholder while the world map is current)
+    town_maps: Vec<GameMap>,     // Town map storage (placeholder at the current town's index)
+    dungeon_maps: Vec<GameMap>,  // Dungeon map storage (placeholder at the current dungeon's index)
+    interior_maps: Vec<GameMap>, // Building cellar storage, two per town
+    npcs: Vec<Npc>,              // NPC list for current map
+    current_map_id: usize,       // ID of the current map (town/dungeon/interior index, 0 for world map)
+    map_npcs: HashMap<(MapType, usize), Vec<Npc>>,  // NPCs of maps the player has left
+    state: GameState,            // Current game state
+    messages: Vec<(String, Color)>, // Message log with each message's color (max 5 messages)
+    combat_log: Vec<String>,     // Blow-by-blow of the current fight (max COMBAT_LOG_LINES)
+    damage_floaters: Vec<DamageFloater>, // Damage numbers drifting up over the combat panel
+    unlocked_achievements: HashSet<&'static str>, // Ids of the achievements earned, in this run or earlier ones
+    achievements_path: Option<&'static str>, // File unlocks are saved to (None = kept in memory only)
+    achievement_popup: Option<(String, f32)>, // Name of the latest unlock and seconds it stays up
+    dialogue_log: Vec<TranscriptEntry>,  // Persistent log of past conversations
+    quests: Vec<Quest>,          // Started quests (active and completed)
+    bestiary: HashMap<String, BestiaryEntry>, // Enemy kinds fought, keyed by name
+    camera_x: i32,               // Camera X coordinate (for map scrolling)
+    camera_y: i32,               // Camera Y coordinate (for map scrolling)
+    camera_fx: f32,              // Camera X easing toward its target, rounded into camera_x
+    camera_fy: f32,              // Camera Y easing toward its target, rounded into camera_y
+    location_stack: Vec<MapLocation>,  // Maps the player came from, innermost last
+    discovered_towns: Vec<usize>,      // Towns entered at least once, in discovery order
+    town_world_positions: Vec<(i32, i32)>, // World map tile of each town's entrance
+    fov: HashSet<(i32, i32)>,          // Tiles currently in the player's field of view
+    difficulty: Difficulty,            // Difficulty chosen for this run
+    stats: RunStats,                   // Tallies of this run
+    animation_timer: f32,              // Seconds of animation time, wrapped at 1000
+    time_of_day: f32,                  // 0.0 = midnight, 0.5 = noon, wraps at 1.0
+    day: u32,                          // Days since the run began, starting at 1
+    auto_pickup: bool,                 // Pick up items by stepping on them (otherwise press G)
+    debug_overlay: bool,               // Draw tile coordinates, grid lines and debug info (F3)
+    current_weather: Weather,          // Weather over the outdoor maps
+    weather_timer: f32,                // Seconds since the weather last changed
+    rain_particles: Vec<(f32, f32)>,   // Screen positions of falling rain drops
+    storm_flash: f32,                  // Seconds since the last lightning flash
+    bogged_down: bool,                 // Stuck in mud; the next step off rain-soaked grass is lost
+    steps_since_encounter: u32,        // World map steps taken since the last ambush
+    turns: u64,                        // Turns elapsed this run, advanced by the move cost of every step taken
+    shift_chorded: bool,               // Another key went down while Shift was held, so releasing it doesn't toggle sneaking
+    held_move: Option<(i32, i32)>,     // Direction key held down while exploring
+    pending_interaction: Option<usize>, // Bumped NPC waiting for the player to pick pickpocket or talk
+    reputation: HashMap<Faction, i32>, // Standing with each faction (missing = 0)
+    karma: i32,                        // How good or wicked the player's deeds have been overall
+    turned_towns: HashSet<usize>,      // Towns whose people won't deal with the player after a killing there
+    guarded_towns: HashSet<usize>,     // Towns that have already posted guards against the player
+    move_repeat_timer: f32,            // Seconds until the held direction steps again
+    move_accumulator: f32,             // Seconds until the player can take another step
+    queued_move: Option<(i32, i32)>,   // Step asked for while the last one was still finishing
+    auto_travel: Option<Vec<(i32, i32)>>, // Steps left of a clicked world map journey, next one last
+    hunger_timer: u64,                 // Turns toward the next point of hunger
+    thirst_timer: u64,                 // Turns toward the next point of thirst
+    starvation_timer: u64,             // Turns toward the next HP lost to hunger or thirst
+    regen_popup: f32,                  // Seconds since passive regeneration last healed (drives the +1 popup)
+    config: Config,                    // Key bindings
+    seed: u64,                         // Run seed that all map generation derives from
+}
+
+impl Game {
+    /// Create new game instance
+    /// Initialize player, maps, NPCs and all game elements for the chosen difficulty and seed;
+    /// `classic_map` swaps the generated world for the original hand-made one;
+    /// towns are built on the shared layout and maps saved from the editor replace their originals
+    fn new(difficulty: Difficulty, class: PlayerClass, seed: u64, classic_map: bool, layouts: &MapLayouts) -> Self {
+        // Initial stat points all set to 5, then raised by the class
+        let mut stats = PlayerStats {
+            strength: 5,
+            perception: 5,
+            endurance: 5,
+            charisma: 5,
+            intelligence: 5,
+            agility: 5,
+            luck: 5,
+        };
+        class.apply(&mut stats);
+        
+        // Create player character; they start at the world map's entry point
+        let mut player = Player {
+            name: "Vault Dweller".to_string(),
+            class,
+            x: 0,
+            y: 0,
+            hp: 100,
+            max_hp: 100,
+            inventory: vec![],  // Initial inventory is empty
+            skills: Skills::from_stats(&stats),
+            stats,
+            level: 1,
+            xp: 0,
+            skill_points: 0,
+            pending_perks: 0,
+            perks: Vec::new(),
+            gold: 0,
+            torch_turns: 0,
+            hunger: MAX_NEED,
+            thirst: MAX_NEED,
+            regen_accumulator: 0,
+            is_sneaking: false,
+            sneak_move_delay: 0.0,
+            detection_level: 0.0,
+            facing: (0, 1),
+            equipped_weapon: None,
+            equipped_armor: None,
+            hotbar: [None; HOTBAR_SLOTS],
+        };
+        player.update_derived_stats();
+        player.gold = class.starting_gold();
+        for item in class.starting_items() {
+            let is_weapon = matches!(item.item_type, ItemType::Weapon { .. });
+            player.add_item(item);
+            if is_weapon {
+                player.equipped_weapon = Some(player.inventory.len() - 1);
+            }
+        }
+        
+        // Every map is generated from its own seed derived from the run seed,
+        // so the same seed always produces the same world
+        
+        // Create world map; it starts out as the current map
+        let world_seed = map_seed(seed, MapType::WorldMap, 0);
+        rand::srand(world_seed);
+        let current_map = if classic_map {
+            GameMap::new_world_map()
+        } else {
+            GameMap::generate_world(world_seed, 80, 40)
+        };
+        (player.x, player.y) = current_map.entry_point;
+        
+        // Pre-generate town maps
+        let town_maps: Vec<GameMap> = (0..WORLD_TOWNS)
+            .map(|town_id| {
+                rand::srand(map_seed(seed, MapType::Town, town_id));
+                let layout = layouts.saved.get(&(MapType::Town, town_id)).unwrap_or(&layouts.town);
+                GameMap::new_town_map(town_id, layout, &current_map, difficulty)
+            })
+            .collect();
+        
+        // Pre-generate building cellars, two for each town
+        let interior_maps = (0..town_maps.len() * 2)
+            .map(|interior_id| {
+                rand::srand(map_seed(seed, MapType::Interior, interior_id));
+                GameMap::new_interior_map(interior_id, &layouts.cellar, difficulty)
+            })
+            .collect();
+        
+        // Pre-generate dungeon maps: even ids are rooms and corridors, odd ids are caves
+        let dungeon_maps = (0..WORLD_DUNGEONS)
+            .map(|dungeon_id| {
+                // A dungeon saved from the editor is used just as it was drawn
+                if let Some(saved) = layouts.saved.get(&(MapType::Dungeon, dungeon_id)) {
+                    return saved.clone();
+                }
+                let dungeon_seed = map_seed(seed, MapType::Dungeon, dungeon_id);
+                let mut dungeon = if dungeon_id % 2 == 0 {
+                    GameMap::generate_dungeon(dungeon_seed, dungeon_id as u32 + 1, difficulty)
+                } else {
+                    GameMap::generate_cave_map(dungeon_seed, 40, 30)
+                };
+                dungeon.name = format!("Dungeon #{}", dungeon_id + 1);
+                // Every dungeon out in the world hides one secret room
+                if dungeon.secret_doors().is_empty() {
+                    let rng = rand::RandGenerator::new();
+                    rng.srand(dungeon_seed.wrapping_add(1));
+                    dungeon.add_secret_room(&rng, dungeon_id as u32 + 1, difficulty);
+                }
+                // Dungeon #2 keeps its best chest behind a gate, with a plate to peek through it
+                if dungeon_id == 1 {
+                    let rng = rand::RandGenerator::new();
+                    rng.srand(dungeon_seed.wrapping_add(2));
+                    dungeon.add_lever_room(&rng, dungeon_id as u32 + 1, true, difficulty);
+                }
+                // A note by the way in gives away roughly where the secret room is
+                if let Some(door) = dungeon.secret_doors().first().copied()
+                    && let Some(pos) = dungeon.free_tile_near(dungeon.entry_point)
+                {
+                    let (ex, ey) = dungeon.entry_point;
+                    let text = format!(
+                        "Whoever dug this place loved their hiding spots. Knock on the walls about {} paces {} of the way in; one of them rings hollow. Search (F) when you're close.",
+                        (door.0 - ex).abs().max((door.1 - ey).abs()),
+                        compass_direction(door.0 - ex, door.1 - ey),
+                    );
+                    dungeon.items.insert(pos, Item {
+                        name: "Crumpled Note".to_string(),
+                        char: "?",
+                        item_type: ItemType::Note { text },
+                        durability: None,
+                        quantity: 1,
+                    });
+                }
+                if dungeon_id == 0 && let Some(pos) = dungeon.farthest_free_tile() {
+                    dungeon.items.insert(pos, Item {
+                        name: STOREHOUSE_KEY.to_string(),
+                        char: "k",
+                        item_type: ItemType::Quest,
+                        durability: None,
+                        quantity: 1,
+                    });
+                }
+                // Deeper in, someone left an account of how the place came to be
+                if let Some(pos) = dungeon.farthest_free_tile() {
+                    dungeon.items.insert(pos, Item {
+                        name: "Faded Journal Page".to_string(),
+                        char: "?",
+                        item_type: ItemType::Note { text: DUNGEON_HISTORIES[dungeon_id % DUNGEON_HISTORIES.len()].to_string() },
+                        durability: None,
+                        quantity: 1,
+                    });
+                }
+                dungeon
+            })
+            .collect();
+        
+        // Gameplay rolls (combat, encounters, perks) continue from the run seed
+        rand::srand(seed);
+        
+        // Fast travel lands on each town's entrance tile
+        let mut town_world_positions = vec![(0, 0); town_maps.len()];
+        for (&pos, &(map_type, map_id)) in &current_map.entrances {
+            if map_type == MapType::Town {
+                town_world_positions[map_id] = pos;
+            }
+        }
+        
+        let mut game = Game {
+            player,
+            current_map,
+            world_map: GameMap::default(),
+            town_maps,
+            dungeon_maps,
+            interior_maps,
+            npcs: Vec::new(),
+            current_map_id: 0,
+            map_npcs: HashMap::new(),
+            state: GameState::Playing,
+            combat_log: Vec::new(),
+            damage_floaters: Vec::new(),
+            unlocked_achievements: HashSet::new(),
+            achievements_path: None,
+            achievement_popup: None,
+            messages: vec![("Welcome to the Wasteland! Press SPACE to enter towns/dungeons, ESC to return.".to_string(), LIGHTGRAY)],
+            dialogue_log: Vec::new(),
+            quests: Vec::new(),
+            bestiary: HashMap::new(),
+            camera_x: 0,
+            camera_y: 0,
+            camera_fx: 0.0,
+            camera_fy: 0.0,
+            location_stack: Vec::new(),
+            discovered_towns: Vec::new(),
+            town_world_positions,
+            fov: HashSet::new(),
+            difficulty,
+            stats: RunStats::default(),
+            animation_timer: 0.0,
+            time_of_day: DAY_START,
+            day: 1,
+            auto_pickup: true,
+            debug_overlay: false,
+            current_weather: Weather::Clear,
+            weather_timer: 0.0,
+            rain_particles: Vec::new(),
+            storm_flash: 0.0,
+            bogged_down: false,
+            steps_since_encounter: 0,
+            turns: 0,
+            shift_chorded: false,
+            held_move: None,
+            pending_interaction: None,
+            reputation: HashMap::new(),
+            karma: 0,
+            turned_towns: HashSet::new(),
+            guarded_towns: HashSet::new(),
+            move_repeat_timer: 0.0,
+            move_accumulator: 0.0,
+            queued_move: None,
+            auto_travel: None,
+            hunger_timer: 0,
+            thirst_timer: 0,
+            starvation_timer: 0,
+            regen_popup: REGEN_POPUP_DURATION,
+            config: Config::default(),
+            seed,
+        };
+        
+        // Populate NPCs on the starting world map
+        game.stats.maps_visited.insert(game.current_map.name.clone());
+        game.load_world_npcs();
+        game.update_fov();
+        game
+    }
+    
+    /// Add message to message log
+    /// Automatically removes oldest message if exceeds 5 messages
+    fn add_message(&mut self, msg: String) {
+        self.push_message(msg, LIGHTGRAY);
+    }
+    
+    /// Add a message shown in red, for harm the player didn't see coming
+    fn add_warning(&mut self, msg: String) {
+        self.push_message(msg, RED);
+    }
+    
+    /// Add a line to the current fight's log, shown under the combat panel instead of the message log
+    fn add_combat_message(&mut self, msg: String) {
+        self.combat_log.push(msg);
+        if self.combat_log.len() > COMBAT_LOG_LINES {
+            self.combat_log.remove(0);
+        }
+    }
+    
+    fn push_message(&mut self, msg: String, color: Color) {
+        self.messages.push((msg, color));
+        if self.messages.len() > 5 {
+            self.messages.remove(0);  // Remove first (oldest) message
+        }
+    }
+    
+    /// Move player
+    /// 
+    /// # Arguments
+    /// * `dx` - X axis movement delta (-1 left, 1 right)
+    /// * `dy` - Y axis movement delta (-1 up, 1 down)
+    fn move_player(&mut self, dx: i32, dy: i32) {
+        // Still finishing the last step, which takes longer on rough ground;
+        // the new one is taken as soon as it is done
+        if self.move_accumulator > 0.0 {
+            self.queued_move = Some((dx, dy));
+            return;
+        }
+        self.pending_interaction = None;
+        // Sneaking steps are slow and deliberate
+        if self.player.is_sneaking {
+            if self.player.sneak_move_delay > 0.0 {
+                return;
+            }
+            self.player.sneak_move_delay = SNEAK_MOVE_DELAY;
+        }
+        let from = (self.player.x, self.player.y);
+        let turns_before = self.turns;
+        self.step_player(dx, dy);
+        if (self.player.x, self.player.y) != from {
+            self.trigger_plates(from);
+            let tile = self.current_map.tiles[self.player.y as usize][self.player.x as usize];
+            self.move_accumulator = tile.movement_cost() * BASE_MOVE_INTERVAL;
+        }
+        
+        // Only steps that land advance the turn clock, by the cost of the tile entered
+        let elapsed = if (self.player.x, self.player.y) == from {
+            0
+        } else {
+            self.current_map.tiles[self.player.y as usize][self.player.x as usize].move_cost()
+        };
+        self.pass_turns(elapsed);
+        self.update_fov();
+        // Enemies only get a turn when the bump spent one: a step taken or a door opened
+        if self.turns > turns_before && matches!(self.state, GameState::Playing) {
+            self.move_enemies();
+        }
+    }
+    
+    /// Use or equip whatever is in hotbar slot `slot`
+    fn use_hotbar(&mut self, slot: usize) {
+        let msg = match self.player.hotbar[slot] {
+            Some(idx) => self.player.use_item(idx),
+            None => format!("Hotbar slot {} is empty", slot + 1),
+        };
+        self.add_message(msg);
+    }
+    
+    /// Advance the turn clock; hunger, thirst and healing move on every turn,
+    /// and a torch burns down once per turn underground
+    fn pass_turns(&mut self, turns: u64) {
+        for _ in 0..turns {
+            self.turns += 1;
+            self.update_needs();
+            self.update_regen();
+            if self.current_map.map_type == MapType::Dungeon {
+                self.burn_torch();
+            }
+        }
+    }
+    
+    /// Faction of the NPCs on the current map
+    fn current_faction(&self) -> Faction {
+        match self.current_map.map_type {
+            MapType::Town | MapType::Interior => Faction::Townsfolk,
+            _ => Faction::Wanderers,
+        }
+    }
+    
+    /// Change standing with a faction by `amount`
+    fn adjust_reputation(&mut self, faction: Faction, amount: i32) {
+        *self.reputation.entry(faction).or_insert(0) += amount;
+    }
+    
+    /// Standing with a faction
+    fn reputation_with(&self, faction: Faction) -> i32 {
+        self.reputation.get(&faction).copied().unwrap_or(0)
+    }
+    
+    /// A price after the current map's faction adds its ±10% for how it feels about the player
+    fn reputation_price(&self, price: i32) -> i32 {
+        let standing = self.reputation_with(self.current_faction());
+        let percent = if standing >= REPUTATION_FRIENDLY {
+            90
+        } else if standing <= REPUTATION_HOSTILE {
+            110
+        } else {
+            100
+        };
+        (price * percent / 100).max(1)
+    }
+    
+    /// Town the current map belongs to, counting its cellars
+    fn current_town(&self) -> Option<usize> {
+        match self.current_map.map_type {
+            MapType::Town => Some(self.current_map_id),
+            MapType::Interior => Some(self.current_map_id / 2),
+            _ => None,
+        }
+    }
+    
+    /// Whether someone was killed in the current town, turning its people against the player
+    fn in_turned_town(&self) -> bool {
+        self.current_town().is_some_and(|town| self.turned_towns.contains(&town))
+    }
+    
+    /// Post guards at the entrance of a town that has turned against the player
+    /// Each town only does so once; guards the player kills stay dead
+    fn post_town_guards(&mut self) {
+        let hated = self.reputation_with(Faction::Townsfolk) <= REPUTATION_HOSTILE || self.in_turned_town();
+        if self.current_map.map_type != MapType::Town
+            || !hated
+            || !self.guarded_towns.insert(self.current_map_id)
+        {
+            return;
+        }
+        let (x, y) = self.current_map.entry_point;
+        for i in 0..TOWN_GUARDS as i32 {
+            let (gx, gy) = self.current_map.nearest_walkable(x + 2 * i - 1, y + 2);
+            let mut guard = Npc::spawn_enemy(&TOWN_GUARD, gx, gy);
+            guard.faction = Some(Faction::Townsfolk);
+            guard.leash = self.current_map.width.max(self.current_map.height);
+            self.difficulty.scale_enemy(&mut guard);
+            self.npcs.push(guard);
+        }
+        self.add_message("Town guards bar your way. Word of your deeds got here first.".to_string());
+    }
+    
+    /// Ask before attacking the NPC waiting on a choice, else whoever stands where the player faces
+    /// Hostiles are fought straight away; friendly folk need a Y to confirm
+    fn request_attack(&mut self) {
+        let (x, y) = (self.player.x + self.player.facing.0, self.player.y + self.player.facing.1);
+        let Some(npc_idx) = self.take_pending_interaction().or_else(|| self.tile_occupant(x, y)) else {
+            self.add_message("There's no one there to attack.".to_string());
+            return;
+        };
+        if self.npcs[npc_idx].hostile {
+            self.start_combat(npc_idx);
+        } else {
+            self.state = GameState::ConfirmAttack(npc_idx);
+        }
+    }
+    
+    /// Handle the attack prompt: only Y attacks, N or ESC backs off, and other keys are ignored
+    fn handle_confirm_attack_input(&mut self) {
+        let GameState::ConfirmAttack(npc_idx) = self.state else {
+            return;
+        };
+        if is_key_pressed(KeyCode::Y) {
+            let npc = &mut self.npcs[npc_idx];
+            npc.hostile = true;
+            npc.alert_state = AlertState::Alerted;
+            let msg = format!("You attack {}!", npc.name);
+            self.add_message(msg);
+            self.start_combat(npc_idx);
+        } else if is_key_pressed(KeyCode::N) || is_key_pressed(KeyCode::Escape) {
+            self.add_message("You think better of it.".to_string());
+            self.state = GameState::Playing;
+        }
+    }
+    
+    /// NPC the player bumped and still stands next to, if a pickpocket prompt is open
+    fn take_pending_interaction(&mut self) -> Option<usize> {
+        let idx = self.pending_interaction.take()?;
+        let npc = self.npcs.get(idx)?;
+        let adjacent = (npc.x - self.player.x).abs() + (npc.y - self.player.y).abs() == 1;
+        (adjacent && !npc.hostile).then_some(idx)
+    }
+    
+    /// Handle input in the inventory
+    /// Left/Right switch tabs, Up/Down select, Enter uses or equips, 1-5 assign the hotbar,
+    /// and I or ESC close it. The cursor counts rows within the tab, so it is mapped back
+    /// to the real inventory index before anything is done to the item
+    fn handle_inventory_input(&mut self) {
+        let GameState::Inventory(mut selected, mut tab) = self.state else {
+            return;
+        };
+        if self.config.left_pressed() {
+            tab = tab.cycled(-1);
+            selected = 0;
+        }
+        if self.config.right_pressed() {
+            tab = tab.cycled(1);
+            selected = 0;
+        }
+        let listed = self.player.tab_items(tab);
+        if self.config.up_pressed() && selected > 0 {
+            selected -= 1;
+        }
+        if self.config.down_pressed() && selected + 1 < listed.len() {
+            selected += 1;
+        }
+        
+        if let Some(&idx) = listed.get(selected) {
+            // R reads the selected note
+            if let ItemType::Note { text } = &self.player.inventory[idx].item_type
+                && is_key_pressed(KeyCode::R)
+            {
+                self.state = GameState::Reading(ReadingState::new(&self.player.inventory[idx].name, text, true));
+                return;
+            }
+            // Enter uses or equips the selected item
+            if is_key_pressed(KeyCode::Enter) {
+                let msg = self.player.use_item(idx);
+                self.add_message(msg);
+                selected = selected.min(self.player.tab_items(tab).len().saturating_sub(1));
+            }
+            // Number keys put the selected item on that hotbar slot
+            else if let Some(slot) = HOTBAR_KEYS.iter().position(|&key| is_key_pressed(key)) {
+                for held in self.player.hotbar.iter_mut().filter(|held| **held == Some(idx)) {
+                    *held = None;
+                }
+                self.player.hotbar[slot] = Some(idx);
+                let msg = format!("{} assigned to hotbar slot {}", self.player.inventory[idx].name, slot + 1);
+                self.add_message(msg);
+            }
+        }
+        
+        // I key or ESC key closes inventory
+        self.state = if is_key_pressed(self.config.open_inventory) || is_key_pressed(KeyCode::Escape) {
+            GameState::Playing
+        } else {
+            GameState::Inventory(selected, tab)
+        };
+    }
+    
+    /// Handle input at the workbench
+    /// Enter picks the first ingredient, then the second and crafts; ESC drops the pick or leaves
+    fn handle_crafting_input(&mut self) {
+        let GameState::Crafting(mut cursor, mut first) = self.state else {
+            return;
+        };
+        let count = self.player.inventory.len();
+        if self.config.up_pressed() && cursor > 0 {
+            cursor -= 1;
+        }
+        if self.config.down_pressed() && cursor + 1 < count {
+            cursor += 1;
+        }
+        
+        if is_key_pressed(KeyCode::Enter) && cursor < count {
+            match first {
+                None => first = Some(cursor),
+                Some(picked) if picked == cursor => first = None,
+                Some(picked) => {
+                    first = None;
+                    let inventory = &self.player.inventory;
+                    match try_craft(&inventory[picked], &inventory[cursor], &recipes()) {
+                        Some(output) => {
+                            // Remove the later index first so the earlier one stays put
+                            self.player.take_one(picked.max(cursor));
+                            self.player.take_one(picked.min(cursor));
+                            self.add_message(format!("Crafted {}", output.name));
+                            self.player.add_item(output);
+                            check_achievement(self, "tinkerer");
+                            cursor = cursor.min(self.player.inventory.len() - 1);
+                        }
+                        None => {
+                            let msg = format!("Nothing comes of combining {} and {}", inventory[picked].name, inventory[cursor].name);
+                            self.add_message(msg);
+                        }
+                    }
+                }
+            }
+        }
+        
+        if is_key_pressed(KeyCode::Escape) {
+            if first.is_some() {
+                first = None;
+            } else {
+                self.state = GameState::Playing;
+                return;
+            }
+        }
+        self.state = GameState::Crafting(cursor, first);
+    }
+    
+    /// Handle input while looking inside a container
+    /// Enter takes the selected item, T takes everything, ESC closes it
+    fn handle_looting_input(&mut self) {
+        let GameState::Looting(pos, mut selected) = self.state else {
+            return;
+        };
+        let Some(container) = self.current_map.containers.get_mut(&pos) else {
+            self.state = GameState::Playing;
+            return;
+        };
+        if self.config.up_pressed() && selected > 0 {
+            selected -= 1;
+        }
+        if self.config.down_pressed() && selected + 1 < container.contents.len() {
+            selected += 1;
+        }
+        
+        let taken: Vec<Item> = if is_key_pressed(KeyCode::T) {
+            container.contents.drain(..).collect()
+        } else if is_key_pressed(KeyCode::Enter) && selected < container.contents.len() {
+            vec![container.contents.remove(selected)]
+        } else {
+            Vec::new()
+        };
+        selected = selected.min(container.contents.len().saturating_sub(1));
+        for item in taken {
+            if let ItemType::Gold { amount } = item.item_type {
+                self.earn_gold(amount);
+                self.add_message(format!("Took {} caps", amount));
+                continue;
+            }
+            self.stats.items_collected += 1;
+            if self.stats.items_collected >= 20 {
+                check_achievement(self, "hoarder");
+            }
+            self.add_message(format!("Took {}", item.display_name()));
+            let name = item.name.clone();
+            self.player.add_item(item);
+            self.advance_quests(QuestEvent::PickedUp(name));
+        }
+        
+        self.state = if is_key_pressed(KeyCode::Escape) {
+            GameState::Playing
+        } else {
+            GameState::Looting(pos, selected)
+        };
+    }
+    
+    /// Count down the step in progress, then take the one queued behind it
+    fn advance_step(&mut self, dt: f32) {
+        self.move_accumulator = (self.move_accumulator - dt).max(0.0);
+        if self.move_accumulator <= 0.0 && let Some((dx, dy)) = self.queued_move.take() {
+            self.move_player(dx, dy);
+        }
+    }
+    
+    /// Keep stepping while a direction key stays held: after the initial delay,
+    /// once every repeat interval. Only runs while exploring, never in menus
+    fn repeat_held_movement(&mut self, dt: f32) {
+        if !matches!(self.state, GameState::Playing) {
+            self.held_move = None;
+            self.queued_move = None;
+            return;
+        }
+        let held = if self.config.hold_to_move { self.config.held_direction() } else { None };
+        if held != self.held_move {
+            // A new key went down (and already stepped) or the key was let go
+            self.held_move = held;
+            self.move_repeat_timer = self.config.move_repeat_delay;
+            return;
+        }
+        let Some((dx, dy)) = held else {
+            return;
+        };
+        self.move_repeat_timer -= dt;
+        if self.move_repeat_timer <= 0.0 {
+            self.move_repeat_timer += self.config.move_repeat_interval;
+            self.move_player(dx, dy);
+        }
+    }
+    
+    /// Give every NPC its turn after the player moves
+    /// Unaware enemies walk their patrol routes, alerted ones close in and attack,
+    /// and friendly folk wander around their homes
+    fn move_enemies(&mut self) {
+        for idx in 0..self.npcs.len() {
+            let npc = &self.npcs[idx];
+            // Whoever the schedule has off the map stays put until they come back
+            if npc.is_away(self.time_of_day) {
+                continue;
+            }
+            if !npc.hostile {
+                self.move_friendly(idx);
+                continue;
+            }
+            // Walking around openly within its aggro radius gets you noticed by anything
+            // that can see you, unless enough are already after you;
+            // a chaser that has had you out of sight past its leash for a few turns
+            // gives up and goes back to its rounds, while one alerted some other way
+            // (the detection meter, a fight) keeps coming until it has seen you
+            let distance = (self.player.x - npc.x).abs().max((self.player.y - npc.y).abs());
+            let in_view = self.fov.contains(&(npc.x, npc.y));
+            let chasers = self.npcs.iter()
+                .filter(|npc| npc.hostile && npc.alert_state == AlertState::Alerted && !npc.is_away(self.time_of_day))
+                .count();
+            if npc.alert_state == AlertState::Alerted && distance <= npc.leash && in_view {
+                self.npcs[idx].last_seen = Some(self.turns);
+            } else if npc.alert_state == AlertState::Alerted
+                && distance > npc.leash
+                && npc.last_seen.is_some_and(|seen| self.turns >= seen + LOSE_TRACK_TURNS)
+            {
+                self.npcs[idx].alert_state = AlertState::Unaware;
+                self.npcs[idx].last_seen = None;
+            } else if npc.alert_state == AlertState::Unaware
+                && distance <= npc.aggro_radius
+                && chasers < MAX_CHASERS
+                && !self.player.is_sneaking
+                && in_view
+            {
+                self.npcs[idx].alert_state = AlertState::Alerted;
+                self.npcs[idx].last_seen = Some(self.turns);
+                self.add_message(format!("{} spots you!", self.npcs[idx].name));
+            }
+            let npc = &self.npcs[idx];
+            let target = match npc.alert_state {
+                AlertState::Alerted => (self.player.x, self.player.y),
+                AlertState::Unaware => match npc.patrol.get(npc.patrol_step) {
+                    Some(&waypoint) => waypoint,
+                    None => continue,
+                },
+            };
+            let (ox, oy) = (target.0 - npc.x, target.1 - npc.y);
+            if npc.alert_state == AlertState::Alerted && ox.abs() + oy.abs() == 1 {
+                self.npcs[idx].facing = (ox, oy);
+                self.add_message(format!("{} catches up with you!", self.npcs[idx].name));
+                self.start_combat(idx);
+                return;
+            }
+            if (ox, oy) == (0, 0) {
+                let npc = &mut self.npcs[idx];
+                npc.patrol_step = (npc.patrol_step + 1) % npc.patrol.len();
+                continue;
+            }
+            
+            // Follow the shortest way around other NPCs
+            let from = (npc.x, npc.y);
+            let blocked: HashSet<(i32, i32)> = self.npcs.iter()
+                .enumerate()
+                .filter(|&(other, _)| other != idx)
+                .map(|(_, other)| (other.x, other.y))
+                .collect();
+            let path = astar(&self.current_map, from, target, &blocked);
+            if let Some(&(nx, ny)) = path.as_ref().and_then(|path| path.first()) {
+                let (dx, dy) = (nx - from.0, ny - from.1);
+                if self.move_npc(idx, dx, dy) {
+                    self.npcs[idx].facing = (dx, dy);
+                    continue;
+                }
+            }
+            // A waypoint that can't be reached right now is passed over
+            if path.is_none() && self.npcs[idx].alert_state == AlertState::Unaware {
+                let npc = &mut self.npcs[idx];
+                npc.patrol_step = (npc.patrol_step + 1) % npc.patrol.len();
+                continue;
+            }
+            
+            // No way through: step along the longer axis first, sliding along the other when blocked
+            let (step_x, step_y) = ((ox.signum(), 0), (0, oy.signum()));
+            let steps = if ox.abs() >= oy.abs() { [step_x, step_y] } else { [step_y, step_x] };
+            for (dx, dy) in steps {
+                if (dx, dy) != (0, 0) && self.move_npc(idx, dx, dy) {
+                    self.npcs[idx].facing = (dx, dy);
+                    break;
+                }
+            }
+        }
+    }
+    
+    /// Give a friendly NPC its turn: head for wherever its schedule puts it,
+    /// or else now and then step to a random free tile near its home
+    /// The NPC the player just bumped into waits for them to choose what to do
+    fn move_friendly(&mut self, idx: usize) {
+        if self.pending_interaction == Some(idx) {
+            return;
+        }
+        if let Some(entry) = self.npcs[idx].scheduled(self.time_of_day) {
+            if let Some(anchor) = entry.anchor {
+                self.head_to(idx, anchor);
+            }
+            return;
+        }
+        if rand::gen_range(0, 100) >= WANDER_CHANCE {
+            return;
+        }
+        let (dx, dy) = [(0, -1), (0, 1), (-1, 0), (1, 0)][rand::gen_range(0, 4)];
+        let npc = &self.npcs[idx];
+        let (x, y) = (npc.x + dx, npc.y + dy);
+        if (x - npc.home.0).abs().max((y - npc.home.1).abs()) > WANDER_RADIUS
+            || self.current_map.items.contains_key(&(x, y))
+        {
+            return;
+        }
+        if self.move_npc(idx, dx, dy) {
+            self.npcs[idx].facing = (dx, dy);
+        }
+    }
+    
+    /// Walk an NPC one step along the shortest way to `anchor`
+    /// With no way through (a locked door, say) it lets itself in while nobody is looking
+    fn head_to(&mut self, idx: usize, anchor: (i32, i32)) {
+        let from = (self.npcs[idx].x, self.npcs[idx].y);
+        if from == anchor {
+            return;
+        }
+        let blocked: HashSet<(i32, i32)> = self.npcs.iter()
+            .enumerate()
+            .filter(|&(other, _)| other != idx)
+            .map(|(_, other)| (other.x, other.y))
+            .collect();
+        match astar(&self.current_map, from, anchor, &blocked).as_ref().and_then(|path| path.first()) {
+            Some(&(nx, ny)) => {
+                let (dx, dy) = (nx - from.0, ny - from.1);
+                if self.move_npc(idx, dx, dy) {
+                    self.npcs[idx].facing = (dx, dy);
+                }
+            }
+            None => {
+                if !self.fov.contains(&from)
+                    && !self.fov.contains(&anchor)
+                    && self.tile_occupant(anchor.0, anchor.1).is_none()
+                    && anchor != (self.player.x, self.player.y)
+                {
+                    (self.npcs[idx].x, self.npcs[idx].y) = anchor;
+                }
+            }
+        }
+    }
+    
+    /// Raise or lower the detection level by how closely enemies are watching
+    /// Enemies only notice what is in the player's own line of sight and in front of them;
+    /// a full meter alerts every enemy on the map
+    fn update_detection(&mut self, dt: f32) {
+        self.player.sneak_move_delay = (self.player.sneak_move_delay - dt).max(0.0);
+        
+        let mut rate = 0.0;
+        for npc in &self.npcs {
+            let (ox, oy) = (self.player.x - npc.x, self.player.y - npc.y);
+            let dist = ox.abs() + oy.abs();
+            if !npc.hostile
+                || npc.alert_state == AlertState::Alerted
+                || dist >= DETECTION_RANGE
+                || npc.facing.0 * ox + npc.facing.1 * oy <= 0
+                || !self.fov.contains(&(npc.x, npc.y))
+            {
+                continue;
+            }
+            let agility = self.player.stats.agility.max(1);
+            rate += 0.1 * (DETECTION_RANGE - dist) as f32 / (DETECTION_RANGE * agility) as f32;
+        }
+        if !self.player.is_sneaking {
+            rate *= NOISY_DETECTION_FACTOR;
+        }
+        
+        let before = self.player.detection_level;
+        if rate == 0.0 {
+            self.player.detection_level = (before - DETECTION_DECAY * dt).max(0.0);
+            return;
+        }
+        self.player.detection_level = (before + rate * dt).min(1.0);
+        if before < 0.5 && self.player.detection_level >= 0.5 {
+            self.add_message("You feel eyes on you.".to_string());
+        }
+        if before < 1.0 && self.player.detection_level >= 1.0 {
+            self.add_message("You've been spotted!".to_string());
+            for npc in self.npcs.iter_mut().filter(|npc| npc.hostile) {
+                npc.alert_state = AlertState::Alerted;
+            }
+        }
+    }
+    
+    /// Burn one turn of the lit torch, warning as it runs low
+    fn burn_torch(&mut self) {
+        if self.player.torch_turns <= 0 {
+            return;
+        }
+        self.player.torch_turns -= 1;
+        match self.player.torch_turns {
+            TORCH_WARNING_TURNS => self.add_message(format!("Your torch flickers ({} turns left)", TORCH_WARNING_TURNS)),
+            0 => self.add_message("Your torch sputters out".to_string()),
+            _ => {}
+        }
+    }
+    
+    /// Pick up the item under the player, if any
+    fn pick_up_item(&mut self) {
+        if let Some(item) = self.current_map.items.remove(&(self.player.x, self.player.y)) {
+            if let ItemType::Gold { amount } = item.item_type {
+                self.earn_gold(amount);
+                self.add_message(format!("Picked up {} caps", amount));
+                return;
+            }
+            self.stats.items_collected += 1;
+            if self.stats.items_collected >= 20 {
+                check_achievement(self, "hoarder");
+            }
+            self.add_message(format!("Picked up {}", item.display_name()));
+            let name = item.name.clone();
+            // Notes are read on the spot; they stay in the inventory for later
+            if let ItemType::Note { text } = &item.item_type {
+                self.state = GameState::Reading(ReadingState::new(&name, text, true));
+                check_achievement(self, "bookworm");
+            }
+            self.player.add_item(item);  // Add item to inventory
+            self.advance_quests(QuestEvent::PickedUp(name));
+        }
+    }
+    
+    /// Where an item dropped at (x, y) comes to rest
+    /// The tile itself if it is walkable and bare, else the first such neighbor; None if boxed in
+    fn drop_spot(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let free = |pos: (i32, i32)| {
+            self.current_map.is_walkable(pos.0, pos.1) && !self.current_map.items.contains_key(&pos)
+        };
+        if free((x, y)) {
+            return Some((x, y));
+        }
+        (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .find(|&pos| free(pos))
+    }
+    
+    /// Index of the NPC standing at (x, y), if any
+    fn tile_occupant(&self, x: i32, y: i32) -> Option<usize> {
+        self.npcs.iter().position(|n| n.x == x && n.y == y && !n.is_away(self.time_of_day))
+    }
+    
+    /// Move an NPC one step unless the target is blocked
+    /// NPCs never share a tile with each other or the player; items stay where they lie
+    /// Returns whether the NPC moved
+    fn move_npc(&mut self, npc_idx: usize, dx: i32, dy: i32) -> bool {
+        let new_x = self.npcs[npc_idx].x + dx;
+        let new_y = self.npcs[npc_idx].y + dy;
+        if !self.current_map.is_walkable(new_x, new_y)
+            || (new_x, new_y) == (self.player.x, self.player.y)
+            || self.tile_occupant(new_x, new_y).is_some() {
+            return false;
+        }
+        self.npcs[npc_idx].x = new_x;
+        self.npcs[npc_idx].y = new_y;
+        true
+    }
+    
+    /// Resolve one step: bump NPCs, open doors, or move and pick up items
+    fn step_player(&mut self, dx: i32, dy: i32) {
+        let new_x = self.player.x + dx;
+        let new_y = self.player.y + dy;
+        self.player.facing = (dx, dy);
+        
+        // Check if there's an NPC at target position
+        if let Some(npc_idx) = self.tile_occupant(new_x, new_y) {
+            // Trigger combat or dialogue based on NPC hostility
+            if self.npcs[npc_idx].hostile {
+                self.add_message(format!("Combat with {}!", self.npcs[npc_idx].name));
+                self.start_combat(npc_idx);
+            } else if self.player.stats.agility >= MIN_PICKPOCKET_AGILITY {
+                // Nimble characters get to choose between lifting a pocket and talking
+                self.pending_interaction = Some(npc_idx);
+                self.add_message("Press P to pickpocket, F to talk or K to attack.".to_string());
+            } else {
+                self.start_dialogue(npc_idx);
+            }
+            return;  // Don't move player position
+        }
+        
+        // Bumping a door opens it instead of moving
+        if self.try_open_door(new_x, new_y) {
+            return;
+        }
+        
+        // Weak walls give way to a strong shove
+        let in_bounds = new_x >= 0 && new_x < self.current_map.width && new_y >= 0 && new_y < self.current_map.height;
+        if in_bounds && self.current_map.tiles[new_y as usize][new_x as usize] == TileType::WeakWall {
+            if self.player.stats.strength >= MIN_SMASH_STRENGTH {
+                smash_wall(&mut self.current_map, new_x, new_y);
+                self.add_message("You smash through the wall!".to_string());
+            } else {
+                self.add_message("The wall won't budge.".to_string());
+            }
+            return;
+        }
+        
+        if self.current_map.signs.contains_key(&(new_x, new_y)) {
+            self.add_message("A signpost. Press Space to read it.".to_string());
+            return;
+        }
+        // Bumping a container opens it
+        if self.current_map.containers.contains_key(&(new_x, new_y)) {
+            self.open_container((new_x, new_y));
+            return;
+        }
+        
+        // Known traps are stepped around rather than walked into
+        if let Some(trap) = self.current_map.traps.get(&(new_x, new_y))
+            && trap.detected
+            && trap.armed()
+        {
+            self.add_message(format!("You avoid the {} at ({},{}). Press F to disarm it.", trap.kind.name(), new_x, new_y));
+            return;
+        }
+        
+        // Check map collision (walls, water, etc.)
+        if self.current_map.is_walkable(new_x, new_y) {
+            // Rain turns grass to mud: every other step off it is spent pulling free
+            if self.is_outdoors()
+                && self.current_weather.is_wet()
+                && self.current_map.tiles[self.player.y as usize][self.player.x as usize] == TileType::Grass
+            {
+                self.bogged_down = !self.bogged_down;
+                if self.bogged_down {
+                    return;
+                }
+            }
+            
+            // Update player position
+            self.player.x = new_x;
+            self.player.y = new_y;
+            
+            self.stats.steps_taken += 1;
+            if self.stats.steps_taken >= 1000 {
+                check_achievement(self, "wanderer");
+            }
+            
+            if self.current_map.tiles[new_y as usize][new_x as usize] == TileType::Workbench {
+                self.add_message("A workbench. Press C to craft.".to_string());
+            }
+            
+            // Check if there's an item to pick up; caps are always scooped up
+            let caps_here = self.current_map.items.get(&(new_x, new_y))
+                .is_some_and(|item| matches!(item.item_type, ItemType::Gold { .. }));
+            if self.auto_pickup || caps_here {
+                self.pick_up_item();
+            } else if let Some(item) = self.current_map.items.get(&(new_x, new_y)) {
+                self.add_message(format!("There is a {} here (press G to pick up)", item.name));
+            }
+            
+            // Hidden traps go off underfoot
+            if self.current_map.traps.get(&(new_x, new_y)).is_some_and(Trap::armed) {
+                self.spring_trap((new_x, new_y), "You triggered");
+            }
+            
+            // Sharp eyes spot traps and hidden doors nearby
+            self.spot_traps();
+            self.spot_secret_doors(0);
+            
+            // Wandering the wasteland can draw out hostiles, but never right after the last ambush
+            if self.current_map.map_type == MapType::WorldMap {
+                self.steps_since_encounter += 1;
+                if self.steps_since_encounter >= ENCOUNTER_SAFE_STEPS
+                    && rand::gen_range(0.0, 1.0) < self.encounter_chance(new_x, new_y)
+                {
+                    self.start_encounter();
+                }
+            }
+        }
+    }
+    
+    /// Chance that a world map step onto (x, y) ends in an ambush
+    /// Forests hide more trouble than open grass, nights are twice as dangerous,
+    /// luck above 5 steers the player clear and below 5 walks them into it
+    fn encounter_chance(&self, x: i32, y: i32) -> f32 {
+        if self.player.has_ability(ABILITY_OUTDOORSMAN) {
+            return 0.0;
+        }
+        let mut chance = self.current_map.tiles[y as usize][x as usize].encounter_chance();
+        if self.is_night() {
+            chance *= 2.0;
+        }
+        chance * (1.5 - self.player.stats.luck as f32 * 0.1).max(0.25)
+    }
+    
+    /// Spawn one or two random hostiles next to the player and go straight into combat
+    /// Ambushers know where the player is, so the second one closes in once the first falls
+    fn start_encounter(&mut self) {
+        let table = encounter_table(self.current_map.tiles[self.player.y as usize][self.player.x as usize]);
+        let mut free: Vec<(i32, i32)> = self.adjacent_tiles().into_iter()
+            .filter(|&(x, y)| (x - self.player.x).abs() + (y - self.player.y).abs() == 1)
+            .filter(|&(x, y)| self.current_map.is_walkable(x, y) && self.tile_occupant(x, y).is_none())
+            .collect();
+        free.shuffle();
+        let count = rand::gen_range(1, 3).min(free.len());
+        if count == 0 {
+            return;
+        }
+        self.steps_since_encounter = 0;
+        
+        let mut names = Vec::new();
+        for &(x, y) in &free[..count] {
+            let template = pick_enemy(table, |total| rand::gen_range(0, total));
+            let mut npc = Npc::spawn_enemy(template, x, y);
+            // Ambushers have already spotted the player
+            npc.facing = (self.player.x - x, self.player.y - y);
+            npc.alert_state = AlertState::Alerted;
+            self.difficulty.scale_enemy(&mut npc);
+            names.push(template.name);
+            self.npcs.push(npc);
+        }
+        let ambushers = match names[..] {
+            [a, b] if a == b => format!("{}s", a),
+            [a, b] => format!("a {} and a {}", a, b),
+            _ => format!("a {}", names[0]),
+        };
+        self.add_message(format!("Ambushed by {}!", ambushers));
+        self.start_combat(self.npcs.len() - count);
+    }
+    
+    /// Run from the current fight
+    /// Wasteland ambushers give up the chase, so normal travel resumes;
+    /// roaming enemies lose sight of you for a moment and go back to their rounds,
+    /// and people the player turned on stay where they are
+    fn flee_combat(&mut self) {
+        self.add_message("You ran away!".to_string());
+        self.state = GameState::Playing;
+        if self.current_map.map_type == MapType::WorldMap {
+            self.npcs.retain(|npc| !npc.hostile || !npc.patrol.is_empty() || npc.faction.is_some());
+            for npc in self.npcs.iter_mut().filter(|npc| npc.hostile) {
+                npc.alert_state = AlertState::Unaware;
+            }
+        }
+    }
+    
+    /// Enter combat with an NPC, rolling initiative by agility
+    /// A faster enemy gets a free strike before the player's first action; ties go to the player
+    fn start_combat(&mut self, npc_idx: usize) {
+        self.state = GameState::Combat(npc_idx);
+        self.combat_log.clear();
+        self.damage_floaters.clear();
+        let name = self.npcs[npc_idx].name.clone();
+        if !self.bestiary.contains_key(&name) {
+            self.add_combat_message(format!("New bestiary entry: {}", name));
+            self.bestiary.insert(name.clone(), BestiaryEntry {
+                description: enemy_lore(&name).to_string(),
+                first_map: self.current_map.name.clone(),
+                kills: 0,
+                name,
+            });
+            if ENEMY_TEMPLATES.iter().all(|template| self.bestiary.contains_key(template.name)) {
+                check_achievement(self, "naturalist");
+            }
+        }
+        if self.npcs[npc_idx].agility > self.player.stats.agility {
+            self.add_combat_message(format!("{} is faster!", self.npcs[npc_idx].name));
+            self.enemy_attack(npc_idx);
+        }
+    }
+    
+    /// Roll perception for every hidden trap within two tiles, revealing the ones noticed
+    fn spot_traps(&mut self) {
+        let chance = self.player.stats.perception * 10 - TRAP_SPOT_DIFFICULTY;
+        let (px, py) = (self.player.x, self.player.y);
+        let mut spotted = Vec::new();
+        for (&(x, y), trap) in self.current_map.traps.iter_mut() {
+            if !trap.detected
+                && (x - px).abs().max((y - py).abs()) <= 2
+                && rand::gen_range(0, 100) < chance
+            {
+                trap.detected = true;
+                spotted.push(format!("You spot a {} at ({},{})!", trap.kind.name(), x, y));
+            }
+        }
+        for msg in spotted {
+            self.add_message(msg);
+        }
+    }
+    
+    /// Set off the trap at `pos` on the player, leaving it spent and in plain sight
+    /// `cause` starts the warning, e.g. "You triggered"
+    fn spring_trap(&mut self, pos: (i32, i32), cause: &str) {
+        let Some(trap) = self.current_map.traps.get_mut(&pos) else {
+            return;
+        };
+        trap.sprung = true;
+        trap.detected = true;
+        let (name, damage) = (trap.kind.name(), trap.damage);
+        self.add_warning(format!("{} a {}! -{} HP", cause, name, damage));
+        self.damage_player(damage);
+    }
+    
+    /// Roll perception for every hidden door next to the player, revealing the ones noticed
+    /// `bonus` is added to the roll; returns whether anything was found
+    fn spot_secret_doors(&mut self, bonus: i32) -> bool {
+        let chance = self.player.stats.perception * 10 - SECRET_DOOR_DIFFICULTY + bonus;
+        let mut found = false;
+        for (x, y) in self.adjacent_tiles() {
+            if self.current_map.tiles[y as usize][x as usize] == TileType::SecretDoor
+                && rand::gen_range(0, 100) < chance
+            {
+                self.current_map.tiles[y as usize][x as usize] = TileType::Door;
+                self.current_map.doors.insert((x, y), DoorState::Closed);
+                self.add_message("You notice a hidden door!".to_string());
+                check_achievement(self, "keen_eye");
+                found = true;
+            }
+        }
+        found
+    }
+    
+    /// Set off for a clicked world map tile along the shortest path there
+    /// Only explored land can be picked and walked through
+    fn travel_to(&mut self, x: i32, y: i32) {
+        if self.current_map.map_type != MapType::WorldMap
+            || !self.current_map.is_walkable(x, y)
+            || (x, y) == (self.player.x, self.player.y)
+        {
+            return;
+        }
+        if !self.is_explored(x, y) {
+            self.add_message("You don't know the way there.".to_string());
+            return;
+        }
+        match self.current_map.find_path((self.player.x, self.player.y), (x, y)) {
+            Some(mut path) => {
+                path.reverse();
+                self.auto_travel = Some(path);
+            }
+            None => self.add_message("You can't find a way there.".to_string()),
+        }
+    }
+    
+    /// Take the next step of a clicked journey once the last step has finished
+    /// The journey ends early when an enemy gives chase or a new item comes into sight
+    fn step_auto_travel(&mut self) {
+        if self.move_accumulator > 0.0 {
+            return;
+        }
+        let Some(&(x, y)) = self.auto_travel.as_ref().and_then(|path| path.last()) else {
+            return;
+        };
+        let items_in_sight = |game: &Game| game.current_map.items.keys().filter(|pos| game.fov.contains(pos)).count();
+        let seen_before = items_in_sight(self);
+        self.move_player(x - self.player.x, y - self.player.y);
+        
+        let arrived = (self.player.x, self.player.y) == (x, y);
+        let chased = self.npcs.iter().any(|npc| npc.hostile && npc.alert_state == AlertState::Alerted);
+        let spotted = items_in_sight(self) > seen_before;
+        let finished = self.auto_travel.as_mut().is_none_or(|path| {
+            path.pop();
+            path.is_empty()
+        });
+        if finished || !arrived || chased || spotted || !matches!(self.state, GameState::Playing) {
+            self.auto_travel = None;
+        }
+    }
+    
+    /// Stand still for a turn, letting enemies close in
+    fn wait(&mut self) {
+        self.pass_turns(1);
+        self.update_fov();
+        if matches!(self.state, GameState::Playing) {
+            self.move_enemies();
+        }
+    }
+    
+    /// Spend a turn searching the surrounding tiles, with a bonus to notice hidden doors
+    fn search(&mut self) {
+        if !self.spot_secret_doors(SEARCH_BONUS) {
+            self.add_message("You search the area but find nothing.".to_string());
+        }
+        self.pass_turns(1);
+        self.update_fov();
+        self.move_enemies();
+    }
+    
+    /// In-bounds tiles surrounding the player
+    fn adjacent_tiles(&self) -> Vec<(i32, i32)> {
+        let mut tiles = Vec::new();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (x, y) = (self.player.x + dx, self.player.y + dy);
+                if (dx, dy) != (0, 0) && x >= 0 && x < self.current_map.width && y >= 0 && y < self.current_map.height {
+                    tiles.push((x, y));
+                }
+            }
+        }
+        tiles
+    }
+    
+    /// Try to disarm a spotted trap next to the player, which takes a turn
+    /// Agility (helped by the lockpick skill) decides it; a fumble sets the trap off
+    /// Returns false if there is no spotted, armed trap nearby
+    fn try_disarm_trap(&mut self) -> bool {
+        let Some((x, y)) = self.adjacent_tiles().into_iter().find(|pos| {
+            self.current_map.traps.get(pos).is_some_and(|trap| trap.detected && trap.armed())
+        }) else {
+            return false;
+        };
+        let chance = self.player.stats.agility * 10 + self.player.skills.lockpick / 2 - TRAP_DISARM_DIFFICULTY;
+        if rand::gen_range(0, 100) < chance {
+            if let Some(trap) = self.current_map.traps.remove(&(x, y)) {
+                self.add_message(format!("You disarm the {} at ({},{}).", trap.kind.name(), x, y));
+                check_achievement(self, "steady_hands");
+            }
+            self.player.skills.practice(SkillType::Lockpick);
+        } else {
+            self.spring_trap((x, y), "You fumble and set off");
+        }
+        self.pass_turns(1);
+        if matches!(self.state, GameState::Playing) {
+            self.update_fov();
+            self.move_enemies();
+        }
+        true
+    }
+    
+    /// Add caps to the player's purse, counting them toward the run's earnings
+    fn earn_gold(&mut self, amount: i32) {
+        self.player.gold += amount;
+        self.stats.gold_earned += amount.max(0) as u32;
+        if self.player.gold >= 500 {
+            check_achievement(self, "well_off");
+        }
+        if self.stats.gold_earned >= 2000 {
+            check_achievement(self, "tycoon");
+        }
+    }
+    
+    /// Deal damage to the player, ending the game if it drops them to 0 HP
+    fn damage_player(&mut self, amount: i32) {
+        self.player.hp -= amount;
+        self.stats.damage_taken += amount.max(0) as u32;
+        if self.player.hp <= 0 {
+            self.player.hp = 0;
+            self.state = GameState::GameOver;
+            self.add_message("You have died.".to_string());
+        }
+    }
+    
+    /// Open a closed door the player bumps into, which takes a turn; locked doors only say so
+    /// Returns true if the bump was handled (the player stays put)
+    fn try_open_door(&mut self, x: i32, y: i32) -> bool {
+        match self.current_map.doors.get(&(x, y)) {
+            Some(DoorState::Closed) => {
+                self.current_map.doors.insert((x, y), DoorState::Open);
+                self.add_message("You open the door.".to_string());
+                self.pass_turns(1);
+                true
+            }
+            Some(DoorState::Locked { .. }) => {
+                self.add_message("Locked.".to_string());
+                true
+            }
+            _ => false,
+        }
+    }
+    
+    /// Close an open door next to the player, which takes a turn
+    /// Doors with someone or something in the doorway stay open
+    /// Returns false if there is no open door to close
+    fn try_close_door(&mut self) -> bool {
+        let (px, py) = (self.player.x, self.player.y);
+        let Some(pos) = [(0, -1), (0, 1), (-1, 0), (1, 0)]
+            .into_iter()
+            .map(|(dx, dy)| (px + dx, py + dy))
+            .find(|&(x, y)| {
+                self.current_map.in_bounds(x, y)
+                    && self.current_map.displayed_tile(x, y) == TileType::Door
+            })
+        else {
+            return false;
+        };
+        if self.tile_occupant(pos.0, pos.1).is_some() || self.current_map.items.contains_key(&pos) {
+            self.add_message("Something is in the doorway.".to_string());
+            return true;
+        }
+        self.current_map.doors.insert(pos, DoorState::Closed);
+        self.add_message("You close the door.".to_string());
+        self.pass_turns(1);
+        self.update_fov();
+        self.move_enemies();
+        true
+    }
+    
+    /// Work on a locked door next to the player, if there is one
+    /// The matching key opens it outright; otherwise Lockpicks roll agility and skill
+    /// against the lock, and a failed roll snaps a pick
+    /// Returns false if there is no locked door to work on
+    fn try_unlock_door(&mut self) -> bool {
+        let (px, py) = (self.player.x, self.player.y);
+        let Some((x, y)) = [(0, -1), (0, 1), (-1, 0), (1, 0)]
+            .into_iter()
+            .map(|(dx, dy)| (px + dx, py + dy))
+            .find(|pos| matches!(self.current_map.doors.get(pos), Some(DoorState::Locked { .. })))
+        else {
+            return false;
+        };
+        let Some(DoorState::Locked { key, difficulty }) = self.current_map.doors.get(&(x, y)).cloned() else {
+            return false;
+        };
+        
+        if let Some(key) = key.filter(|key| self.player.item_index(key).is_some()) {
+            self.add_message(format!("You unlock the door with the {}.", key));
+        } else {
+            match self.pick_lock(difficulty) {
+                Some(true) => {}
+                Some(false) => return true,
+                None => {
+                    self.add_message("Locked. You need the key or some Lockpicks.".to_string());
+                    return true;
+                }
+            }
+        }
+        self.current_map.doors.insert((x, y), DoorState::Open);
+        self.wait();
+        true
+    }
+    
+    /// Pull a lever next to the player, toggling the gate it works
+    /// Returns false if there is no lever nearby
+    fn try_pull_lever(&mut self) -> bool {
+        let Some(pos) = self.adjacent_tiles().into_iter()
+            .find(|&(x, y)| self.current_map.tiles[y as usize][x as usize] == TileType::Lever)
+        else {
+            return false;
+        };
+        let Some(&link) = self.current_map.switches.get(&pos) else {
+            self.add_message("The lever won't move.".to_string());
+            return true;
+        };
+        let open = !self.current_map.links[link].lever_open;
+        let blocked = self.occupied_tiles();
+        let msg = if !self.current_map.set_lever(link, open, &blocked) {
+            "Clunk! Nothing seems to happen."
+        } else if open {
+            "Clunk! Somewhere, stone grinds open."
+        } else {
+            "Clunk! Somewhere, stone grinds shut."
+        };
+        self.add_message(msg.to_string());
+        self.pass_turns(1);
+        self.update_fov();
+        if matches!(self.state, GameState::Playing) {
+            self.move_enemies();
+        }
+        true
+    }
+    
+    /// Positions of the player and every NPC, which gates never close on
+    fn occupied_tiles(&self) -> Vec<(i32, i32)> {
+        self.npcs.iter()
+            .map(|npc| (npc.x, npc.y))
+            .chain([(self.player.x, self.player.y)])
+            .collect()
+    }
+    
+    /// Work the pressure plates around a step from `from` to where the player now stands:
+    /// leaving one lets it up, stepping onto one holds its gate open
+    fn trigger_plates(&mut self, from: (i32, i32)) {
+        let to = (self.player.x, self.player.y);
+        let blocked = self.occupied_tiles();
+        let plate_link = |map: &GameMap, (x, y): (i32, i32)| {
+            (map.tiles[y as usize][x as usize] == TileType::PressurePlate)
+                .then(|| map.switches.get(&(x, y)).copied())
+                .flatten()
+        };
+        if let Some(link) = plate_link(&self.current_map, from)
+            && self.current_map.set_plate(link, false, &blocked)
+        {
+            self.add_message("The plate clicks back up. Stone grinds shut.".to_string());
+        }
+        if let Some(link) = plate_link(&self.current_map, to)
+            && self.current_map.set_plate(link, true, &blocked)
+        {
+            self.add_message("Click. The plate sinks underfoot and stone grinds open.".to_string());
+        }
+    }
+    
+    /// Read a signpost next to the player
+    /// Returns false if there is no sign nearby
+    fn try_read_sign(&mut self) -> bool {
+        let Some(text) = self.adjacent_tiles().into_iter().find_map(|pos| self.current_map.signs.get(&pos)) else {
+            return false;
+        };
+        self.state = GameState::Reading(ReadingState::new("Sign", text, false));
+        true
+    }
+    
+    /// Handle input while reading a sign or note
+    /// Up/Down scroll the text, any other key puts it away
+    fn handle_reading_input(&mut self) {
+        let GameState::Reading(reading) = &mut self.state else {
+            return;
+        };
+        if self.config.up_pressed() {
+            reading.scroll = reading.scroll.saturating_sub(1);
+        }
+        if self.config.down_pressed() && reading.scroll + 1 < reading_lines(&reading.text).len() {
+            reading.scroll += 1;
+        }
+        if get_last_key_pressed().is_some() && !self.config.up_pressed() && !self.config.down_pressed() {
+            self.state = GameState::Playing;
+        }
+    }
+    
+    /// Handle input in the map editor
+    /// Arrows or the mouse move the cursor; clicks cycle the tile, E and I place or remove
+    /// an enemy or a Stimpak, Tab picks the enemy, Enter moves the entry point,
+    /// Ctrl+S saves the map and F4 or ESC goes back to playing
+    fn handle_editor_input(&mut self) {
+        let GameState::Editor(editor) = &mut self.state else {
+            return;
+        };
+        let map = &mut self.current_map;
+        
+        // The mouse takes the cursor along whenever it is over the map
+        let (mouse_x, mouse_y) = mouse_position();
+        let hovered = (
+            self.camera_x + ((mouse_x - MAP_ORIGIN.0) / TILE_SIZE).floor() as i32,
+            self.camera_y + ((mouse_y - MAP_ORIGIN.1) / TILE_SIZE).floor() as i32,
+        );
+        if mouse_delta_position() != Vec2::ZERO && map.in_bounds(hovered.0, hovered.1) {
+            editor.cursor = hovered;
+        }
+        
+        // Ctrl+S saves; holding Ctrl keeps S from also moving the cursor
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        if ctrl && is_key_pressed(KeyCode::S) {
+            let path = map_file_path(&map.name);
+            match std::fs::write(&path, map.to_text(&self.npcs)) {
+                Ok(()) => self.add_message(format!("Saved the map to {}", path)),
+                Err(err) => self.add_warning(format!("Could not save {}: {}", path, err)),
+            }
+            return;
+        }
+        
+        let (mut x, mut y) = editor.cursor;
+        if !ctrl {
+            if self.config.up_pressed() {
+                y -= 1;
+            }
+            if self.config.down_pressed() {
+                y += 1;
+            }
+            if self.config.left_pressed() {
+                x -= 1;
+            }
+            if self.config.right_pressed() {
+                x += 1;
+            }
+        }
+        if map.in_bounds(x, y) {
+            editor.cursor = (x, y);
+        }
+        let (x, y) = editor.cursor;
+        
+        // Left click steps forward through the tile types, right click back
+        let step = if is_mouse_button_pressed(MouseButton::Left) {
+            1
+        } else if is_mouse_button_pressed(MouseButton::Right) {
+            TileType::ALL.len() - 1
+        } else {
+            0
+        };
+        if step > 0 {
+            let tile = map.written_tile(x, y);
+            let current = TileType::ALL.iter().position(|&t| t == tile).unwrap_or(0);
+            map.tiles[y as usize][x as usize] = TileType::ALL[(current + step) % TileType::ALL.len()];
+            map.doors.remove(&(x, y));
+            map.track_doors();
+        }
+        
+        if is_key_pressed(KeyCode::Tab) {
+            editor.template = (editor.template + 1) % ENEMY_TEMPLATES.len();
+        }
+        let template = ENEMY_TEMPLATES[editor.template];
+        if is_key_pressed(KeyCode::E) {
+            match self.npcs.iter().position(|npc| (npc.x, npc.y) == (x, y)) {
+                Some(idx) => {
+                    self.npcs.remove(idx);
+                }
+                None => self.npcs.push(Npc::spawn_enemy(template, x, y)),
+            }
+        }
+        if is_key_pressed(KeyCode::I) && map.items.remove(&(x, y)).is_none() {
+            map.items.insert((x, y), Item {
+                name: "Stimpak".to_string(),
+                char: "+",
+                item_type: ItemType::Consumable { heal: 30, hunger_restore: 0, thirst_restore: 0 },
+                durability: None,
+                quantity: 1,
+            });
+        }
+        if is_key_pressed(KeyCode::Enter) {
+            map.entry_point = (x, y);
+        }
+        
+        if is_key_pressed(KeyCode::F4) || is_key_pressed(KeyCode::Escape) {
+            self.state = GameState::Playing;
+        }
+    }
+    
+    /// Handle input on the bestiary screen
+    /// Up/Down move through the entries, B or ESC closes it
+    fn handle_bestiary_input(&mut self) {
+        let count = self.bestiary.len();
+        let GameState::Bestiary(selected) = &mut self.state else {
+            return;
+        };
+        if self.config.up_pressed() {
+            *selected = selected.saturating_sub(1);
+        }
+        if self.config.down_pressed() && *selected + 1 < count {
+            *selected += 1;
+        }
+        if is_key_pressed(KeyCode::B) || is_key_pressed(KeyCode::Escape) {
+            self.state = GameState::Playing;
+        }
+    }
+    
+    /// Roll agility and skill against a lock with the player's Lockpicks
+    /// A failed roll snaps a pick; returns whether the lock gave, or None without Lockpicks
+    fn pick_lock(&mut self, difficulty: i32) -> Option<bool> {
+        let picks = self.player.item_index("Lockpicks")?;
+        let chance = self.player.stats.agility * 5 + self.player.skills.lockpick - difficulty;
+        if rand::gen_range(0, 100) >= chance {
+            self.player.take_one(picks);
+            self.add_message("Your pick snaps in the lock.".to_string());
+            return Some(false);
+        }
+        self.player.skills.practice(SkillType::Lockpick);
+        self.add_message("You pick the lock.".to_string());
+        check_achievement(self, "nimble_fingers");
+        Some(true)
+    }
+    
+    /// Open a container next to the player and look inside, picking its lock first if needed
+    /// Returns false if there is no container nearby
+    fn try_open_container(&mut self) -> bool {
+        let Some(pos) = self.adjacent_tiles().into_iter().find(|pos| self.current_map.containers.contains_key(pos)) else {
+            return false;
+        };
+        self.open_container(pos);
+        true
+    }
+    
+    /// Open the container at `pos` into the loot window, picking its lock first if needed
+    fn open_container(&mut self, pos: (i32, i32)) {
+        if self.current_map.containers[&pos].locked {
+            match self.pick_lock(DEFAULT_LOCK_DIFFICULTY) {
+                Some(true) => {
+                    if let Some(container) = self.current_map.containers.get_mut(&pos) {
+                        container.locked = false;
+                    }
+                }
+                Some(false) => return,
+                None => {
+                    let msg = format!("The {} is locked. You need some Lockpicks.", self.current_map.containers[&pos].name);
+                    self.add_message(msg);
+                    return;
+                }
+            }
+        }
+        if let Some(container) = self.current_map.containers.get_mut(&pos) {
+            container.opened = true;
+        }
+        self.state = GameState::Looting(pos, 0);
+    }
+    
+    /// Start a conversation with an NPC from its first dialogue node
+    /// Intelligence variants and class options are chosen here, once per conversation
+    fn start_dialogue(&mut self, npc_idx: usize) {
+        self.npcs[npc_idx].met = true;
+        let npc = &self.npcs[npc_idx];
+        let low_int = self.player.stats.intelligence <= LOW_INT_THRESHOLD;
+        // Waking someone up gets a grumble instead of the usual talk
+        let nodes = if npc.is_asleep(self.time_of_day) {
+            vec![DialogueNode {
+                text: "*yawn* Whassat? It's the middle of the night. Come back in the morning.".to_string(),
+                options: vec![DialogueOption::new("Sorry. Go back to sleep.", None)],
+            }]
+        } else {
+            // How the NPC's people feel about the player decides how they open
+            let standing = npc.faction.map_or(0, |faction| self.reputation_with(faction));
+            let shunned = npc.faction == Some(Faction::Townsfolk) && self.in_turned_town();
+            if standing <= REPUTATION_HOSTILE || shunned {
+                vec![DialogueNode {
+                    text: "I know what you've done. I've got nothing to say to you.".to_string(),
+                    options: vec![DialogueOption::new("Suit yourself.", None)],
+                }]
+            } else {
+                let mut nodes = npc.dialogue_for(low_int, self.player.class);
+                if standing >= REPUTATION_FRIENDLY {
+                    nodes[0].text = format!("Well, if it isn't our favorite wanderer! {}", nodes[0].text);
+                }
+                nodes
+            }
+        };
+        let first = TranscriptEntry {
+            speaker: npc.name.clone(),
+            text: nodes[0].text.clone(),
+            chosen: None,
+        };
+        self.state = GameState::Dialogue(DialogueState {
+            npc_idx,
+            node_idx: 0,
+            selected: 0,
+            nodes,
+            transcript: vec![first],
+            history_scroll: 0,
+            page: 0,
+            quest_count: self.quests.len(),
+        });
+    }
+    
+    /// Append a finished conversation line to the persistent dialogue log
+    /// Drops the oldest entries once the log exceeds `MAX_DIALOGUE_LOG`
+    fn log_dialogue(&mut self, entry: TranscriptEntry) {
+        self.dialogue_log.push(entry);
+        if self.dialogue_log.len() > MAX_DIALOGUE_LOG {
+            let excess = self.dialogue_log.len() - MAX_DIALOGUE_LOG;
+            self.dialogue_log.drain(..excess);
+        }
+    }
+    
+    /// Handle input while in a conversation
+    /// Moves the selection, follows chosen options and scrolls the transcript
+    fn handle_dialogue_input(&mut self) {
+        let GameState::Dialogue(mut dialogue) = std::mem::replace(&mut self.state, GameState::Playing) else {
+            return;
+        };
+        
+        let node = &dialogue.nodes[dialogue.node_idx];
+        let num_options = node.options.len();
+        
+        // Up/Down keys to select options
+        if self.config.up_pressed() && dialogue.selected > 0 {
+            dialogue.selected -= 1;
+        }
+        if self.config.down_pressed() && dialogue.selected + 1 < num_options {
+            dialogue.selected += 1;
+        }
+        
+        // PageUp/PageDown turn the pages of long node text, then scroll the conversation history
+        let pages = dialogue_text_lines(&self.npcs[dialogue.npc_idx], &node.text).len().div_ceil(DIALOGUE_PAGE_LINES);
+        let history_lines = transcript_lines(&dialogue.transcript).len();
+        if is_key_pressed(KeyCode::PageUp) {
+            if dialogue.history_scroll == 0 && dialogue.page > 0 {
+                dialogue.page -= 1;
+            } else if dialogue.history_scroll + 1 < history_lines {
+                dialogue.history_scroll += 1;
+            }
+        }
+        if is_key_pressed(KeyCode::PageDown) {
+            if dialogue.history_scroll > 0 {
+                dialogue.history_scroll -= 1;
+            } else if dialogue.page + 1 < pages {
+                dialogue.page += 1;
+            }
+        }
+        
+        // Space or Enter reads on until the last page, where the options are
+        let confirm = is_key_pressed(KeyCode::Space) || is_key_pressed(KeyCode::Enter);
+        if confirm && dialogue.page + 1 < pages {
+            dialogue.page += 1;
+        } else if confirm {
+            let option = &node.options[dialogue.selected];
+            
+            // Options asking for an item can't be picked without it, and take it when picked
+            if let Some(name) = &option.requires_item {
+                let Some(idx) = self.player.item_index(name) else {
+                    self.add_message(format!("You don't have the {}.", name));
+                    self.state = GameState::Dialogue(dialogue);
+                    return;
+                };
+                let item = self.player.take_one(idx);
+                self.add_message(format!("You hand over the {}.", item.name));
+            }
+            
+            let mut next_node = option.next_node;
+            if let Some((difficulty, success_node)) = option.speech_check
+                && self.player.speech_score() >= difficulty
+            {
+                next_node = Some(success_node);
+                self.player.skills.practice(SkillType::Speech);
+                check_achievement(self, "silver_tongue");
+            }
+            let action = option.action.clone();
+            let chosen = option.text.clone();
+            let speaker = self.npcs[dialogue.npc_idx].name.clone();
+            if let Some(current) = dialogue.transcript.last_mut() {
+                current.chosen = Some(chosen);
+            }
+            if let Some(action) = action {
+                self.apply_dialogue_action(action);
+            }
+            
+            if let Some(next) = next_node {
+                // Jump to next node and record what the NPC says there
+                let entry = TranscriptEntry {
+                    speaker,
+                    text: dialogue.nodes[next].text.clone(),
+                    chosen: None,
+                };
+                dialogue.transcript.push(entry);
+                dialogue.node_idx = next;
+                dialogue.selected = 0;
+                dialogue.history_scroll = 0;
+                dialogue.page = 0;
+            } else {
+                // End dialogue
+                self.finish_dialogue(dialogue);
+                return;
+            }
+        }
+        
+        // ESC key exits dialogue
+        if is_key_pressed(KeyCode::Escape) {
+            self.finish_dialogue(dialogue);
+            return;
+        }
+        
+        self.state = GameState::Dialogue(dialogue);
+    }
+    
+    /// End a conversation, moving its transcript into the persistent log
+    fn finish_dialogue(&mut self, dialogue: DialogueState) {
+        let npc_name = self.npcs[dialogue.npc_idx].name.clone();
+        for entry in dialogue.transcript {
+            self.log_dialogue(entry);
+        }
+        self.state = GameState::Playing;
+        // Quests accepted in this conversation can't be handed in by it
+        let accepted = self.quests.split_off(dialogue.quest_count);
+        self.advance_quests(QuestEvent::Talked(npc_name));
+        self.quests.extend(accepted);
+    }
+    
+    /// Apply the effect of a chosen dialogue option
+    fn apply_dialogue_action(&mut self, action: DialogueAction) {
+        match action {
+            DialogueAction::StartQuest(id) => self.start_quest(id),
+            DialogueAction::Buy(factory, base) => {
+                let item = factory();
+                let price = self.reputation_price(self.player.barter_price(base));
+                if self.player.gold < price {
+                    self.add_message(format!("You can't afford the {} ({} caps)", item.name, price));
+                } else {
+                    self.player.gold -= price;
+                    self.add_message(format!("Bought {} for {} caps", item.name, price));
+                    self.player.add_item(item);
+                }
+            }
+            DialogueAction::Reward { caps } => {
+                self.earn_gold(caps);
+                self.add_message(format!("Received {} caps", caps));
+            }
+            DialogueAction::RevealRegion { center_x, center_y, radius, cost } => {
+                if self.player.gold < cost {
+                    self.add_message(format!("You can't afford the map ({} caps)", cost));
+                    return;
+                }
+                self.player.gold -= cost;
+                self.reveal_region(center_x, center_y, radius);
+                self.add_message(format!("Bought a map of the surrounding land ({} caps)", cost));
+            }
+            DialogueAction::RepairItem { cost } => {
+                let Some(idx) = self.player.equipped_weapon else {
+                    self.add_message("You have no weapon equipped to repair.".to_string());
+                    return;
+                };
+                if self.player.gold < cost {
+                    self.add_message(format!("You can't afford the repair ({} caps)", cost));
+                    return;
+                }
+                let weapon = &mut self.player.inventory[idx];
+                if let Some((current, max)) = weapon.durability.as_mut() {
+                    *current = *max;
+                }
+                let msg = format!("Your {} is good as new ({} caps)", weapon.name, cost);
+                self.player.gold -= cost;
+                self.add_message(msg);
+            }
+        }
+    }
+    
+    /// Add a quest to the journal unless it was already started
+    fn start_quest(&mut self, id: usize) {
+        if self.quests.iter().any(|q| q.id == id) {
+            return;
+        }
+        if let Some(quest) = Quest::by_id(id) {
+            self.add_message(format!("New quest: {}", quest.title));
+            self.quests.push(quest);
+        }
+    }
+    
+    /// Advance active quests affected by a game event
+    /// Quests whose objective is met are completed and rewarded
+    fn advance_quests(&mut self, event: QuestEvent) {
+        let mut completed = Vec::new();
+        let mut reminders = Vec::new();
+        for (i, quest) in self.quests.iter_mut().enumerate() {
+            if quest.state != QuestState::Active {
+                continue;
+            }
+            let done = match (&quest.objective, &event) {
+                (QuestObjective::KillNpc { name, map, count }, QuestEvent::Killed(killed))
+                    if name == killed && *map == self.current_map.name => {
+                    quest.progress += 1;
+                    quest.progress >= *count
+                }
+                (QuestObjective::DeliverItem { item, to }, QuestEvent::PickedUp(picked)) => {
+                    if item == picked {
+                        reminders.push(format!("Bring the {} to the {}", item, to));
+                    }
+                    false
+                }
+                (QuestObjective::DeliverItem { item, to }, QuestEvent::Talked(npc)) => {
+                    to == npc && self.player.inventory.iter().any(|i| i.name == *item)
+                }
+                _ => false,
+            };
+            if done {
+                completed.push(i);
+            }
+        }
+        for reminder in reminders {
+            self.add_message(reminder);
+        }
+        for i in completed {
+            self.complete_quest(i);
+        }
+    }
+    
+    /// Mark a quest completed and grant its reward
+    fn complete_quest(&mut self, quest_idx: usize) {
+        self.quests[quest_idx].state = QuestState::Completed;
+        let quest = self.quests[quest_idx].clone();
+        self.add_message(format!("Quest completed: {}", quest.title));
+        check_achievement(self, "helping_hand");
+        self.karma += QUEST_KARMA;
+        self.adjust_reputation(Faction::Townsfolk, QUEST_REPUTATION_GAIN);
+        
+        // Delivered items are handed over
+        if let QuestObjective::DeliverItem { item, .. } = &quest.objective
+            && let Some(idx) = self.player.inventory.iter().position(|i| i.name == *item)
+        {
+            self.player.take_one(idx);
+        }
+        
+        let reward = quest.reward;
+        if reward.gold > 0 {
+            self.earn_gold(reward.gold);
+            self.add_message(format!("Received {} caps", reward.gold));
+        }
+        if let Some(item) = reward.item {
+            self.add_message(format!("Received {}", item.name));
+            self.player.add_item(item);
+        }
+        self.award_xp(reward.xp);
+    }
+    
+    /// Grant experience, raised by the player's intelligence bonus
+    /// Opens the level-up screen if the player gained a level
+    fn award_xp(&mut self, xp: i32) {
+        if xp <= 0 {
+            return;
+        }
+        let xp = xp * (100 + self.player.xp_bonus()) / 100;
+        self.add_message(format!("Gained {} XP", xp));
+        if self.player.gain_xp(xp) {
+            self.add_message(format!("Level up! You are now level {}", self.player.level));
+            if self.player.level >= 5 {
+                check_achievement(self, "seasoned");
+            }
+            if self.player.level >= 10 {
+                check_achievement(self, "veteran");
+            }
+            self.advance_level_up();
+        }
+    }
+    
+    /// Move to the next level-up step: pending perk choices, then skill points
+    fn advance_level_up(&mut self) {
+        if self.player.pending_perks > 0 {
+            self.state = GameState::PerkSelection(self.player.roll_perk_choices(), 0);
+        } else if self.player.skill_points > 0 {
+            self.state = GameState::LevelUp(LevelUpState::new());
+        } else {
+            self.state = GameState::Playing;
+        }
+    }
+    
+    /// Handle input on the perk selection screen
+    fn handle_perk_selection_input(&mut self) {
+        let GameState::PerkSelection(choices, selected) = &mut self.state else {
+            return;
+        };
+        
+        if self.config.up_pressed() && *selected > 0 {
+            *selected -= 1;
+        }
+        if self.config.down_pressed() && *selected + 1 < choices.len() {
+            *selected += 1;
+        }
+        
+        // Enter/Space takes the highlighted perk
+        if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::Space) {
+            let perk = choices.get(*selected).copied();
+            self.player.pending_perks -= 1;
+            if let Some(perk) = perk {
+                self.add_message(format!("Perk gained: {}", perk.name));
+                self.player.add_perk(perk);
+            }
+            self.advance_level_up();
+        }
+    }
+    
+    /// Float a damage number up from the enemy's HP line, or the player's when `on_enemy` is false
+    /// Numbers already in the air push the new one right so they don't overlap
+    fn spawn_floater(&mut self, on_enemy: bool, value: i32, color: Color) {
+        let y = if on_enemy { 85.0 } else { 110.0 };
+        let stacked = self.damage_floaters.iter().filter(|floater| floater.pos.1 == y).count();
+        self.damage_floaters.push(DamageFloater {
+            pos: (200.0 + stacked as f32 * 40.0, y),
+            value,
+            color,
+            age: 0.0,
+        });
+    }
+    
+    /// Age the damage numbers, dropping the ones that have faded out
+    fn update_floaters(&mut self, dt: f32) {
+        for floater in &mut self.damage_floaters {
+            floater.age += dt;
+        }
+        self.damage_floaters.retain(|floater| floater.age < DAMAGE_FLOATER_DURATION);
+    }
+    
+    /// Resolve a player attack against the NPC in combat
+    fn player_attack(&mut self, npc_idx: usize) {
+        // Calculate damage, doubling it on a critical hit
+        let mut damage = self.player.attack_damage();
+        let crit = rand::gen_range(0, 100) < self.player.crit_chance();
+        if crit {
+            damage *= 2;
+            self.add_combat_message("Critical hit!".to_string());
+        }
+        self.npcs[npc_idx].hp -= damage;
+        self.stats.damage_dealt += damage.max(0) as u32;
+        self.spawn_floater(true, damage, if crit { YELLOW } else { WHITE });
+        self.add_combat_message(format!("You dealt {} damage!", damage));
+        if let Some(idx) = self.player.equipped_weapon
+            && let Some(name) = self.player.wear_item(idx)
+        {
+            self.add_combat_message(format!("Your {} broke!", name));
+        }
+        
+        // Check if enemy is defeated
+        if self.npcs[npc_idx].hp <= 0 {
+            self.defeat_enemy(npc_idx);
+        } else {
+            self.enemy_attack(npc_idx);
+        }
+    }
+    
+    /// Throw the inventory item at `item_idx` at the NPC in combat
+    /// Every hostile within the blast radius of the target is hit; if the target lives through it,
+    /// it strikes back, and if not, the surviving enemies take their turn
+    fn throw_item(&mut self, npc_idx: usize, item_idx: usize) {
+        let item = self.player.take_one(item_idx);
+        let ItemType::Throwable { damage, radius } = item.item_type else {
+            return;
+        };
+        self.add_combat_message(format!("You throw the {}!", item.name));
+        
+        let (tx, ty) = (self.npcs[npc_idx].x, self.npcs[npc_idx].y);
+        let hit: Vec<usize> = (0..self.npcs.len())
+            .filter(|&i| {
+                let npc = &self.npcs[i];
+                i == npc_idx || (npc.hostile && (npc.x - tx).abs().max((npc.y - ty).abs()) <= radius)
+            })
+            .collect();
+        self.spawn_floater(true, damage, WHITE);
+        for &i in &hit {
+            let npc = &mut self.npcs[i];
+            npc.hp -= damage;
+            npc.alert_state = AlertState::Alerted;
+            self.stats.damage_dealt += damage.max(0) as u32;
+            let msg = format!("{} takes {} damage!", npc.name, damage);
+            self.add_combat_message(msg);
+        }
+        
+        // Clear out the dead from the back so earlier indices stay valid
+        let mut target = Some(npc_idx);
+        for &i in hit.iter().rev() {
+            if self.npcs[i].hp <= 0 {
+                self.defeat_enemy(i);
+                target = match target {
+                    Some(t) if t == i => None,
+                    Some(t) if t > i => Some(t - 1),
+                    t => t,
+                };
+            }
+        }
+        if let Some(target) = target
+            && matches!(self.state, GameState::Playing | GameState::Throwing(..))
+        {
+            self.state = GameState::Combat(target);
+            self.enemy_attack(target);
+        } else if matches!(self.state, GameState::Playing) {
+            self.move_enemies();
+        }
+    }
+    
+    /// Handle input while picking something to throw
+    /// Up/Down select, Enter throws, ESC goes back to the fight
+    fn handle_throwing_input(&mut self) {
+        let GameState::Throwing(npc_idx, mut selected) = self.state else {
+            return;
+        };
+        let throwables = self.player.throwables();
+        if self.config.up_pressed() && selected > 0 {
+            selected -= 1;
+        }
+        if self.config.down_pressed() && selected + 1 < throwables.len() {
+            selected += 1;
+        }
+        self.state = GameState::Throwing(npc_idx, selected);
+        
+        if is_key_pressed(KeyCode::Escape) {
+            self.state = GameState::Combat(npc_idx);
+        } else if is_key_pressed(KeyCode::Enter) && let Some(&item_idx) = throwables.get(selected) {
+            self.throw_item(npc_idx, item_idx);
+        }
+    }
+    
+    /// Strike against the player by the NPC in combat, reduced by armor
+    fn enemy_attack(&mut self, npc_idx: usize) {
+        let base_damage = (self.npcs[npc_idx].damage - self.player.defense()).max(1);
+        let enemy_damage = (base_damage * self.difficulty.enemy_damage_percent() / 100).max(1);
+        self.add_combat_message(format!("{} dealt {} damage!", self.npcs[npc_idx].name, enemy_damage));
+        self.spawn_floater(false, enemy_damage, RED);
+        if let Some(idx) = self.player.equipped_armor
+            && let Some(name) = self.player.wear_item(idx)
+        {
+            self.add_combat_message(format!("Your {} broke!", name));
+        }
+        self.damage_player(enemy_damage);
+    }
+    
+    /// Remove a defeated NPC, award experience and advance quests
+    fn defeat_enemy(&mut self, npc_idx: usize) {
+        let npc = self.npcs.remove(npc_idx);  // Remove enemy from game
+        if self.player.has_ability(ABILITY_BLOODY_MESS) {
+            self.add_message(format!("{} bursts into a fine red mist!", npc.name));
+        } else {
+            self.add_message(format!("{} defeated!", npc.name));
+        }
+        self.state = GameState::Playing;
+        self.stats.enemies_killed += 1;
+        if let Some(entry) = self.bestiary.get_mut(&npc.name) {
+            entry.kills += 1;
+        }
+        // Killing anyone who isn't a wild enemy weighs on the conscience and their people,
+        // and the whole town it happened in turns its back on the player.
+        // Guards sent after the player are fought in self-defence and cost no karma
+        let mut trade_goods = Vec::new();
+        if let Some(faction) = npc.faction {
+            if npc.name != TOWN_GUARD.name {
+                self.karma -= MURDER_KARMA;
+            }
+            self.adjust_reputation(faction, -MURDER_REPUTATION_LOSS);
+            self.add_message(format!("You killed {}. Word will spread.", npc.name));
+            if let Some(town) = self.current_town() {
+                self.turned_towns.insert(town);
+            }
+            // Whatever they had for sale is left lying around
+            for option in npc.dialogue.iter().flat_map(|node| &node.options) {
+                if let Some(DialogueAction::Buy(factory, _)) = &option.action {
+                    let item = factory();
+                    if !trade_goods.iter().any(|good: &Item| good.name == item.name) {
+                        trade_goods.push(item);
+                    }
+                }
+            }
+        }
+        check_achievement(self, "first_blood");
+        if self.stats.enemies_killed >= 25 {
+            check_achievement(self, "slayer");
+        }
+        if self.stats.enemies_killed >= 100 {
+            check_achievement(self, "exterminator");
+        }
+        
+        // Everything it had falls where it died; auto-pickup scoops it straight up
+        for item in npc.inventory.into_iter().chain(npc.drops).chain(trade_goods) {
+            self.add_message(format!("{} dropped {}!", npc.name, item.name));
+            match self.drop_spot(npc.x, npc.y) {
+                Some(pos) if !self.auto_pickup => {
+                    self.current_map.items.insert(pos, item);
+                }
+                _ => {
+                    let name = item.name.clone();
+                    self.player.add_item(item);
+                    self.advance_quests(QuestEvent::PickedUp(name));
+                }
+            }
+        }
+        if npc.gold_carried > 0 {
+            self.add_message(format!("{} dropped {} caps!", npc.name, npc.gold_carried));
+            self.earn_gold(npc.gold_carried);
+        }
+        self.award_xp(npc.xp);
+        self.advance_quests(QuestEvent::Killed(npc.name));
+    }
+    
+    /// Handle input on the level-up screen
+    /// Up/Down select a stat, Left/Right (or -/+) assign points, Enter confirms
+    fn handle_level_up_input(&mut self) {
+        let GameState::LevelUp(level_up) = &mut self.state else {
+            return;
+        };
+        
+        if self.config.up_pressed() && level_up.selected > 0 {
+            level_up.selected -= 1;
+        }
+        let rows = StatType::ALL.len() + SkillType::ALL.len();
+        if self.config.down_pressed() && level_up.selected + 1 < rows {
+            level_up.selected += 1;
+        }
+        
+        // Pick the allocation slot and capped value for the selected row
+        let row = level_up.selected;
+        let can_spend = level_up.spent() < self.player.skill_points;
+        let (allocated, below_cap) = if row < StatType::ALL.len() {
+            let stat = StatType::ALL[row];
+            let slot = &mut level_up.stat_allocated[row];
+            let below_cap = self.player.stats.get(stat) + *slot < MAX_STAT;
+            (slot, below_cap)
+        } else {
+            let skill = SkillType::ALL[row - StatType::ALL.len()];
+            let slot = &mut level_up.skill_allocated[row - StatType::ALL.len()];
+            let below_cap = self.player.skills.get(skill) + *slot * SKILL_POINT_VALUE < MAX_SKILL;
+            (slot, below_cap)
+        };
+        let raise = self.config.right_pressed() || is_key_pressed(KeyCode::Equal);
+        let lower = self.config.left_pressed() || is_key_pressed(KeyCode::Minus);
+        if raise && can_spend && below_cap {
+            *allocated += 1;
+        }
+        if lower && *allocated > 0 {
+            *allocated -= 1;
+        }
+        
+        // Enter confirms; any unassigned points are kept for later
+        if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::Escape) {
+            let spent = level_up.spent();
+            for (stat, points) in StatType::ALL.iter().zip(level_up.stat_allocated) {
+                *self.player.stats.get_mut(*stat) += points;
+            }
+            for (skill, points) in SkillType::ALL.iter().zip(level_up.skill_allocated) {
+                let value = self.player.skills.get_mut(*skill);
+                *value = (*value + points * SKILL_POINT_VALUE).min(MAX_SKILL);
+            }
+            self.player.skill_points -= spent;
+            self.player.update_derived_stats();
+            self.state = GameState::Playing;
+        }
+    }
+    
+    /// Handle input on the character creation screen
+    /// Up/Down select a stat, Left/Right (or -/+) trade points, Enter starts once every point is spent
+    fn handle_creation_input(&mut self) {
+        let GameState::CharacterCreation(creation) = &mut self.state else {
+            return;
+        };
+        
+        if self.config.up_pressed() && creation.selected > 0 {
+            creation.selected -= 1;
+        }
+        if self.config.down_pressed() && creation.selected + 1 < StatType::ALL.len() {
+            creation.selected += 1;
+        }
+        let value = &mut creation.stats[creation.selected];
+        let raise = self.config.right_pressed() || is_key_pressed(KeyCode::Equal);
+        let lower = self.config.left_pressed() || is_key_pressed(KeyCode::Minus);
+        if raise && creation.points > 0 && *value < MAX_STAT {
+            *value += 1;
+            creation.points -= 1;
+        }
+        if lower && *value > MIN_STAT {
+            *value -= 1;
+            creation.points += 1;
+        }
+        
+        // The character is final; skills and max HP follow from the chosen stats
+        if is_key_pressed(KeyCode::Enter) && creation.points == 0 {
+            for (stat, value) in StatType::ALL.iter().zip(creation.stats) {
+                *self.player.stats.get_mut(*stat) = value;
+            }
+            self.player.skills = Skills::from_stats(&self.player.stats);
+            self.player.update_derived_stats();
+            self.player.hp = self.player.max_hp;
+            // Perception sets how far the player sees
+            self.update_fov();
+            self.state = GameState::Playing;
+        }
+    }
+    
+    /// Try to enter the town, dungeon or building whose entrance the player stands on
+    fn try_enter_location(&mut self) {
+        let x = self.player.x;
+        let y = self.player.y;
+        
+        // Look up where this entrance leads
+        let tile = self.current_map.tiles[y as usize][x as usize];
+        let Some(&(map_type, map_id)) = self.current_map.entrances.get(&(x, y)) else {
+            if tile.is_enterable() {
+                self.add_message(format!("Warning: entrance at ({}, {}) leads nowhere", x, y));
+            }
+            return;
+        };
+        
+        // Remember where we came from and keep this map's NPCs
+        self.store_npcs();
+        self.location_stack.push(MapLocation {
+            map_type: self.current_map.map_type,
+            map_id: self.current_map_id,
+            x,
+            y,
+        });
+        
+        // Move the destination map in and park the map being left
+        let map = std::mem::take(self.map_slot(map_type, map_id));
+        self.park_current_map(map);
+        (self.player.x, self.player.y) = self.current_map.entry_point;
+        self.current_map_id = map_id;
+        self.restore_npcs();
+        self.post_town_guards();
+        self.update_fov();
+        self.add_message(format!("Entered {}", self.current_map.name));
+        
+        // First visits unlock the town for fast travel
+        if map_type == MapType::Town && !self.discovered_towns.contains(&map_id) {
+            self.discovered_towns.push(map_id);
+            if self.discovered_towns.len() == self.town_maps.len() {
+                check_achievement(self, "globetrotter");
+            }
+        }
+        if map_type == MapType::Dungeon {
+            check_achievement(self, "delver");
+        } else if map_type == MapType::Interior {
+            check_achievement(self, "down_the_hatch");
+        }
+    }
+    
+    /// Handle input on the new game screen
+    /// Up/Down pick a difficulty, digits and Backspace edit the seed, Enter returns the chosen settings
+    fn handle_new_game_input(&mut self) -> Option<(Difficulty, PlayerClass, u64, bool)> {
+        let GameState::NewGame(new_game) = &mut self.state else {
+            return None;
+        };
+        
+        if self.config.up_pressed() && new_game.selected > 0 {
+            new_game.selected -= 1;
+        }
+        if self.config.down_pressed() && new_game.selected + 1 < Difficulty::ALL.len() {
+            new_game.selected += 1;
+        }
+        while let Some(ch) = get_char_pressed() {
+            if ch.is_ascii_digit() && new_game.seed_input.len() < NewGameState::MAX_SEED_DIGITS {
+                new_game.seed_input.push(ch);
+            }
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            new_game.seed_input.pop();
+        }
+        if is_key_pressed(KeyCode::Tab) {
+            new_game.classic_map = !new_game.classic_map;
+        }
+        let classes = PlayerClass::ALL.len();
+        if is_key_pressed(KeyCode::Left) {
+            new_game.class = (new_game.class + classes - 1) % classes;
+        }
+        if is_key_pressed(KeyCode::Right) {
+            new_game.class = (new_game.class + 1) % classes;
+        }
+        
+        is_key_pressed(KeyCode::Enter).then(|| {
+            (Difficulty::ALL[new_game.selected], PlayerClass::ALL[new_game.class], new_game.seed(), new_game.classic_map)
+        })
+    }
+    
+    /// Open the fast travel menu
+    /// Only available on the world map once at least one town has been discovered
+    fn open_fast_travel(&mut self) {
+        if self.current_map.map_type != MapType::WorldMap {
+            self.add_message("You can only fast travel from the world map.".to_string());
+        } else if self.discovered_towns.is_empty() {
+            self.add_message("You haven't discovered any towns yet.".to_string());
+        } else {
+            self.state = GameState::FastTravel(0);
+        }
+    }
+    
+    /// Handle input in the fast travel menu
+    /// Up/Down select a town, Enter travels there, T/ESC closes the menu
+    fn handle_fast_travel_input(&mut self) {
+        let GameState::FastTravel(selected) = &mut self.state else {
+            return;
+        };
+        
+        if self.config.up_pressed() && *selected > 0 {
+            *selected -= 1;
+        }
+        if self.config.down_pressed() && *selected + 1 < self.discovered_towns.len() {
+            *selected += 1;
+        }
+        
+        if is_key_pressed(KeyCode::Enter) {
+            let town_id = self.discovered_towns[*selected];
+            (self.player.x, self.player.y) = self.town_world_positions[town_id];
+            self.update_fov();
+            self.state = GameState::Playing;
+            self.add_message(format!("Traveled to {}", self.town_maps[town_id].name));
+        } else if is_key_pressed(self.config.open_map) || is_key_pressed(KeyCode::Escape) {
+            self.state = GameState::Playing;
+        }
+    }
+    
+    /// Handle input on the full-screen map: +/- zoom, arrow keys pan, ESC closes
+    fn handle_map_view_input(&mut self) {
+        let GameState::MapView(view) = &mut self.state else {
+            return;
+        };
+        
+        if is_key_pressed(KeyCode::Equal) || is_key_pressed(KeyCode::KpAdd) {
+            view.zoom = (view.zoom * 1.25).min(MAX_MAP_ZOOM);
+        }
+        if is_key_pressed(KeyCode::Minus) || is_key_pressed(KeyCode::KpSubtract) {
+            view.zoom = (view.zoom / 1.25).max(1.0);
+        }
+        
+        let step = MAP_PAN_SPEED * get_frame_time();
+        if is_key_down(KeyCode::Left) {
+            view.pan_x -= step;
+        }
+        if is_key_down(KeyCode::Right) {
+            view.pan_x += step;
+        }
+        if is_key_down(KeyCode::Up) {
+            view.pan_y -= step;
+        }
+        if is_key_down(KeyCode::Down) {
+            view.pan_y += step;
+        }
+        
+        // Panning only reaches as far as the map overflows the window
+        let scale = map_view_scale(&self.current_map, view.zoom);
+        let (_, _, area_w, area_h) = map_view_area();
+        view.pan_x = view.pan_x.clamp(0.0, (self.current_map.width as f32 - area_w / scale).max(0.0));
+        view.pan_y = view.pan_y.clamp(0.0, (self.current_map.height as f32 - area_h / scale).max(0.0));
+        
+        if is_key_pressed(KeyCode::Escape) {
+            self.state = GameState::Playing;
+        }
+    }
+    
+    /// Name of a stored map
+    fn map_name(&self, map_type: MapType, map_id: usize) -> &str {
+        match map_type {
+            MapType::WorldMap => &self.world_map.name,
+            MapType::Town => &self.town_maps[map_id].name,
+            MapType::Dungeon => &self.dungeon_maps[map_id].name,
+            MapType::Interior => &self.interior_maps[map_id].name,
+        }
+    }
+    
+    /// Positions on the current map of NPCs and items that active quests point at
+    fn quest_markers(&self) -> Vec<(i32, i32)> {
+        let mut npc_names = Vec::new();
+        let mut item_names = Vec::new();
+        for quest in self.quests.iter().filter(|q| q.state == QuestState::Active) {
+            match &quest.objective {
+                QuestObjective::KillNpc { name, map, .. } if *map == self.current_map.name => npc_names.push(name),
+                QuestObjective::KillNpc { .. } => {}
+                QuestObjective::DeliverItem { item, to } => {
+                    if self.player.inventory.iter().any(|i| i.name == *item) {
+                        npc_names.push(to);
+                    } else {
+                        item_names.push(item);
+                    }
+                }
+            }
+        }
+        let npcs = self.npcs.iter()
+            .filter(|npc| npc_names.contains(&&npc.name))
+            .map(|npc| (npc.x, npc.y));
+        let items = self.current_map.items.iter()
+            .filter(|(_, item)| item_names.contains(&&item.name))
+            .map(|(&pos, _)| pos);
+        npcs.chain(items).collect()
+    }
+    
+    /// Return to the map the player entered this one from
+    /// Does nothing on the outermost map
+    fn return_to_previous_map(&mut self) {
+        let Some(prev_loc) = self.location_stack.pop() else {
+            return;
+        };
+        
+        // Keep this map's NPCs as they are for the next visit
+        self.store_npcs();
+        
+        // Move the previous map back in and return the left map to its slot
+        let map = std::mem::take(self.map_slot(prev_loc.map_type, prev_loc.map_id));
+        self.park_current_map(map);
+        self.current_map_id = prev_loc.map_id;
+        self.player.x = prev_loc.x;
+        self.player.y = prev_loc.y;
+        
+        // Load the previous map's NPCs
+        self.restore_npcs();
+        self.post_town_guards();
+        self.update_fov();
+        self.add_message(format!("Returned to {}", self.current_map.name));
+    }
+    
+    /// Storage slot holding the given map while it is not current
+    fn map_slot(&mut self, map_type: MapType, map_id: usize) -> &mut GameMap {
+        match map_type {
+            MapType::WorldMap => &mut self.world_map,
+            MapType::Town => &mut self.town_maps[map_id],
+            MapType::Dungeon => &mut self.dungeon_maps[map_id],
+            MapType::Interior => &mut self.interior_maps[map_id],
+        }
+    }
+    
+    /// Make `map` current and move the map being left back into its storage slot
+    fn park_current_map(&mut self, map: GameMap) {
+        self.stats.maps_visited.insert(map.name.clone());
+        let left = std::mem::replace(&mut self.current_map, map);
+        let map_type = left.map_type;
+        *self.map_slot(map_type, self.current_map_id) = left;
+    }
+    
+    /// Save the current map's NPCs so kills and changes persist after leaving
+    fn store_npcs(&mut self) {
+        let key = (self.current_map.map_type, self.current_map_id);
+        self.map_npcs.insert(key, std::mem::take(&mut self.npcs));
+    }
+    
+    /// Restore the current map's NPCs as they were left
+    /// Maps visited for the first time are populated by their loader
+    fn restore_npcs(&mut self) {
+        let key = (self.current_map.map_type, self.current_map_id);
+        if let Some(npcs) = self.map_npcs.remove(&key) {
+            self.npcs = npcs;
+            return;
+        }
+        match key.0 {
+            MapType::WorldMap => self.load_world_npcs(),
+            MapType::Town => self.load_town_npcs(key.1),
+            MapType::Dungeon => self.load_dungeon_npcs(),
+            MapType::Interior => self.npcs = Vec::new(),  // Cellars start out empty
+        }
+    }
+    
+    /// Load world map NPCs
+    fn load_world_npcs(&mut self) {
+        self.npcs = vec![
+            Npc {
+                name: "Traveling Merchant".to_string(),
+                char: "♥",
+                x: 35,
+                y: 20,
+                hp: 50,
+                max_hp: 50,
+                hostile: false,
+                agility: 5,
+                damage: 10,
+                xp: 25,
+                dialogue: vec![
+                    DialogueNode {
+                        text: "Howdy, stranger! What brings you to these parts?".to_string(),
+                        options: vec![
+                            DialogueOption::new("I'm here for adventure!", Some(1))
+                                .low_int("Me want ad... adven... fight things!")
+                                .low_int_route(3),
+                            DialogueOption::new("Just passing by.", Some(2))
+                                .low_int("Me walk. Walk walk walk."),
+                            DialogueOption::new("None of your business.", None)
+                                .low_int("Go 'way. Mine."),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "Adventure, eh? Well, watch out for demonic cows!".to_string(),
+                        options: vec![
+                            DialogueOption::new("Thanks for the tip!", None),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "Safe travels, partner!".to_string(),
+                        options: vec![
+                            DialogueOption::new("See ya!", None)
+                                .low_int("Bye bye!"),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "Fight things? Buddy, you just tried to shake hands with a cactus. Stay on the road, alright?".to_string(),
+                        options: vec![
+                            DialogueOption::new("Cactus was friendly.", None),
+                        ],
+                    },
+                ],
+                met: false,
+                patrol: Vec::new(),
+                patrol_step: 0,
+                home: (0, 0),
+                // Trades by day and makes camp somewhere out of sight at night
+                schedule: vec![ScheduleEntry { start: DUSK, end: DAWN, anchor: None, asleep: false }],
+                facing: (0, 1),
+                alert_state: AlertState::Unaware,
+                aggro_radius: AGGRO_RADIUS,
+                leash: AGGRO_RADIUS,
+                last_seen: None,
+                inventory: vec![Item {
+                    name: "Nuka-Cola".to_string(),
+                    char: "!",
+                    item_type: ItemType::Consumable { heal: 10, hunger_restore: 0, thirst_restore: 25 },
+                    durability: None,
+                    quantity: 1,
+                }],
+                gold_carried: 30,
+                drops: Vec::new(),
+                portrait: MERCHANT_PORTRAIT,
+                faction: Some(Faction::Wanderers),
+            },
+        ];
+        
+        // Generated worlds may put water or mountains where the merchant stands
+        for npc in &mut self.npcs {
+            (npc.x, npc.y) = self.current_map.nearest_walkable(npc.x, npc.y);
+            npc.home = (npc.x, npc.y);
+        }
+        self.spawn_world_enemies();
+    }
+    
+    /// Scatter enemies over the world map, away from the player, each pacing between
+    /// where it starts and a spot nearby
+    /// They are out in the open, so the player can see them coming and steer clear
+    fn spawn_world_enemies(&mut self) {
+        let rng = rand::RandGenerator::new();
+        rng.srand(map_seed(self.seed, MapType::WorldMap, 0).wrapping_add(3));
+        let map = &self.current_map;
+        let open = |x: i32, y: i32| {
+            map.is_walkable(x, y) && !matches!(map.tiles[y as usize][x as usize], TileType::Town | TileType::Dungeon)
+        };
+        let mut placed = 0;
+        for _ in 0..WORLD_ENEMIES * 50 {
+            if placed == WORLD_ENEMIES {
+                break;
+            }
+            let (x, y) = (rng.gen_range(0, map.width), rng.gen_range(0, map.height));
+            if !open(x, y)
+                || (x - self.player.x).abs().max((y - self.player.y).abs()) < WORLD_ENEMY_MIN_DISTANCE
+                || self.npcs.iter().any(|npc| (npc.x, npc.y) == (x, y))
+            {
+                continue;
+            }
+            let template = pick_enemy(encounter_table(map.tiles[y as usize][x as usize]), |total| rng.gen_range(0, total));
+            let mut npc = Npc::spawn_enemy(template, x, y);
+            npc.aggro_radius = WORLD_AGGRO_RADIUS;
+            npc.leash = WORLD_LEASH;
+            let (px, py) = (x + rng.gen_range(-4, 5), y + rng.gen_range(-4, 5));
+            npc.patrol = if open(px, py) { vec![(x, y), (px, py)] } else { vec![(x, y)] };
+            self.difficulty.scale_enemy(&mut npc);
+            self.npcs.push(npc);
+            placed += 1;
+        }
+    }
+    
+    /// Load town NPCs
+    fn load_town_npcs(&mut self, town_id: usize) {
+        let (town_x, town_y) = self.town_world_positions[town_id];
+        self.npcs = vec![
+            Npc {
+                name: "Townfolk".to_string(),
+                char: "☺",
+                x: 15,
+                y: 15,
+                hp: 50,
+                max_hp: 50,
+                hostile: false,
+                agility: 5,
+                damage: 10,
+                xp: 25,
+                dialogue: vec![
+                    DialogueNode {
+                        text: "Welcome to our town! Are you lost or just weird?".to_string(),
+                        options: vec![
+                            DialogueOption::new("A bit of both, honestly.", Some(1))
+                                .low_int("Me lost. Where me?")
+                                .low_int_route(3),
+                            DialogueOption::new("I'm looking for work.", Some(2))
+                                .low_int("Me want job. Job good."),
+                            DialogueOption::new("Anything dangerous going on?", Some(4)),
+                            DialogueOption::new("[Medic] That cough sounds bad. Let me take a look.", Some(5))
+                                .requires_class(PlayerClass::Medic),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "That's the spirit! You'll fit right in.".to_string(),
+                        options: vec![
+                            DialogueOption::new("Thanks?", None),
                         ],
                     },
                     DialogueNode {
                         text: "Try the saloon. Or the cemetery. Both are lively.".to_string(),
                         options: vec![
-                            DialogueOption { text: "I'll check them out.".to_string(), next_node: None },
+                            DialogueOption::new("I'll check them out.", None)
+                                .low_int("Sa-loon. Okay!"),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "You're in town, sweetie. It's the place with all the buildings. Try not to lick the well again.".to_string(),
+                        options: vec![
+                            DialogueOption::new("Well tasted good.", None),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "Guards from Dungeon #1 keep raiding our supply runs. Clear them out and we'll make it worth your while.".to_string(),
+                        options: vec![
+                            DialogueOption::new("Consider it done.", None)
+                                .with_action(DialogueAction::StartQuest(QUEST_CLEAR_GUARDS)),
+                            DialogueOption::new("Not my problem.", None),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "It's nothing, just the dust... Well, if you've got something for it, I won't say no.".to_string(),
+                        options: vec![
+                            DialogueOption::new("Here, this Stimpak will clear it up.", Some(6))
+                                .requires_item("Stimpak"),
+                            DialogueOption::new("Drink clean water and rest. That's all I can do.", None),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "I can breathe again! Take these caps, doc. I insist.".to_string(),
+                        options: vec![
+                            DialogueOption::new("Stay healthy.", None)
+                                .with_action(DialogueAction::Reward { caps: 40 }),
+                        ],
+                    },
+                ],
+                met: false,
+                patrol: Vec::new(),
+                patrol_step: 0,
+                home: (0, 0),
+                schedule: Vec::new(),
+                facing: (0, 1),
+                alert_state: AlertState::Unaware,
+                aggro_radius: AGGRO_RADIUS,
+                leash: AGGRO_RADIUS,
+                last_seen: None,
+                inventory: vec![Item {
+                    name: "Canned Food".to_string(),
+                    char: "%",
+                    item_type: ItemType::Consumable { heal: 5, hunger_restore: 40, thirst_restore: 0 },
+                    durability: None,
+                    quantity: 1,
+                }],
+                gold_carried: 12,
+                drops: Vec::new(),
+                portrait: TOWNFOLK_PORTRAIT,
+                faction: Some(Faction::Townsfolk),
+            },
+            Npc {
+                name: "Merchant".to_string(),
+                char: "♥",
+                x: 24,
+                y: 18,
+                hp: 50,
+                max_hp: 50,
+                hostile: false,
+                agility: 5,
+                damage: 10,
+                xp: 25,
+                dialogue: vec![
+                    DialogueNode {
+                        text: "Supplies for the road! Heading underground? You'll want a light.".to_string(),
+                        options: vec![
+                            DialogueOption::new(&format!("Buy a torch. [{} caps]", TORCH_PRICE), Some(1))
+                                .with_action(DialogueAction::Buy(Item::torch, TORCH_PRICE)),
+                            DialogueOption::new(&format!("Buy a grenade. [{} caps]", GRENADE_PRICE), Some(1))
+                                .with_action(DialogueAction::Buy(Item::grenade, GRENADE_PRICE)),
+                            DialogueOption::new("Just browsing.", None),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "Anything else?".to_string(),
+                        options: vec![
+                            DialogueOption::new("Another torch.", Some(1))
+                                .with_action(DialogueAction::Buy(Item::torch, TORCH_PRICE)),
+                            DialogueOption::new("A grenade.", Some(1))
+                                .with_action(DialogueAction::Buy(Item::grenade, GRENADE_PRICE)),
+                            DialogueOption::new("That's all.", None),
                         ],
                     },
                 ],
+                met: false,
+                patrol: Vec::new(),
+                patrol_step: 0,
+                home: (0, 0),
+                schedule: Vec::new(),
+                facing: (0, 1),
+                alert_state: AlertState::Unaware,
+                aggro_radius: AGGRO_RADIUS,
+                leash: AGGRO_RADIUS,
+                last_seen: None,
+                inventory: vec![Item::torch()],
+                gold_carried: 40,
+                drops: Vec::new(),
+                portrait: MERCHANT_PORTRAIT,
+                faction: Some(Faction::Townsfolk),
             },
-            NPC {
+            Npc {
                 name: "Blacksmith".to_string(),
                 char: "♦",
                 x: 10,
@@ -658,300 +6848,1992 @@ impl Game {
                 hp: 80,
                 max_hp: 80,
                 hostile: false,
+                agility: 4,
+                damage: 10,
+                xp: 40,
                 dialogue: vec![
                     DialogueNode {
                         text: "Need repairs? Or just here to chat?".to_string(),
                         options: vec![
-                            DialogueOption { text: "My gear's busted.".to_string(), next_node: Some(1) },
-                            DialogueOption { text: "Just lonely.".to_string(), next_node: Some(2) },
+                            DialogueOption::new("My gear's busted.", Some(1)),
+                            DialogueOption::new("Just lonely.", Some(2)),
+                            DialogueOption::new("Need a hand with anything?", Some(3)),
+                            DialogueOption::new("Give the Blacksmith the broken sword.", Some(6))
+                                .requires_item("Broken Sword"),
                         ],
                     },
                     DialogueNode {
                         text: "That'll be 50 meat. Up front.".to_string(),
                         options: vec![
-                            DialogueOption { text: "Here you go.".to_string(), next_node: None },
+                            DialogueOption::new("Here you go.", None)
+                                .with_action(DialogueAction::RepairItem { cost: 50 }),
+                            DialogueOption::new("[Speech] Fifty? I heard you were the fair one.", Some(4))
+                                .speech_check(50, 5),
                         ],
                     },
                     DialogueNode {
                         text: "Me too, friend. Me too.".to_string(),
                         options: vec![
-                            DialogueOption { text: "...".to_string(), next_node: None },
+                            DialogueOption::new("...", None),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "My supply crate never made it to the forge. Probably still sitting by the well. Bring it here?".to_string(),
+                        options: vec![
+                            DialogueOption::new("I'll fetch it.", None)
+                                .with_action(DialogueAction::StartQuest(QUEST_BLACKSMITH_SUPPLIES)),
+                            DialogueOption::new("Fetch it yourself.", None),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "Flattery doesn't fix armor. Fifty.".to_string(),
+                        options: vec![
+                            DialogueOption::new("Fine.", None)
+                                .with_action(DialogueAction::RepairItem { cost: 50 }),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "Heh. Fair, huh? Twenty-five, and you didn't hear it from me.".to_string(),
+                        options: vec![
+                            DialogueOption::new("Deal.", None)
+                                .with_action(DialogueAction::RepairItem { cost: 25 }),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "Well, I'll be. That's the blade I forged for the last fool who went underground. Good steel never goes to waste here.".to_string(),
+                        options: vec![
+                            DialogueOption::new("Glad it found its way home.", None),
+                        ],
+                    },
+                ],
+                met: false,
+                patrol: Vec::new(),
+                patrol_step: 0,
+                home: (0, 0),
+                schedule: Vec::new(),
+                facing: (0, 1),
+                alert_state: AlertState::Unaware,
+                aggro_radius: AGGRO_RADIUS,
+                leash: AGGRO_RADIUS,
+                last_seen: None,
+                inventory: Vec::new(),
+                gold_carried: 25,
+                drops: Vec::new(),
+                portrait: BLACKSMITH_PORTRAIT,
+                faction: Some(Faction::Townsfolk),
+            },
+            Npc {
+                name: "Cartographer".to_string(),
+                char: "☼",
+                x: 33,
+                y: 5,
+                hp: 40,
+                max_hp: 40,
+                hostile: false,
+                agility: 5,
+                damage: 5,
+                xp: 20,
+                dialogue: vec![
+                    DialogueNode {
+                        text: "Maps! Hand-drawn, mostly accurate. Forty caps and you'll know the land for twenty miles around this town.".to_string(),
+                        options: vec![
+                            DialogueOption::new("I'll take one.", None)
+                                .with_action(DialogueAction::RevealRegion { center_x: town_x, center_y: town_y, radius: 20, cost: 40 }),
+                            DialogueOption::new("Mostly accurate?", Some(1))
+                                .low_int("Map have pictures?"),
+                            DialogueOption::new("Not today.", None),
+                        ],
+                    },
+                    DialogueNode {
+                        text: "The mountains don't move much. Everything else, no promises.".to_string(),
+                        options: vec![
+                            DialogueOption::new("Good enough. I'll take one.", None)
+                                .with_action(DialogueAction::RevealRegion { center_x: town_x, center_y: town_y, radius: 20, cost: 40 }),
+                            DialogueOption::new("I'll find my own way.", None),
                         ],
                     },
                 ],
+                met: false,
+                patrol: Vec::new(),
+                patrol_step: 0,
+                home: (0, 0),
+                schedule: Vec::new(),
+                facing: (0, 1),
+                alert_state: AlertState::Unaware,
+                aggro_radius: AGGRO_RADIUS,
+                leash: AGGRO_RADIUS,
+                last_seen: None,
+                inventory: Vec::new(),
+                gold_carried: 15,
+                drops: Vec::new(),
+                portrait: CARTOGRAPHER_PORTRAIT,
+                faction: Some(Faction::Townsfolk),
+            },
+        ];
+        
+        // The town layout says where everyone stands, and that is where they wander around;
+        // a spot named `<name> night` is where they sleep, keeping to their post by day
+        for npc in &mut self.npcs {
+            if let Some(&(_, pos)) = self.current_map.markers.iter().find(|(name, _)| *name == npc.name) {
+                (npc.x, npc.y) = pos;
+            }
+            npc.home = (npc.x, npc.y);
+            let bed = format!("{} night", npc.name);
+            if let Some(&(_, pos)) = self.current_map.markers.iter().find(|(name, _)| *name == bed) {
+                npc.schedule = vec![
+                    ScheduleEntry { start: DAWN, end: DUSK, anchor: Some(npc.home), asleep: false },
+                    ScheduleEntry { start: DUSK, end: DAWN, anchor: Some(pos), asleep: true },
+                ];
+            }
+        }
+        
+        // Enemies placed in the map editor
+        for mut npc in std::mem::take(&mut self.current_map.spawns) {
+            self.difficulty.scale_enemy(&mut npc);
+            self.npcs.push(npc);
+        }
+    }
+    
+    /// Load dungeon NPCs (enemies)
+    /// Generated dungeons bring their own; other layouts get a guard walking
+    /// a loop through the corridor at y=5, and a beast
+    fn load_dungeon_npcs(&mut self) {
+        self.npcs = std::mem::take(&mut self.current_map.spawns);
+        // Layouts that place no enemies of their own (caves, hand-drawn maps) get a guard
+        // pacing the far end of the way in, with a beast lurking halfway along it
+        if self.npcs.is_empty() {
+            let map = &self.current_map;
+            let far = map.farthest_free_tile().unwrap_or(map.entry_point);
+            let route = map.find_path(map.entry_point, far).unwrap_or_else(|| vec![far]);
+            let mut guard = Npc::spawn_enemy(&DUNGEON_GUARD, far.0, far.1);
+            guard.patrol = vec![far, route[route.len() * 3 / 4]];
+            self.npcs = vec![guard];
+            if route.len() >= 4 {
+                let (x, y) = route[route.len() / 2];
+                self.npcs.push(Npc::spawn_enemy(&MUTANT_BEAST, x, y));
+            }
+        }
+        
+        // Spots drawn in a map file may sit on walls
+        for npc in &mut self.npcs {
+            (npc.x, npc.y) = self.current_map.nearest_walkable(npc.x, npc.y);
+            for waypoint in &mut npc.patrol {
+                *waypoint = self.current_map.nearest_walkable(waypoint.0, waypoint.1);
+            }
+            self.difficulty.scale_enemy(npc);
+        }
+    }
+    
+    /// Recompute the player's field of view on the current map
+    /// Sight radius grows with perception; everything seen is remembered as explored
+    fn update_fov(&mut self) {
+        let radius = 3 + self.player.stats.perception - self.sight_penalty();
+        self.fov = self.current_map.field_of_view(self.player.x, self.player.y, radius);
+        let map = &mut self.current_map;
+        for &(x, y) in &self.fov {
+            if x >= 0 && x < map.width && y >= 0 && y < map.height {
+                map.explored[y as usize][x as usize] = true;
+            }
+        }
+    }
+    
+    /// Whether a tile is currently seen: dungeons only show what is in view,
+    /// and the world map only what the player has seen or been shown on a map
+    fn is_visible(&self, x: i32, y: i32) -> bool {
+        match self.current_map.map_type {
+            MapType::Dungeon => self.fov.contains(&(x, y)),
+            MapType::WorldMap => self.is_explored(x, y),
+            _ => true,
+        }
+    }
+    
+    /// Mark every world map tile within `radius` of (x, y) as explored, wherever the player is
+    fn reveal_region(&mut self, x: i32, y: i32, radius: i32) {
+        let world = if self.current_map.map_type == MapType::WorldMap {
+            &mut self.current_map
+        } else {
+            &mut self.world_map
+        };
+        for ty in (y - radius).max(0)..(y + radius + 1).min(world.height) {
+            for tx in (x - radius).max(0)..(x + radius + 1).min(world.width) {
+                if (tx - x).pow(2) + (ty - y).pow(2) <= radius * radius {
+                    world.explored[ty as usize][tx as usize] = true;
+                }
+            }
+        }
+    }
+    
+    /// Brightness of a visible tile, from 1.0 (fully lit) down to 0.0
+    /// Tiles fade past twice the player's perception (Manhattan distance) and are
+    /// black beyond four times it; dungeons also fade beyond the light radius.
+    /// However bad the weather, the player's own tile and its neighbours stay lit
+    fn light_level(&self, x: i32, y: i32) -> f32 {
+        let vision = (self.player.stats.perception * 2 - self.sight_penalty()).max(1);
+        let dist = (x - self.player.x).abs() + (y - self.player.y).abs();
+        let sight = if dist > vision * 2 {
+            0.0
+        } else {
+            (1.0 - (dist - vision) as f32 / vision as f32).clamp(0.0, 1.0)
+        };
+        if self.current_map.map_type != MapType::Dungeon {
+            return sight;
+        }
+        let dx = (x - self.player.x) as f32;
+        let dy = (y - self.player.y) as f32;
+        let beyond = (dx * dx + dy * dy).sqrt() - self.player.light_radius() as f32;
+        sight.min((1.0 - beyond.max(0.0) * 0.3).max(0.1))
+    }
+    
+    /// Advance hunger and thirst by one turn
+    /// An empty stomach or canteen costs 1 HP every STARVATION_INTERVAL turns
+    fn update_needs(&mut self) {
+        self.hunger_timer += 1;
+        if self.hunger_timer >= HUNGER_INTERVAL {
+            self.hunger_timer -= HUNGER_INTERVAL;
+            self.player.hunger = (self.player.hunger - 1).max(0);
+            if self.player.hunger == 0 {
+                self.add_message("You are starving!".to_string());
+            }
+        }
+        self.thirst_timer += 1;
+        if self.thirst_timer >= THIRST_INTERVAL {
+            self.thirst_timer -= THIRST_INTERVAL;
+            self.player.thirst = (self.player.thirst - 1).max(0);
+            if self.player.thirst == 0 {
+                self.add_message("You are dying of thirst!".to_string());
+            }
+        }
+        
+        if self.player.hunger > 0 && self.player.thirst > 0 {
+            self.starvation_timer = 0;
+            return;
+        }
+        self.starvation_timer += 1;
+        if self.starvation_timer >= STARVATION_INTERVAL {
+            self.starvation_timer -= STARVATION_INTERVAL;
+            self.damage_player(1);
+        }
+    }
+    
+    /// Passive regeneration for one turn: 1 HP every (11 - endurance) turns, at least every turn,
+    /// plus the easier difficulties' 1 HP every `regen_interval` turns
+    fn update_regen(&mut self) {
+        if let Some(interval) = self.difficulty.regen_interval()
+            && self.turns.is_multiple_of(interval as u64)
+        {
+            self.player.hp = (self.player.hp + 1).min(self.player.max_hp);
+        }
+        if self.player.hp >= self.player.max_hp {
+            self.player.regen_accumulator = 0;
+            return;
+        }
+        self.player.regen_accumulator += 1;
+        if self.player.regen_accumulator >= (11 - self.player.stats.endurance).max(1) as u64 {
+            self.player.regen_accumulator = 0;
+            self.player.hp += 1;
+            self.regen_popup = 0.0;
+        }
+    }
+    
+    /// Drink from water next to the player
+    fn drink(&mut self) {
+        let near_water = self.adjacent_tiles().into_iter()
+            .any(|(x, y)| self.current_map.tiles[y as usize][x as usize] == TileType::Water);
+        if !near_water {
+            self.add_message("There is no water nearby".to_string());
+            return;
+        }
+        self.player.thirst = (self.player.thirst + DRINK_RESTORE).min(MAX_NEED);
+        self.add_message(format!("You drink from the water (+{} water)", DRINK_RESTORE));
+    }
+    
+    /// Whether the current map is out under the sky, where weather applies
+    fn is_outdoors(&self) -> bool {
+        matches!(self.current_map.map_type, MapType::WorldMap | MapType::Town)
+    }
+    
+    /// Tiles of sight lost to the weather and, on the world map, to darkness
+    fn sight_penalty(&self) -> i32 {
+        let mut penalty = 0;
+        if self.is_outdoors() && self.current_weather.is_foggy() {
+            penalty += FOG_SIGHT_PENALTY;
+        }
+        if self.current_map.map_type == MapType::WorldMap && self.is_night() {
+            penalty += NIGHT_SIGHT_PENALTY;
+        }
+        penalty
+    }
+    
+    /// Advance weather timers, rain and lightning by one frame
+    /// Every few minutes the weather changes at random
+    fn update_weather(&mut self, dt: f32) {
+        self.weather_timer += dt;
+        if self.weather_timer >= WEATHER_CHANGE_INTERVAL {
+            self.weather_timer = 0.0;
+            let next = Weather::ALL[rand::gen_range(0, Weather::ALL.len())];
+            if next != self.current_weather {
+                self.current_weather = next;
+                self.add_message(next.announcement().to_string());
+                self.update_fov();
+            }
+        }
+        
+        // Rain falls diagonally and wraps back to the top of the screen
+        if self.current_weather.is_wet() {
+            if self.rain_particles.is_empty() {
+                self.rain_particles = (0..RAIN_PARTICLES)
+                    .map(|_| (rand::gen_range(0.0, screen_width()), rand::gen_range(0.0, screen_height())))
+                    .collect();
+            }
+            for (x, y) in &mut self.rain_particles {
+                *x -= 60.0 * dt;
+                *y += 400.0 * dt;
+                if *y > screen_height() {
+                    *y -= screen_height();
+                    *x = rand::gen_range(0.0, screen_width());
+                }
+                if *x < 0.0 {
+                    *x += screen_width();
+                }
+            }
+        } else {
+            self.rain_particles.clear();
+        }
+        
+        if self.current_weather == Weather::Storm {
+            self.storm_flash += dt;
+            if self.storm_flash >= STORM_FLASH_INTERVAL {
+                self.storm_flash = 0.0;
+            }
+        }
+    }
+    
+    /// Whether it is currently night (before 04:48 or after 19:12)
+    fn is_night(&self) -> bool {
+        !(DAWN..DUSK).contains(&self.time_of_day)
+    }
+    
+    /// Overlay drawn over the world map for the time of day
+    /// Dark blue at night, orange at dawn and dusk, none during the day
+    fn daylight_tint(&self) -> Option<Color> {
+        let t = self.time_of_day;
+        if self.is_night() {
+            Some(Color::new(0.0, 0.0, 0.3, 0.5))
+        } else if !(0.3..0.7).contains(&t) {
+            Some(Color::new(1.0, 0.5, 0.1, 0.2))
+        } else {
+            None
+        }
+    }
+    
+    /// Day of the run and time of day as a 24-hour clock, e.g. "Day 2, 21:30"
+    fn clock(&self) -> String {
+        let minutes = (self.time_of_day * 24.0 * 60.0) as u32 % (24 * 60);
+        format!("Day {}, {:02}:{:02}", self.day, minutes / 60, minutes % 60)
+    }
+    
+    /// Whether the player has seen this tile before (remembered terrain)
+    fn is_explored(&self, x: i32, y: i32) -> bool {
+        self.current_map.explored[y as usize][x as usize]
+    }
+    
+    /// Update camera position to follow player
+    /// Camera keeps player near center of the viewport but never scrolls past the map edges;
+    /// maps smaller than the viewport are centered instead. It eases toward that spot
+    /// over a few frames, and jumps straight there when it is half a screen or more away
+    fn update_camera(&mut self, dt: f32) {
+        let (view_w, view_h) = viewport_tiles();
+        // The editor's cursor takes the player's place at the center
+        let (x, y) = match &self.state {
+            GameState::Editor(editor) => editor.cursor,
+            _ => (self.player.x, self.player.y),
+        };
+        let target_x = camera_axis(x, view_w, self.current_map.width) as f32;
+        let target_y = camera_axis(y, view_h, self.current_map.height) as f32;
+        if (target_x - self.camera_fx).abs() * 2.0 >= view_w as f32 || (target_y - self.camera_fy).abs() * 2.0 >= view_h as f32 {
+            (self.camera_fx, self.camera_fy) = (target_x, target_y);
+        } else {
+            let t = (CAMERA_EASE * dt).min(1.0);
+            self.camera_fx += (target_x - self.camera_fx) * t;
+            self.camera_fy += (target_y - self.camera_fy) * t;
+        }
+        self.camera_x = self.camera_fx.round() as i32;
+        self.camera_y = self.camera_fy.round() as i32;
+    }
+}
+
+// ========== Rendering System ==========
+
+/// Height of the bottom message log, which panels avoid covering
+const MESSAGE_LOG_HEIGHT: f32 = 120.0;
+
+/// Pixel size of each map tile
+const TILE_SIZE: f32 = 20.0;
+
+/// Screen position of the map viewport's top-left corner (below the status bar)
+const MAP_ORIGIN: (f32, f32) = (20.0, 40.0);
+
+/// Size of the map viewport in whole tiles, between the status bar and the message log
+/// Shared by the camera and the renderer so both agree on what is visible
+fn viewport_tiles() -> (i32, i32) {
+    let w = ((screen_width() - MAP_ORIGIN.0) / TILE_SIZE) as i32;
+    let h = ((screen_height() - MAP_ORIGIN.1 - MESSAGE_LOG_HEIGHT) / TILE_SIZE) as i32;
+    (w.max(1), h.max(1))
+}
+
+/// How quickly the camera closes the gap to its target, per second
+const CAMERA_EASE: f32 = 8.0;
+
+/// Camera offset along one axis for a viewport `view` tiles long over a map `size` tiles long
+fn camera_axis(player: i32, view: i32, size: i32) -> i32 {
+    if size <= view {
+        // Whole map fits: center it (negative offset shifts the map right/down)
+        -(view - size) / 2
+    } else {
+        (player - view / 2).clamp(0, size - view)
+    }
+}
+
+/// Size a panel as a fraction of the window, clamped to `min`..`max` pixels
+/// Never grows past the window itself, so small windows shrink the panel instead of overflowing
+fn panel_size(width_frac: f32, height_frac: f32, min: (f32, f32), max: (f32, f32)) -> (f32, f32) {
+    let w = (screen_width() * width_frac).clamp(min.0, max.0).min(screen_width() - 20.0);
+    let h = (screen_height() * height_frac).clamp(min.1, max.1).min(screen_height() - 20.0);
+    (w, h)
+}
+
+/// Break text into lines no wider than `max_width` at the given font and size
+/// Lines break on word boundaries; words wider than a whole line are split mid-word
+fn wrap_text(text: &str, font: Option<&Font>, font_size: u16, max_width: f32) -> Vec<String> {
+    let fits = |line: &str| measure_text(line, font, font_size, 1.0).width <= max_width;
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+        if fits(&candidate) {
+            line = candidate;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        // Start the word on a fresh line, hard-splitting it if it still does not fit
+        for ch in word.chars() {
+            line.push(ch);
+            if !fits(&line) && line.chars().count() > 1 {
+                line.pop();
+                lines.push(std::mem::replace(&mut line, ch.to_string()));
+            }
+        }
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Color for remembered terrain: the tile's own color at 40% brightness
+fn dimmed(color: Color) -> Color {
+    darkened(color, 0.4)
+}
+
+/// Multiply a color toward black, keeping `factor` of its brightness
+fn darkened(color: Color, factor: f32) -> Color {
+    Color::new(color.r * factor, color.g * factor, color.b * factor, color.a)
+}
+
+/// Which of two looks animated tiles show; flips 1.5 times per second
+fn animation_flip(game: &Game) -> bool {
+    ((game.animation_timer * 1.5) as u32).is_multiple_of(2)
+}
+
+/// Color the tile at (x, y) is drawn with, including water and world map town animation
+/// Natural terrain gets a slight per-tile brightness variation so large regions aren't flat blocks
+fn tile_color(game: &Game, tile: TileType, x: i32, y: i32) -> Color {
+    let flip = animation_flip(game);
+    let color = match tile {
+        TileType::Water => if flip { BLUE } else { DARKBLUE },
+        TileType::Town if game.current_map.map_type == MapType::WorldMap => if flip { ORANGE } else { GOLD },
+        _ => tile.color(),
+    };
+    match tile {
+        TileType::Grass | TileType::Forest | TileType::Water | TileType::Mountain => {
+            let factor = 0.88 + 0.24 * tile_noise(x, y);
+            Color::new((color.r * factor).min(1.0), (color.g * factor).min(1.0), (color.b * factor).min(1.0), color.a)
+        }
+        _ => color,
+    }
+}
+
+/// Stable pseudo-random value in [0, 1) for a map coordinate
+fn tile_noise(x: i32, y: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(374_761_393) ^ (y as u32).wrapping_mul(668_265_263);
+    h = (h ^ (h >> 13)).wrapping_mul(1_274_126_177);
+    h ^= h >> 16;
+    (h % 1000) as f32 / 1000.0
+}
+
+/// Draw main game interface (map, items, NPCs, player)
+fn draw_game(game: &Game) {
+    let tile_size = TILE_SIZE;         // Pixel size of each tile
+    let (start_x, start_y) = MAP_ORIGIN; // Map drawing start coordinates
+    
+    let flip = animation_flip(game);
+    let tile_char = |tile: TileType| match tile {
+        TileType::Water => if flip { "~" } else { "≈" },
+        _ => tile.as_char(),
+    };
+    
+    // Only visit tiles inside the viewport (plus one for a partly visible edge)
+    let (view_w, view_h) = viewport_tiles();
+    let first_x = game.camera_x.max(0);
+    let first_y = game.camera_y.max(0);
+    let last_x = (game.camera_x + view_w + 1).min(game.current_map.width);
+    let last_y = (game.camera_y + view_h + 1).min(game.current_map.height);
+    
+    for y in first_y..last_y {
+        let screen_y = start_y + (y - game.camera_y) as f32 * tile_size;
+        // Hidden traps are drawn as the floor they pretend to be
+        let tile_at = |x: i32| game.current_map.displayed_tile(x, y);
+        
+        // Draw tile backgrounds as runs of same-colored tiles, one rectangle per run
+        // Remembered tiles out of sight are dimmed; never-seen tiles are left black
+        let shade = |x: i32| {
+            if game.is_visible(x, y) {
+                Some(darkened(tile_color(game, tile_at(x), x, y), game.light_level(x, y)))
+            } else if game.is_explored(x, y) {
+                Some(dimmed(tile_color(game, tile_at(x), x, y)))
+            } else {
+                None
+            }
+        };
+        let mut x = first_x;
+        while x < last_x {
+            let color = shade(x);
+            let run_start = x;
+            while x < last_x && shade(x) == color {
+                x += 1;
+            }
+            if let Some(color) = color {
+                let screen_x = start_x + (run_start - game.camera_x) as f32 * tile_size;
+                draw_rectangle(screen_x, screen_y, (x - run_start) as f32 * tile_size, tile_size, color);
+            }
+        }
+        
+        // Draw each seen tile's ASCII character on top of the backgrounds
+        for x in first_x..last_x {
+            let color = if game.is_visible(x, y) {
+                darkened(WHITE, game.light_level(x, y))
+            } else if game.is_explored(x, y) {
+                dimmed(WHITE)
+            } else {
+                continue;
+            };
+            let screen_x = start_x + (x - game.camera_x) as f32 * tile_size;
+            draw_text_ex(
+                tile_char(tile_at(x)),
+                screen_x + 5.0,
+                screen_y + 15.0,
+                TextParams {
+                    font: None,
+                    font_size: 20,
+                    color,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+    
+    // Draw items on map
+    for ((x, y), item) in game.current_map.items.iter().filter(|((x, y), _)| game.is_visible(*x, *y) && game.light_level(*x, *y) > 0.0) {
+        // Calculate item's screen position
+        let screen_x = start_x + (*x - game.camera_x) as f32 * tile_size;
+        let screen_y = start_y + (*y - game.camera_y) as f32 * tile_size;
+        
+        // Under an NPC the item shrinks into the tile corner so both glyphs stay readable
+        let (offset, font_size) = if game.tile_occupant(*x, *y).is_some() {
+            ((13.0, 19.0), 10)
+        } else {
+            ((5.0, 15.0), 20)
+        };
+        
+        // Draw item character in yellow
+        draw_text_ex(
+            item.char,
+            screen_x + offset.0,
+            screen_y + offset.1,
+            TextParams {
+                font: None,
+                font_size,
+                color: YELLOW,
+                ..Default::default()
             },
-        ];
+        );
+    }
+    
+    // Draw containers in sight, dimmed once emptied
+    for ((x, y), container) in game.current_map.containers.iter().filter(|((x, y), _)| game.is_visible(*x, *y) && game.light_level(*x, *y) > 0.0) {
+        let screen_x = start_x + (*x - game.camera_x) as f32 * tile_size;
+        let screen_y = start_y + (*y - game.camera_y) as f32 * tile_size;
+        draw_text_ex(
+            "☐",
+            screen_x + 5.0,
+            screen_y + 15.0,
+            TextParams {
+                font: None,
+                font_size: 20,
+                color: if container.contents.is_empty() { dimmed(ORANGE) } else { ORANGE },
+                ..Default::default()
+            },
+        );
+    }
+    
+    // Draw all NPCs in sight
+    for npc in game.npcs.iter().filter(|npc| {
+        game.is_visible(npc.x, npc.y) && game.light_level(npc.x, npc.y) > 0.0 && !npc.is_away(game.time_of_day)
+    }) {
+        // Calculate NPC's screen position
+        let screen_x = start_x + (npc.x - game.camera_x) as f32 * tile_size;
+        let screen_y = start_y + (npc.y - game.camera_y) as f32 * tile_size;
+        
+        // Set color based on hostility: red for enemies, green for friendly
+        let color = if npc.hostile { RED } else { GREEN };
+        
+        // Draw NPC character
+        draw_text_ex(
+            npc.char,
+            screen_x + 5.0,
+            screen_y + 15.0,
+            TextParams {
+                font: None,
+                font_size: 20,
+                color,
+                ..Default::default()
+            },
+        );
+        draw_facing_mark(screen_x, screen_y, tile_size, npc.facing, color);
+        
+        // Enemies on the hunt wear a "!" overhead
+        if npc.hostile && npc.alert_state == AlertState::Alerted {
+            draw_text_ex("!", screen_x + 7.0, screen_y - 2.0, TextParams {
+                font: None,
+                font_size: 16,
+                color: YELLOW,
+                ..Default::default()
+            });
+        }
+    }
+    
+    // Draw player character (represented by @ symbol)
+    let player_screen_x = start_x + (game.player.x - game.camera_x) as f32 * tile_size;
+    let player_screen_y = start_y + (game.player.y - game.camera_y) as f32 * tile_size;
+    draw_text_ex(
+        "@",
+        player_screen_x + 5.0,
+        player_screen_y + 15.0,
+        TextParams {
+            font: None,
+            font_size: 20,
+            color: SKYBLUE,
+            ..Default::default()
+        },
+    );
+    draw_facing_mark(player_screen_x, player_screen_y, tile_size, game.player.facing, SKYBLUE);
+    
+    // Faint +1 drifting up from the player after passive regeneration
+    if game.regen_popup < REGEN_POPUP_DURATION {
+        let progress = game.regen_popup / REGEN_POPUP_DURATION;
+        draw_text_ex("+1", player_screen_x + 8.0, player_screen_y - 2.0 - progress * 12.0, TextParams {
+            font: None,
+            font_size: 14,
+            color: Color::new(0.0, 0.9, 0.0, 0.7 * (1.0 - progress)),
+            ..Default::default()
+        });
+    }
+    
+    // Tint the world map for the time of day, and towns half as strongly; underground ignores the cycle
+    if let Some(tint) = game.daylight_tint() {
+        match game.current_map.map_type {
+            MapType::WorldMap => draw_rectangle(0.0, 0.0, screen_width(), screen_height(), tint),
+            MapType::Town => draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color { a: tint.a * 0.5, ..tint }),
+            _ => {}
+        }
+    }
+    
+    // Name the seen town and dungeon entrances on screen so it's clear where each leads
+    if game.current_map.map_type == MapType::WorldMap {
+        for (&(x, y), &(map_type, map_id)) in &game.current_map.entrances {
+            let in_view = x >= first_x && x < last_x && y >= first_y && y < last_y;
+            if !in_view || !(game.is_visible(x, y) || game.is_explored(x, y)) {
+                continue;
+            }
+            if !matches!(game.current_map.tiles[y as usize][x as usize], TileType::Town | TileType::Dungeon) {
+                continue;
+            }
+            let label = game.map_name(map_type, map_id);
+            let width = measure_text(label, None, 14, 1.0).width;
+            let screen_x = start_x + (x - game.camera_x) as f32 * tile_size + (tile_size - width) / 2.0;
+            let screen_y = start_y + (y - game.camera_y) as f32 * tile_size;
+            draw_text_ex(label, screen_x, screen_y - 2.0, TextParams {
+                font: None,
+                font_size: 14,
+                color: if map_type == MapType::Town { WHITE } else { ORANGE },
+                ..Default::default()
+            });
+        }
+    }
+    
+    if game.is_outdoors() {
+        draw_weather(game);
+    }
+}
+
+/// Draw a small arrowhead on the edge of the tile at (x, y) on screen, pointing where a character faces
+/// Any length of `facing` works; only the signs of its parts matter
+fn draw_facing_mark(x: f32, y: f32, tile_size: f32, facing: (i32, i32), color: Color) {
+    let (dx, dy) = (facing.0.signum() as f32, facing.1.signum() as f32);
+    if (dx, dy) == (0.0, 0.0) {
+        return;
+    }
+    let len = (dx * dx + dy * dy).sqrt();
+    let (dx, dy) = (dx / len, dy / len);
+    let center = vec2(x + tile_size / 2.0, y + tile_size / 2.0);
+    let tip = center + vec2(dx, dy) * (tile_size / 2.0);
+    let base = center + vec2(dx, dy) * (tile_size / 2.0 - 4.0);
+    let side = vec2(-dy, dx) * 3.0;
+    draw_triangle(tip, base + side, base - side, color);
+}
+
+/// Draw weather effects over an outdoor map: fog haze, rain and lightning
+fn draw_weather(game: &Game) {
+    if game.current_weather.is_foggy() {
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.6, 0.6, 0.6, 0.35));
+    }
+    for &(x, y) in &game.rain_particles {
+        draw_text_ex(".", x, y, TextParams {
+            font: None,
+            font_size: 20,
+            color: Color::new(0.6, 0.7, 1.0, 0.8),
+            ..Default::default()
+        });
+    }
+    if game.current_weather == Weather::Storm && game.storm_flash < STORM_FLASH_DURATION {
+        let alpha = 0.6 * (1.0 - game.storm_flash / STORM_FLASH_DURATION);
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(1.0, 1.0, 1.0, alpha));
+    }
+}
+
+/// Draw user interface (status bar, message log, control hints)
+fn draw_ui(game: &Game) {
+    // === Draw top status bar ===
+    // Black background
+    draw_rectangle(0.0, 0.0, screen_width(), 30.0, BLACK);
+    
+    // Display player status info and current map
+    draw_text_ex(
+        &format!("HP: {}/{} | Lv {} ({}/{} XP) | Pos: ({},{}) | Items: {} | Gold: {} | Map: {} | {} {} | Turn {}", 
+                 game.player.hp, game.player.max_hp,
+                 game.player.level, game.player.xp, game.player.xp_to_next_level(),
+                 game.player.x, game.player.y,
+                 game.player.inventory.len(),
+                 game.player.gold,
+                 game.current_map.name,
+                 game.clock(),
+                 game.current_weather.name(),
+                 game.turns),
+        10.0, 20.0,
+        TextParams {
+            font: None,
+            font_size: 20,
+            color: GREEN,
+            ..Default::default()
+        }
+    );
+    
+    // Hunger and thirst bars at the right end of the status bar
+    let bars_x = screen_width() - 150.0;
+    draw_rectangle(bars_x - 10.0, 0.0, 160.0, 30.0, BLACK);
+    for (i, (label, value, color)) in [("H", game.player.hunger, ORANGE), ("T", game.player.thirst, SKYBLUE)].into_iter().enumerate() {
+        let x = bars_x + i as f32 * 75.0;
+        draw_text_ex(label, x, 20.0, TextParams {
+            font: None,
+            font_size: 18,
+            color,
+            ..Default::default()
+        });
+        draw_rectangle(x + 14.0, 11.0, 50.0, 8.0, DARKGRAY);
+        draw_rectangle(x + 14.0, 11.0, 50.0 * value as f32 / MAX_NEED as f32, 8.0, color);
+    }
+    
+    // Detection meter under the status bar while sneaking or being watched
+    if game.player.is_sneaking || game.player.detection_level > 0.0 {
+        let level = game.player.detection_level;
+        let color = if level >= 1.0 { RED } else if level >= 0.5 { ORANGE } else { YELLOW };
+        let label = if game.player.is_sneaking { "Sneaking" } else { "Seen" };
+        draw_rectangle(bars_x - 10.0, 30.0, 160.0, 22.0, BLACK);
+        draw_text_ex(label, bars_x, 46.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: LIGHTGRAY,
+            ..Default::default()
+        });
+        draw_rectangle(bars_x + 70.0, 37.0, 70.0, 8.0, DARKGRAY);
+        draw_rectangle(bars_x + 70.0, 37.0, 70.0 * level, 8.0, color);
+    }
+    
+    // === Draw bottom message log ===
+    let log_y = screen_height() - MESSAGE_LOG_HEIGHT;
+    // Semi-transparent black background
+    draw_rectangle(0.0, log_y, screen_width(), MESSAGE_LOG_HEIGHT, Color::new(0.0, 0.0, 0.0, 0.8));
+    
+    // Display the most recent messages, wrapped to the window width
+    // Only the last 5 lines fit above the control hints
+    let lines: Vec<(String, Color)> = game.messages.iter()
+        .flat_map(|(msg, color)| wrap_text(msg, None, 18, screen_width() - 20.0).into_iter().map(move |line| (line, *color)))
+        .collect();
+    for (i, (line, color)) in lines[lines.len().saturating_sub(5)..].iter().enumerate() {
+        draw_text_ex(
+            line, 
+            10.0, 
+            log_y + 20.0 + i as f32 * 20.0,
+            TextParams {
+                font: None,
+                font_size: 18,
+                color: *color,
+                ..Default::default()
+            }
+        );
+    }
+    
+    // === Draw achievement popup ===
+    // A banner under the status bar that fades out over its last second
+    if let Some((name, remaining)) = &game.achievement_popup {
+        let alpha = remaining.min(1.0);
+        let text = format!("Achievement unlocked: {}", name);
+        let width = measure_text(&text, None, 20, 1.0).width + 20.0;
+        let x = (screen_width() - width) / 2.0;
+        draw_rectangle(x, 40.0, width, 30.0, Color::new(0.0, 0.0, 0.0, 0.8 * alpha));
+        draw_rectangle_lines(x, 40.0, width, 30.0, 2.0, Color { a: alpha, ..GOLD });
+        draw_text_ex(&text, x + 10.0, 61.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: Color { a: alpha, ..GOLD },
+            ..Default::default()
+        });
+    }
+    
+    // === Draw control hints ===
+    let controls = if game.current_map.map_type == MapType::WorldMap {
+        "WASD/Arrow: Move | Space: Enter/Open | T: Travel | Shift+M: Map | Shift: Sneak | G: Pick up | F: Search | .: Wait | K: Attack | R: Drink | I: Inventory | X: Close door | C: Character | J: Journal | B: Bestiary | Shift+S: Stats | L: Log"
+    } else {
+        "WASD/Arrow: Move | Space: Enter/Open | ESC: Leave | Shift: Sneak | G: Pick up | F: Search | .: Wait | K: Attack | R: Drink | I: Inventory | X: Close door | C: Character | J: Journal | B: Bestiary | Shift+S: Stats | L: Log"
+    };
+    draw_text_ex(
+        controls, 
+        10.0, 
+        screen_height() - 10.0,
+        TextParams {
+            font: None,
+            font_size: 16,
+            color: DARKGRAY,
+            ..Default::default()
+        }
+    );
+}
+
+/// Draw the workbench: the inventory with the picked ingredient marked, and the known recipes
+fn draw_crafting(game: &Game, cursor: usize, first: Option<usize>) {
+    let (panel_w, panel_h) = panel_size(0.6, 0.7, (360.0, 320.0), (700.0, 620.0));
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, BEIGE);
+    draw_text_ex("WORKBENCH", panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: BEIGE,
+        ..Default::default()
+    });
+    
+    // Recipes take the bottom of the panel; the inventory scrolls in what is left above
+    let all_recipes = recipes();
+    let recipes_h = 30.0 + all_recipes.len() as f32 * 20.0;
+    let rows = (((panel_h - 100.0 - recipes_h) / 25.0) as usize).max(1);
+    let first_row = (cursor + 1).saturating_sub(rows);
+    if game.player.inventory.is_empty() {
+        draw_text_ex("Nothing to work with", panel_x + 10.0, panel_y + 60.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: GRAY,
+            ..Default::default()
+        });
+    }
+    for (i, item) in game.player.inventory.iter().enumerate().skip(first_row).take(rows) {
+        let prefix = if i == cursor { "> " } else { "  " };
+        let picked = if first == Some(i) { " [picked]" } else { "" };
+        draw_text_ex(&format!("{}{} - {}{}", prefix, item.char, item.display_name(), picked), panel_x + 10.0, panel_y + 60.0 + (i - first_row) as f32 * 25.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: if first == Some(i) { GREEN } else if i == cursor { YELLOW } else { WHITE },
+            ..Default::default()
+        });
+    }
+    
+    let recipes_y = panel_y + panel_h - 30.0 - recipes_h;
+    draw_text_ex("Recipes", panel_x + 10.0, recipes_y + 20.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: BEIGE,
+        ..Default::default()
+    });
+    for (i, recipe) in all_recipes.iter().enumerate() {
+        let text = format!("{} + {} -> {}", recipe.input_a, recipe.input_b, recipe.output.name);
+        draw_text_ex(&text, panel_x + 20.0, recipes_y + 40.0 + i as f32 * 20.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: LIGHTGRAY,
+            ..Default::default()
+        });
+    }
+    
+    draw_text_ex("↑↓Select, Enter Pick/Craft, ESC Unpick/Close", panel_x + 10.0, panel_y + panel_h - 12.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
+}
+
+/// Draw the contents of the container at `pos`, with the selected item highlighted
+fn draw_looting(game: &Game, pos: (i32, i32), selected: usize) {
+    let Some(container) = game.current_map.containers.get(&pos) else {
+        return;
+    };
+    let (panel_w, panel_h) = (360.0, 80.0 + container.contents.len().max(1) as f32 * 25.0);
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, ORANGE);
+    draw_text_ex(&container.name.to_uppercase(), panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: ORANGE,
+        ..Default::default()
+    });
+    
+    if container.contents.is_empty() {
+        draw_text_ex("Empty", panel_x + 10.0, panel_y + 60.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: GRAY,
+            ..Default::default()
+        });
+    }
+    for (i, item) in container.contents.iter().enumerate() {
+        let prefix = if i == selected { "> " } else { "  " };
+        draw_text_ex(&format!("{}{} - {}", prefix, item.char, item.display_name()), panel_x + 10.0, panel_y + 60.0 + i as f32 * 25.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: if i == selected { YELLOW } else { WHITE },
+            ..Default::default()
+        });
+    }
+    
+    draw_text_ex("↑↓Select, Enter Take, T Take all, ESC Close", panel_x + 10.0, panel_y + panel_h - 12.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
+}
+
+/// Size of the panel signs and notes are read in
+const READING_PANEL: (f32, f32) = (420.0, 260.0);
+/// Lines of text shown at once in the reading panel
+const READING_LINES: usize = 8;
+/// Paper color notes are drawn on
+const PARCHMENT: Color = Color::new(0.9, 0.85, 0.7, 1.0);
+/// Ink color of text written on parchment
+const INK: Color = Color::new(0.25, 0.18, 0.1, 1.0);
+
+/// Sign or note text wrapped to the reading panel
+fn reading_lines(text: &str) -> Vec<String> {
+    wrap_text(text, None, 18, READING_PANEL.0 - 20.0)
+}
+
+/// Draw a sign or note in a panel, from the scrolled-to line on
+fn draw_reading(reading: &ReadingState) {
+    let (panel_w, panel_h) = READING_PANEL;
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    // Notes are dark ink on parchment, signs pale letters on a dark board
+    let (background, border, title_color, text_color) = if reading.parchment {
+        (PARCHMENT, BROWN, INK, INK)
+    } else {
+        (BLACK, BEIGE, BEIGE, WHITE)
+    };
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, background);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, border);
+    draw_text_ex(&reading.title, panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: title_color,
+        ..Default::default()
+    });
+    
+    let lines = reading_lines(&reading.text);
+    for (i, line) in lines.iter().skip(reading.scroll).take(READING_LINES).enumerate() {
+        draw_text_ex(line, panel_x + 10.0, panel_y + 60.0 + i as f32 * 20.0, TextParams {
+            font: None,
+            font_size: 18,
+            color: text_color,
+            ..Default::default()
+        });
+    }
+    
+    let hint = if lines.len() > READING_LINES { "↑↓Scroll, Any other key Close" } else { "Any key Close" };
+    draw_text_ex(hint, panel_x + 10.0, panel_y + panel_h - 12.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: if reading.parchment { BROWN } else { DARKGRAY },
+        ..Default::default()
+    });
+}
+
+/// Draw the five hotbar slots in a row just above the message log
+/// Each slot shows its number key, the item's glyph and, for stacks, how many are left
+fn draw_hotbar(game: &Game) {
+    let slot_size = 36.0;
+    let gap = 6.0;
+    let total_w = HOTBAR_SLOTS as f32 * (slot_size + gap) - gap;
+    let start_x = (screen_width() - total_w) / 2.0;
+    let y = screen_height() - MESSAGE_LOG_HEIGHT - slot_size - 6.0;
+    
+    for (slot, held) in game.player.hotbar.iter().enumerate() {
+        let x = start_x + slot as f32 * (slot_size + gap);
+        let item = held.and_then(|idx| game.player.inventory.get(idx));
+        draw_rectangle(x, y, slot_size, slot_size, Color::new(0.0, 0.0, 0.0, 0.8));
+        draw_rectangle_lines(x, y, slot_size, slot_size, 1.0, if item.is_some() { LIGHTGRAY } else { DARKGRAY });
+        draw_text_ex(&(slot + 1).to_string(), x + 3.0, y + 11.0, TextParams {
+            font: None,
+            font_size: 12,
+            color: GRAY,
+            ..Default::default()
+        });
+        let Some(item) = item else { continue };
+        draw_text_ex(item.char, x + slot_size / 2.0 - 6.0, y + slot_size / 2.0 + 8.0, TextParams {
+            font: None,
+            font_size: 24,
+            color: WHITE,
+            ..Default::default()
+        });
+        if item.quantity > 1 {
+            let count = item.quantity.to_string();
+            let count_w = measure_text(&count, None, 12, 1.0).width;
+            draw_text_ex(&count, x + slot_size - count_w - 3.0, y + slot_size - 3.0, TextParams {
+                font: None,
+                font_size: 12,
+                color: YELLOW,
+                ..Default::default()
+            });
+        }
     }
+}
+
+/// Draw inventory interface
+/// `selected` is the row within `tab`
+fn draw_inventory(game: &Game, selected: usize, tab: InventoryTab) {
+    // Calculate centered panel position
+    let (panel_w, panel_h) = panel_size(0.5, 0.5, (460.0, 260.0), (640.0, 520.0));
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
     
-    /// Load dungeon NPCs (enemies)
-    fn load_dungeon_npcs(&mut self, _dungeon_id: usize) {
-        self.npcs = vec![
-            NPC {
-                name: "Dungeon Guard".to_string(),
-                char: "G",
-                x: 10,
-                y: 10,
-                hp: 80,
-                max_hp: 80,
-                hostile: true,
-                dialogue: vec![
-                    DialogueNode {
-                        text: "Intruders must die!".to_string(),
-                        options: vec![
-                            DialogueOption { text: "Fight!".to_string(), next_node: None },
-                        ],
-                    },
-                ],
-            },
-            NPC {
-                name: "Mutant Beast".to_string(),
-                char: "M",
-                x: 25,
-                y: 15,
-                hp: 100,
-                max_hp: 100,
-                hostile: true,
-                dialogue: vec![
-                    DialogueNode {
-                        text: "Hssssss...".to_string(),
-                        options: vec![
-                            DialogueOption { text: "Back away slowly...".to_string(), next_node: None },
-                        ],
-                    },
-                ],
-            },
-        ];
+    // Draw panel background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, WHITE);
+    
+    // Draw title
+    draw_text_ex("INVENTORY", panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: YELLOW,
+        ..Default::default()
+    });
+    
+    // Tabs with their item counts, the open one highlighted
+    let mut tab_x = panel_x + 10.0;
+    for other in InventoryTab::ALL {
+        let label = format!("{} ({})", other.name(), game.player.tab_items(other).len());
+        draw_text_ex(&label, tab_x, panel_y + 55.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: if other == tab { YELLOW } else { GRAY },
+            ..Default::default()
+        });
+        tab_x += measure_text(&label, None, 16, 1.0).width + 12.0;
+    }
+    
+    // Display the tab's contents
+    let listed = game.player.tab_items(tab);
+    if listed.is_empty() {
+        draw_text_ex("Empty", panel_x + 10.0, panel_y + 85.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: GRAY,
+            ..Default::default()
+        });
+    } else {
+        // List the items that fit, scrolled to keep the selection visible
+        // Equipped gear is marked and the selection is highlighted
+        let rows = (((panel_h - 125.0) / 25.0) as usize).max(1);
+        let first = (selected + 1).saturating_sub(rows);
+        for (row, &i) in listed.iter().enumerate().skip(first).take(rows) {
+            let item = &game.player.inventory[i];
+            let equipped = game.player.equipped_weapon == Some(i) || game.player.equipped_armor == Some(i);
+            let prefix = if row == selected { "> " } else { "  " };
+            let suffix = if equipped { " [E]" } else { "" };
+            let wear = item.durability.map(|(current, max)| format!(" ({}/{})", current, max)).unwrap_or_default();
+            draw_text_ex(
+                &format!("{}{} - {}{}{}", prefix, item.char, item.display_name(), wear, suffix),
+                panel_x + 10.0,
+                panel_y + 85.0 + (row - first) as f32 * 25.0,
+                TextParams {
+                    font: None,
+                    font_size: 20,
+                    color: if row == selected { YELLOW } else { WHITE },
+                    ..Default::default()
+                }
+            );
+        }
+        
+        // Compare selected gear against what is equipped, floating right of its row
+        let player = &game.player;
+        let row = selected;
+        let selected = listed.get(row).copied().unwrap_or(0);
+        if let Some((slot, slot_name)) = player.gear_slot(selected)
+            && slot != Some(selected)
+        {
+            let segments = match slot {
+                None => vec![(format!("No {} equipped.", slot_name), GRAY)],
+                Some(equipped) => {
+                    let (this, this_stat) = player.inventory[selected].gear_stat().unwrap_or((0, ""));
+                    let (worn, worn_stat) = player.inventory[equipped].gear_stat().unwrap_or((0, ""));
+                    let delta = this - worn;
+                    let (arrow, color) = match delta.signum() {
+                        1 => (" ▲", GREEN),
+                        -1 => (" ▼", RED),
+                        _ => ("", GRAY),
+                    };
+                    vec![
+                        (format!("Equipped: {} ({} {}) | This: {} ({} {}) ",
+                            player.inventory[equipped].name, worn_stat, worn,
+                            player.inventory[selected].name, this_stat, this), WHITE),
+                        (format!("[{:+}{}]", delta, arrow), color),
+                    ]
+                }
+            };
+            let widths: Vec<f32> = segments.iter().map(|(text, _)| measure_text(text, None, 16, 1.0).width).collect();
+            let tip_w = widths.iter().sum::<f32>() + 12.0;
+            let tip_x = (panel_x + panel_w + 8.0).min(screen_width() - tip_w - 8.0).max(0.0);
+            let tip_y = panel_y + 69.0 + (row - first) as f32 * 25.0;
+            draw_rectangle(tip_x, tip_y, tip_w, 22.0, Color::new(0.15, 0.15, 0.15, 1.0));
+            draw_rectangle_lines(tip_x, tip_y, tip_w, 22.0, 1.0, segments[segments.len() - 1].1);
+            let mut text_x = tip_x + 6.0;
+            for ((text, color), width) in segments.iter().zip(widths) {
+                draw_text_ex(text, text_x, tip_y + 16.0, TextParams {
+                    font: None,
+                    font_size: 16,
+                    color: *color,
+                    ..Default::default()
+                });
+                text_x += width;
+            }
+        }
+    }
+    
+    // Draw control hint
+    draw_text_ex("←→Tab, ↑↓Select, Enter Use/Equip, 1-5 Hotbar, I Close", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
+}
+
+/// Flatten transcript entries into display lines
+/// Each entry produces the speaker's line followed by the player's reply, if any
+fn transcript_lines(transcript: &[TranscriptEntry]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for entry in transcript {
+        lines.push(format!("{}: {}", entry.speaker, entry.text));
+        if let Some(chosen) = &entry.chosen {
+            lines.push(format!("  You: {}", chosen));
+        }
+    }
+    lines
+}
+
+/// Draw a window of transcript lines, newest at the bottom
+/// `scroll` is the number of lines scrolled back from the newest line
+fn draw_transcript(lines: &[String], scroll: usize, x: f32, y: f32, max_lines: usize) {
+    let end = lines.len().saturating_sub(scroll);
+    let start = end.saturating_sub(max_lines);
+    for (i, line) in lines[start..end].iter().enumerate() {
+        let color = if line.starts_with("  You:") { SKYBLUE } else { LIGHTGRAY };
+        draw_text_ex(line, x, y + i as f32 * 20.0, TextParams {
+            font: None,
+            font_size: 16,
+            color,
+            ..Default::default()
+        });
+    }
+}
+
+/// Draw dialogue interface
+/// Draw branching dialogue interface (West of Loathing style)
+fn draw_dialogue(game: &Game, dialogue: &DialogueState) {
+    // Get NPC data
+    let npc = &game.npcs[dialogue.npc_idx];
+
+    // Get current dialogue node
+    let node = &dialogue.nodes[dialogue.node_idx];
+
+    // A portrait takes the left of the dialogue area and the text box narrows to make room
+    let (area_w, panel_w) = dialogue_widths(npc);
+    let portrait_w = area_w - panel_w;
+    
+    // Wrap node text and options to the panel width, then size the box to fit the page shown
+    // Options wait until the last page of text
+    let all_lines = dialogue_text_lines(npc, &node.text);
+    let last_page = dialogue.page + 1 >= all_lines.len().div_ceil(DIALOGUE_PAGE_LINES);
+    let text_lines: Vec<String> = all_lines.into_iter()
+        .skip(dialogue.page * DIALOGUE_PAGE_LINES)
+        .take(DIALOGUE_PAGE_LINES)
+        .collect();
+    // Options needing an item the player doesn't carry are grayed out and say what they need
+    let unmet = |opt: &DialogueOption| opt.requires_item.clone().filter(|name| game.player.item_index(name).is_none());
+    let option_lines: Vec<Vec<String>> = node.options.iter().enumerate()
+        .map(|(i, opt)| {
+            let prefix = if i == dialogue.selected { "> " } else { "  " };
+            let needs = unmet(opt).map(|name| format!(" (needs {})", name)).unwrap_or_default();
+            wrap_text(&format!("{}{}{}", prefix, opt.text, needs), None, 18, panel_w - 40.0)
+        })
+        .collect();
+    let option_rows: usize = if last_page { option_lines.iter().map(Vec::len).sum() } else { 1 };
+    let panel_h = (100.0 + text_lines.len() as f32 * 22.0 + option_rows as f32 * 22.0).max(PORTRAIT_HEIGHT);
+
+    // Sit just above the message log, but never above the status bar
+    let area_x = (screen_width() - area_w) / 2.0;
+    let panel_x = area_x + portrait_w;
+    let panel_y = (screen_height() - MESSAGE_LOG_HEIGHT - panel_h - 10.0).max(40.0);
+
+    // Draw conversation history above the dialogue box, using whatever room is left
+    // The current node is already shown in the box, so history excludes it
+    let history = transcript_lines(&dialogue.transcript[..dialogue.transcript.len() - 1]);
+    let history_lines = (((panel_y - 60.0) / 20.0) as usize).min(6);
+    if !history.is_empty() && history_lines > 0 {
+        let history_h = history_lines as f32 * 20.0 + 20.0;
+        let history_y = panel_y - history_h - 10.0;
+        draw_rectangle(panel_x, history_y, panel_w, history_h, Color::new(0.0, 0.0, 0.0, 0.85));
+        draw_rectangle_lines(panel_x, history_y, panel_w, history_h, 1.0, DARKGREEN);
+        let scroll = dialogue.history_scroll.min(history.len().saturating_sub(1));
+        draw_transcript(&history, scroll, panel_x + 10.0, history_y + 22.0, history_lines);
+    }
+
+    // Draw the portrait in its own box, one art line per row in the monospace default font
+    if !npc.portrait.is_empty() {
+        draw_rectangle(area_x, panel_y, PORTRAIT_WIDTH, PORTRAIT_HEIGHT, BLACK);
+        draw_rectangle_lines(area_x, panel_y, PORTRAIT_WIDTH, PORTRAIT_HEIGHT, 2.0, GREEN);
+        for (i, line) in npc.portrait.iter().enumerate() {
+            draw_text_ex(line, area_x + 10.0, panel_y + 30.0 + i as f32 * 20.0, TextParams {
+                font: None,
+                font_size: 20,
+                color: if npc.hostile { RED } else { GREEN },
+                ..Default::default()
+            });
+        }
+    }
+    
+    // Draw dialogue box background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, GREEN);
+
+    // Display NPC name
+    draw_text_ex(&npc.name, panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 22,
+        color: GREEN,
+        ..Default::default()
+    });
+
+    // Display current node text
+    let mut y = panel_y + 60.0;
+    for line in &text_lines {
+        draw_text_ex(line, panel_x + 10.0, y, TextParams {
+            font: None,
+            font_size: 18,
+            color: WHITE,
+            ..Default::default()
+        });
+        y += 22.0;
+    }
+
+    // Display all options, highlight selected option
+    y += 18.0;
+    if !last_page {
+        draw_text_ex("▼ more", panel_x + 30.0, y, TextParams {
+            font: None,
+            font_size: 18,
+            color: YELLOW,
+            ..Default::default()
+        });
+    }
+    for (i, lines) in option_lines.iter().enumerate().filter(|_| last_page) {
+        let color = match (i == dialogue.selected, unmet(&node.options[i]).is_some()) {
+            (true, false) => YELLOW,
+            (true, true) => Color::new(0.5, 0.5, 0.2, 1.0),
+            (false, false) => GRAY,
+            (false, true) => DARKGRAY,
+        };
+        for line in lines {
+            draw_text_ex(line, panel_x + 30.0, y, TextParams {
+                font: None,
+                font_size: 18,
+                color,
+                ..Default::default()
+            });
+            y += 22.0;
+        }
+    }
+
+    // Draw hint
+    draw_text_ex("↑↓Select, Enter/Space Confirm or Read on, PgUp/PgDn Page/History, ESC Exit", panel_x + 10.0, panel_y + panel_h - 12.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
+}
+
+/// Lines of node text shown at once in the dialogue box; longer text is paged
+const DIALOGUE_PAGE_LINES: usize = 3;
+
+/// Width of the whole dialogue area and of the text box within it, which
+/// gives up room on its left for the NPC's portrait
+fn dialogue_widths(npc: &Npc) -> (f32, f32) {
+    let area_w = (screen_width() * 0.6).clamp(360.0, 900.0).min(screen_width() - 20.0);
+    let portrait_w = if npc.portrait.is_empty() { 0.0 } else { PORTRAIT_WIDTH + 10.0 };
+    (area_w, area_w - portrait_w)
+}
+
+/// Node text wrapped to the dialogue box as it is drawn for this NPC
+fn dialogue_text_lines(npc: &Npc, text: &str) -> Vec<String> {
+    wrap_text(text, None, 18, dialogue_widths(npc).1 - 20.0)
+}
+
+/// Size of the portrait box drawn left of the dialogue text
+const PORTRAIT_WIDTH: f32 = 130.0;
+/// Height of the portrait box, which the dialogue box never drops below
+const PORTRAIT_HEIGHT: f32 = 110.0;
+
+/// Draw the dialogue log screen listing past conversations
+fn draw_dialogue_log(game: &Game, scroll: usize) {
+    // Calculate centered panel position
+    let panel_w = 600.0;
+    let panel_h = 400.0;
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    // Draw panel background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, GREEN);
+    
+    // Draw title
+    draw_text_ex("DIALOGUE LOG", panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: GREEN,
+        ..Default::default()
+    });
+    
+    let lines = transcript_lines(&game.dialogue_log);
+    if lines.is_empty() {
+        draw_text_ex("No conversations yet", panel_x + 10.0, panel_y + 60.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: GRAY,
+            ..Default::default()
+        });
+    } else {
+        draw_transcript(&lines, scroll, panel_x + 10.0, panel_y + 60.0, 15);
+    }
+    
+    // Draw close hint
+    draw_text_ex("PgUp/PgDn Scroll, L/ESC Close", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
+}
+
+/// Draw combat interface
+/// `throwing` is the selected entry while the throw list is open
+fn draw_combat(game: &Game, npc_idx: usize, throwing: Option<usize>) {
+    // Calculate centered combat panel position
+    let (panel_w, panel_h) = panel_size(0.5, 0.4, (320.0, 255.0), (600.0, 300.0));
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    // Draw combat panel background and border (red border indicates combat)
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, RED);
+    
+    // Get enemy data
+    let npc = &game.npcs[npc_idx];
+    
+    // Display combat title
+    draw_text_ex("COMBAT", panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: RED,
+        ..Default::default()
+    });
+    
+    // Display enemy information
+    draw_text_ex(&format!("Enemy: {}", npc.name), panel_x + 10.0, panel_y + 60.0, TextParams {
+        font: None,
+        font_size: 20,
+        color: ORANGE,
+        ..Default::default()
+    });
+    draw_text_ex(&format!("Enemy HP: {}/{}", npc.hp, npc.max_hp), 
+              panel_x + 10.0, panel_y + 85.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: WHITE,
+        ..Default::default()
+    });
+    
+    // Display player information
+    draw_text_ex(&format!("Your HP: {}/{}", game.player.hp, game.player.max_hp), 
+              panel_x + 10.0, panel_y + 110.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: WHITE,
+        ..Default::default()
+    });
+    
+    // Display combat options
+    draw_text_ex("1: Attack", panel_x + 10.0, panel_y + 150.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: YELLOW,
+        ..Default::default()
+    });
+    draw_text_ex("2: Use Item", panel_x + 10.0, panel_y + 175.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: YELLOW,
+        ..Default::default()
+    });
+    draw_text_ex("3: Run", panel_x + 10.0, panel_y + 200.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: YELLOW,
+        ..Default::default()
+    });
+    draw_text_ex("5: Throw", panel_x + 10.0, panel_y + 225.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: YELLOW,
+        ..Default::default()
+    });
+    
+    // Damage numbers rise and fade over the HP lines
+    for floater in &game.damage_floaters {
+        let progress = floater.age / DAMAGE_FLOATER_DURATION;
+        let color = Color::new(floater.color.r, floater.color.g, floater.color.b, 1.0 - progress);
+        draw_text_ex(&format!("-{}", floater.value), panel_x + floater.pos.0, panel_y + floater.pos.1 - progress * DAMAGE_FLOATER_RISE, TextParams {
+            font: None,
+            font_size: 22,
+            color,
+            ..Default::default()
+        });
     }
     
-    /// Update camera position to follow player
-    /// Camera keeps player near center of screen
-    fn update_camera(&mut self) {
-        // Center camera on player position
-        // Offset adjusted for viewport size (20 tiles wide, 10 tiles high)
-        self.camera_x = self.player.x - 20;
-        self.camera_y = self.player.y - 10;
+    // The fight so far, newest at the bottom, in its own box under the panel
+    let log_y = panel_y + panel_h + 10.0;
+    let log_h = 20.0 + COMBAT_LOG_LINES as f32 * 20.0;
+    draw_rectangle(panel_x, log_y, panel_w, log_h, Color::new(0.0, 0.0, 0.0, 0.85));
+    draw_rectangle_lines(panel_x, log_y, panel_w, log_h, 1.0, MAROON);
+    for (i, line) in game.combat_log.iter().enumerate() {
+        draw_text_ex(line, panel_x + 10.0, log_y + 22.0 + i as f32 * 20.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: LIGHTGRAY,
+            ..Default::default()
+        });
+    }
+    
+    // Throwables listed on the right half while picking one
+    if let Some(selected) = throwing {
+        let list_x = panel_x + panel_w / 2.0;
+        draw_text_ex("Throw what? (Enter, ESC back)", list_x, panel_y + 60.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: LIGHTGRAY,
+            ..Default::default()
+        });
+        for (row, idx) in game.player.throwables().into_iter().enumerate() {
+            let item = &game.player.inventory[idx];
+            let ItemType::Throwable { damage, radius } = item.item_type else { continue };
+            let prefix = if row == selected { "> " } else { "  " };
+            draw_text_ex(&format!("{}{} ({} DMG, radius {})", prefix, item.display_name(), damage, radius),
+                      list_x, panel_y + 85.0 + row as f32 * 22.0, TextParams {
+                font: None,
+                font_size: 16,
+                color: if row == selected { YELLOW } else { WHITE },
+                ..Default::default()
+            });
+        }
     }
 }
 
-// ========== Rendering System ==========
+/// Lines kept in the combat log under the combat panel
+const COMBAT_LOG_LINES: usize = 8;
 
-/// Draw main game interface (map, items, NPCs, player)
-fn draw_game(game: &Game) {
-    let tile_size = 20.0;   // Pixel size of each tile
-    let start_x = 20.0;     // Map drawing start X coordinate
-    let start_y = 40.0;     // Map drawing start Y coordinate
-    
-    // Draw all map tiles
-    for y in 0..game.current_map.height {
-        for x in 0..game.current_map.width {
-            // Calculate tile's screen position (accounting for camera offset)
-            let screen_x = start_x + (x - game.camera_x) as f32 * tile_size;
-            let screen_y = start_y + (y - game.camera_y) as f32 * tile_size;
-            
-            // Skip drawing if tile is outside visible screen area
-            if screen_x < 0.0 || screen_y < 0.0 || screen_x > screen_width() || screen_y > screen_height() {
-                continue;
-            }
-            
-            // Get tile type and set corresponding color
-            let tile = game.current_map.tiles[y as usize][x as usize];
-            let color = match tile {
-                TileType::Floor => DARKGRAY,     // Floor: dark gray
-                TileType::Wall => GRAY,          // Wall: gray
-                TileType::Door => BROWN,         // Door: brown
-                TileType::Water => BLUE,         // Water: blue
-                TileType::Grass => DARKGREEN,    // Grass: dark green
-                TileType::Mountain => LIGHTGRAY, // Mountain: light gray
-                TileType::Forest => GREEN,       // Forest: green
-                TileType::Town => ORANGE,        // Town: orange
-                TileType::Dungeon => DARKPURPLE, // Dungeon: dark purple
-            };
-            
-            // Draw tile rectangle background
-            draw_rectangle(screen_x, screen_y, tile_size, tile_size, color);
-            
-            // Draw tile's ASCII character
-            draw_text_ex(
-                tile.as_char(),
-                screen_x + 5.0,
-                screen_y + 15.0,
-                TextParams {
-                    font: None,
-                    font_size: 20,
-                    color: WHITE,
-                    ..Default::default()
-                },
-            );
-        }
-    }
+/// Draw level-up interface for spending skill points
+fn draw_level_up(game: &Game, level_up: &LevelUpState) {
+    // Calculate centered panel position
+    let panel_w = 640.0;
+    let panel_h = 330.0;
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
     
-    // Draw items on map
-    for ((x, y), item) in &game.current_map.items {
-        // Calculate item's screen position
-        let screen_x = start_x + (*x - game.camera_x) as f32 * tile_size;
-        let screen_y = start_y + (*y - game.camera_y) as f32 * tile_size;
-        
-        // Draw item character in yellow
+    // Draw panel background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, YELLOW);
+    
+    // Draw title and remaining points
+    draw_text_ex(&format!("LEVEL {}", game.player.level), panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: YELLOW,
+        ..Default::default()
+    });
+    let remaining = game.player.skill_points - level_up.spent();
+    draw_text_ex(&format!("Skill points: {}", remaining), panel_x + 10.0, panel_y + 55.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: WHITE,
+        ..Default::default()
+    });
+    
+    // List all seven stats (left) and seven skills (right) with +/- controls
+    let rows = StatType::ALL.iter().map(|stat| (stat.name(), game.player.stats.get(*stat)))
+        .chain(SkillType::ALL.iter().map(|skill| (skill.name(), game.player.skills.get(*skill))));
+    for (i, (name, base)) in rows.enumerate() {
+        let (column, row, added) = if i < StatType::ALL.len() {
+            (0.0, i, level_up.stat_allocated[i])
+        } else {
+            let j = i - StatType::ALL.len();
+            (310.0, j, level_up.skill_allocated[j] * SKILL_POINT_VALUE)
+        };
+        let selected = i == level_up.selected;
+        let color = if selected { YELLOW } else if added > 0 { GREEN } else { GRAY };
+        let prefix = if selected { "> " } else { "  " };
         draw_text_ex(
-            item.char,
-            screen_x + 5.0,
-            screen_y + 15.0,
+            &format!("{}{:<13} - {:>2} +", prefix, name, base + added),
+            panel_x + 20.0 + column,
+            panel_y + 90.0 + row as f32 * 28.0,
             TextParams {
                 font: None,
                 font_size: 20,
-                color: YELLOW,
+                color,
                 ..Default::default()
-            },
+            }
         );
     }
     
-    // Draw all NPCs
-    for npc in &game.npcs {
-        // Calculate NPC's screen position
-        let screen_x = start_x + (npc.x - game.camera_x) as f32 * tile_size;
-        let screen_y = start_y + (npc.y - game.camera_y) as f32 * tile_size;
-        
-        // Set color based on hostility: red for enemies, green for friendly
-        let color = if npc.hostile { RED } else { GREEN };
-        
-        // Draw NPC character
+    // Draw control hint
+    draw_text_ex("↑↓Select, ←→ -/+, Enter Confirm", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
+}
+
+/// Draw the character creation screen for spreading the starting stat points
+fn draw_character_creation(game: &Game, creation: &CreationState) {
+    // Calculate centered panel position
+    let panel_w = 460.0;
+    let panel_h = 330.0;
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    // Draw panel background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, YELLOW);
+    
+    // Draw title and remaining points
+    draw_text_ex(&format!("CREATE {}", game.player.class.name().to_uppercase()), panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: YELLOW,
+        ..Default::default()
+    });
+    draw_text_ex(&format!("Points left: {}", creation.points), panel_x + 10.0, panel_y + 55.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: if creation.points > 0 { WHITE } else { GREEN },
+        ..Default::default()
+    });
+    
+    // List the seven stats with +/- controls
+    for (i, stat) in StatType::ALL.iter().enumerate() {
+        let selected = i == creation.selected;
+        let prefix = if selected { "> " } else { "  " };
         draw_text_ex(
-            npc.char,
-            screen_x + 5.0,
-            screen_y + 15.0,
+            &format!("{}{:<13} - {:>2} +", prefix, stat.name(), creation.stats[i]),
+            panel_x + 20.0,
+            panel_y + 90.0 + i as f32 * 28.0,
             TextParams {
                 font: None,
                 font_size: 20,
-                color,
+                color: if selected { YELLOW } else { GRAY },
                 ..Default::default()
-            },
+            }
         );
     }
     
-    // Draw player character (represented by @ symbol)
-    let player_screen_x = start_x + (game.player.x - game.camera_x) as f32 * tile_size;
-    let player_screen_y = start_y + (game.player.y - game.camera_y) as f32 * tile_size;
-    draw_text_ex(
-        "@",
-        player_screen_x + 5.0,
-        player_screen_y + 15.0,
-        TextParams {
+    // Max HP as it will be with the chosen endurance
+    let endurance = StatType::ALL.iter().position(|stat| *stat == StatType::Endurance).map_or(0, |i| creation.stats[i]);
+    let max_hp = Player::base_max_hp(endurance, game.player.level);
+    draw_text_ex(&format!("Max HP: {}", max_hp), panel_x + 300.0, panel_y + 90.0, TextParams {
+        font: None,
+        font_size: 18,
+        color: WHITE,
+        ..Default::default()
+    });
+    
+    // Draw control hint
+    let hint = if creation.points > 0 { "↑↓Select, ←→ -/+, spend all points to start" } else { "↑↓Select, ←→ -/+, Enter Start" };
+    draw_text_ex(hint, panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
+}
+
+/// Draw perk selection interface
+fn draw_perk_selection(choices: &[Perk], selected: usize) {
+    // Calculate centered panel position
+    let panel_w = 460.0;
+    let panel_h = 300.0;
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    // Draw panel background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, YELLOW);
+    
+    // Draw title
+    draw_text_ex("CHOOSE A PERK", panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: YELLOW,
+        ..Default::default()
+    });
+    
+    // List offered perks with their descriptions, highlighting the selection
+    for (i, perk) in choices.iter().enumerate() {
+        let y = panel_y + 70.0 + i as f32 * 60.0;
+        let is_selected = i == selected;
+        if is_selected {
+            draw_rectangle(panel_x + 10.0, y - 22.0, panel_w - 20.0, 52.0, Color::new(0.3, 0.3, 0.0, 0.6));
+        }
+        let prefix = if is_selected { "> " } else { "  " };
+        draw_text_ex(&format!("{}{}", prefix, perk.name), panel_x + 20.0, y, TextParams {
             font: None,
-            font_size: 20,
-            color: SKYBLUE,
+            font_size: 22,
+            color: if is_selected { YELLOW } else { WHITE },
             ..Default::default()
-        },
-    );
+        });
+        draw_text_ex(perk.description, panel_x + 45.0, y + 22.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: LIGHTGRAY,
+            ..Default::default()
+        });
+    }
+    
+    // Draw control hint
+    draw_text_ex("↑↓Select, Enter/Space Confirm", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
 }
 
-/// Draw user interface (status bar, message log, control hints)
-fn draw_ui(game: &Game) {
-    // === Draw top status bar ===
-    // Black background
-    draw_rectangle(0.0, 0.0, screen_width(), 30.0, BLACK);
+/// Draw character sheet with SPECIAL stats, derived values and equipment
+fn draw_character_sheet(game: &Game) {
+    let player = &game.player;
     
-    // Display player status info and current map
+    // Calculate centered panel position
+    let panel_w = 420.0;
+    let panel_h = 682.0;
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    // Draw panel background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, GREEN);
+    
+    // Draw name, class, level and experience
+    draw_text_ex(&format!("{} the {}", player.name, player.class.name()), panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: GREEN,
+        ..Default::default()
+    });
     draw_text_ex(
-        &format!("HP: {}/{} | Pos: ({},{}) | Items: {} | Map: {}", 
-                 game.player.hp, game.player.max_hp,
-                 game.player.x, game.player.y,
-                 game.player.inventory.len(),
-                 game.current_map.name),
-        10.0, 20.0,
+        &format!("Level {}   XP: {}/{}", player.level, player.xp, player.xp_to_next_level()),
+        panel_x + 10.0, panel_y + 55.0,
         TextParams {
             font: None,
-            font_size: 20,
-            color: GREEN,
+            font_size: 18,
+            color: WHITE,
             ..Default::default()
         }
     );
     
-    // === Draw bottom message log ===
-    let log_y = screen_height() - 120.0;
-    // Semi-transparent black background
-    draw_rectangle(0.0, log_y, screen_width(), 120.0, Color::new(0.0, 0.0, 0.0, 0.8));
+    // List SPECIAL stats
+    let mut y = panel_y + 90.0;
+    for stat in StatType::ALL {
+        draw_text_ex(&format!("{:<13} {:>2}", stat.name(), player.stats.get(stat)), panel_x + 20.0, y, TextParams {
+            font: None,
+            font_size: 18,
+            color: LIGHTGRAY,
+            ..Default::default()
+        });
+        y += 22.0;
+    }
     
-    // Display most recent 5 messages
-    for (i, msg) in game.messages.iter().enumerate() {
-        draw_text_ex(
-            msg, 
-            10.0, 
-            log_y + 20.0 + i as f32 * 20.0,
-            TextParams {
+    // List skills below SPECIAL
+    y += 10.0;
+    draw_text_ex("Skills", panel_x + 10.0, y, TextParams {
+        font: None,
+        font_size: 20,
+        color: GREEN,
+        ..Default::default()
+    });
+    y += 24.0;
+    for skill in SkillType::ALL {
+        draw_text_ex(&format!("{:<14} {:>3}", skill.name(), player.skills.get(skill)), panel_x + 20.0, y, TextParams {
+            font: None,
+            font_size: 18,
+            color: LIGHTGRAY,
+            ..Default::default()
+        });
+        y += 22.0;
+    }
+    
+    // List derived values and equipment
+    let details = [
+        format!("Max HP: {}", player.max_hp),
+        format!("Carry Weight: {}/{}", player.carry_weight(), player.carry_capacity()),
+        format!("Crit Chance: {}%", player.crit_chance()),
+        format!("XP Bonus: +{}%", player.xp_bonus()),
+        format!("Weapon: {}", player.equipped_name(player.equipped_weapon)),
+        format!("Armor: {}", player.equipped_name(player.equipped_armor)),
+        format!("Gold: {}", player.gold),
+        format!("Karma: {} ({})", game.karma, karma_tier(game.karma)),
+        format!("Perks: {}", if player.perks.is_empty() {
+            "None".to_string()
+        } else {
+            player.perks.iter().map(|perk| perk.name).collect::<Vec<_>>().join(", ")
+        }),
+    ];
+    y += 15.0;
+    for line in &details {
+        draw_text_ex(line, panel_x + 20.0, y, TextParams {
+            font: None,
+            font_size: 18,
+            color: WHITE,
+            ..Default::default()
+        });
+        y += 22.0;
+    }
+    
+    // Draw close hint
+    draw_text_ex("Press C to close", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
+}
+
+/// Draw quest journal listing active and completed quests
+fn draw_journal(game: &Game) {
+    // Calculate centered panel position
+    let panel_w = 560.0;
+    let panel_h = 400.0;
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    // Draw panel background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, GOLD);
+    
+    // Draw title
+    draw_text_ex("JOURNAL", panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: GOLD,
+        ..Default::default()
+    });
+    
+    if game.quests.is_empty() {
+        draw_text_ex("No quests yet", panel_x + 10.0, panel_y + 60.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: GRAY,
+            ..Default::default()
+        });
+    }
+    
+    // Active quests first, then completed ones
+    let mut y = panel_y + 60.0;
+    for (heading, state) in [("Active", QuestState::Active), ("Completed", QuestState::Completed)] {
+        let quests: Vec<&Quest> = game.quests.iter().filter(|q| q.state == state).collect();
+        if quests.is_empty() {
+            continue;
+        }
+        draw_text_ex(heading, panel_x + 10.0, y, TextParams {
+            font: None,
+            font_size: 20,
+            color: YELLOW,
+            ..Default::default()
+        });
+        y += 25.0;
+        
+        for quest in quests {
+            let color = if state == QuestState::Active { WHITE } else { GRAY };
+            draw_text_ex(&quest.title, panel_x + 20.0, y, TextParams {
                 font: None,
                 font_size: 18,
+                color,
+                ..Default::default()
+            });
+            draw_text_ex(&quest.description, panel_x + 30.0, y + 20.0, TextParams {
+                font: None,
+                font_size: 16,
                 color: LIGHTGRAY,
                 ..Default::default()
-            }
-        );
+            });
+            draw_text_ex(&format!("- {}", quest.objective_text()), panel_x + 30.0, y + 40.0, TextParams {
+                font: None,
+                font_size: 16,
+                color,
+                ..Default::default()
+            });
+            y += 65.0;
+        }
     }
     
-    // === Draw control hints ===
-    let controls = if game.current_map.map_type == MapType::WorldMap {
-        "WASD/Arrow: Move | Space: Enter Town/Dungeon | I: Inventory"
-    } else {
-        "WASD/Arrow: Move | ESC: Return to World | I: Inventory"
-    };
-    draw_text_ex(
-        controls, 
-        10.0, 
-        screen_height() - 10.0,
-        TextParams {
-            font: None,
-            font_size: 16,
-            color: DARKGRAY,
-            ..Default::default()
-        }
-    );
+    // Draw close hint
+    draw_text_ex("Press J to close", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
 }
 
-/// Draw inventory interface
-fn draw_inventory(game: &Game) {
+/// Bestiary entries sorted by name
+fn bestiary_entries(game: &Game) -> Vec<&BestiaryEntry> {
+    let mut entries: Vec<&BestiaryEntry> = game.bestiary.values().collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
+
+/// Bestiary entries that fit in the panel at once
+const BESTIARY_ROWS: usize = 5;
+
+/// Draw the bestiary, one entry per row with its kill count and lore
+fn draw_bestiary(game: &Game, selected: usize) {
     // Calculate centered panel position
-    let panel_w = 400.0;
-    let panel_h = 300.0;
+    let panel_w = 560.0;
+    let panel_h = 400.0;
     let panel_x = (screen_width() - panel_w) / 2.0;
     let panel_y = (screen_height() - panel_h) / 2.0;
     
     // Draw panel background and border
     draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
-    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, WHITE);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, GOLD);
     
     // Draw title
-    draw_text_ex("INVENTORY", panel_x + 10.0, panel_y + 30.0, TextParams {
+    draw_text_ex("BESTIARY", panel_x + 10.0, panel_y + 30.0, TextParams {
         font: None,
         font_size: 24,
-        color: YELLOW,
+        color: GOLD,
         ..Default::default()
     });
     
-    // Display inventory contents
-    if game.player.inventory.is_empty() {
-        draw_text_ex("Empty", panel_x + 10.0, panel_y + 60.0, TextParams {
+    let entries = bestiary_entries(game);
+    if entries.is_empty() {
+        draw_text_ex("No enemies encountered yet", panel_x + 10.0, panel_y + 60.0, TextParams {
             font: None,
             font_size: 20,
             color: GRAY,
             ..Default::default()
         });
-    } else {
-        // List all items
-        for (i, item) in game.player.inventory.iter().enumerate() {
-            draw_text_ex(
-                &format!("{} - {}", item.char, item.name),
-                panel_x + 10.0,
-                panel_y + 60.0 + i as f32 * 25.0,
-                TextParams {
-                    font: None,
-                    font_size: 20,
-                    color: WHITE,
-                    ..Default::default()
-                }
-            );
-        }
+    }
+    
+    // Keep the selected entry in the visible window
+    let first = selected.saturating_sub(BESTIARY_ROWS - 1);
+    let mut y = panel_y + 60.0;
+    for (i, entry) in entries.iter().enumerate().skip(first).take(BESTIARY_ROWS) {
+        let color = if i == selected { YELLOW } else { WHITE };
+        let prefix = if i == selected { "> " } else { "  " };
+        draw_text_ex(&format!("{}{}", prefix, entry.name), panel_x + 10.0, y, TextParams {
+            font: None,
+            font_size: 20,
+            color,
+            ..Default::default()
+        });
+        draw_text_ex(&format!("Kills: {}", entry.kills), panel_x + panel_w - 110.0, y, TextParams {
+            font: None,
+            font_size: 18,
+            color,
+            ..Default::default()
+        });
+        let description = if entry.kills == 0 { "???" } else { entry.description.as_str() };
+        draw_text_ex(description, panel_x + 30.0, y + 20.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: LIGHTGRAY,
+            ..Default::default()
+        });
+        draw_text_ex(&format!("First met: {}", entry.first_map), panel_x + 30.0, y + 40.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: GRAY,
+            ..Default::default()
+        });
+        y += 60.0;
     }
     
     // Draw close hint
-    draw_text_ex("Press I to close", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+    draw_text_ex("Up/Down: Browse | B: Close", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
         font: None,
         font_size: 16,
         color: DARKGRAY,
@@ -959,56 +8841,180 @@ fn draw_inventory(game: &Game) {
     });
 }
 
-/// Draw dialogue interface
-/// Draw branching dialogue interface (West of Loathing style)
-fn draw_dialogue(game: &Game, npc_idx: usize, node_idx: usize, selected: usize) {
-    // Calculate dialogue box position (bottom of screen)
-    let panel_w = 500.0;
-    let panel_h = 200.0;
+/// Draw the fast travel menu listing discovered towns
+fn draw_fast_travel(game: &Game, selected: usize) {
+    // Calculate centered panel position
+    let (panel_w, panel_h) = panel_size(0.4, 0.4, (300.0, 200.0), (480.0, 360.0));
     let panel_x = (screen_width() - panel_w) / 2.0;
-    let panel_y = screen_height() - panel_h - 50.0;
-
-    // Draw dialogue box background and border
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    // Draw panel background and border
     draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
-    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, GREEN);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, ORANGE);
+    
+    // Draw title
+    draw_text_ex("FAST TRAVEL", panel_x + 10.0, panel_y + 30.0, TextParams {
+        font: None,
+        font_size: 24,
+        color: ORANGE,
+        ..Default::default()
+    });
+    
+    // List discovered towns, highlighting the selection
+    for (i, &town_id) in game.discovered_towns.iter().enumerate() {
+        let prefix = if i == selected { "> " } else { "  " };
+        draw_text_ex(&format!("{}{}", prefix, game.town_maps[town_id].name), panel_x + 10.0, panel_y + 60.0 + i as f32 * 25.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: if i == selected { YELLOW } else { WHITE },
+            ..Default::default()
+        });
+    }
+    
+    // Draw control hint
+    draw_text_ex("↑↓Select, Enter Travel, T/ESC Close", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
+        ..Default::default()
+    });
+}
 
-    // Get NPC data
-    let npc = &game.npcs[npc_idx];
+/// Screen rectangle (x, y, width, height) the full-screen map is drawn in
+fn map_view_area() -> (f32, f32, f32, f32) {
+    (10.0, 40.0, screen_width() - 20.0, screen_height() - 70.0)
+}
 
-    // Get current dialogue node
-    let node = &npc.dialogue[node_idx];
+/// Pixels per tile in the map view: the whole map fits at zoom 1.0
+fn map_view_scale(map: &GameMap, zoom: f32) -> f32 {
+    let (_, _, area_w, area_h) = map_view_area();
+    (area_w / map.width as f32).min(area_h / map.height as f32) * zoom
+}
 
-    // Display NPC name
-    draw_text_ex(&npc.name, panel_x + 10.0, panel_y + 30.0, TextParams {
+/// Draw the whole current map scaled to the window
+/// Shows seen terrain, the player, discovered entrances and active quest targets
+fn draw_map_view(game: &Game, view: &MapViewState) {
+    let map = &game.current_map;
+    let (area_x, area_y, area_w, area_h) = map_view_area();
+    let scale = map_view_scale(map, view.zoom);
+    
+    // Center maps narrower than the window; otherwise start at the pan offset
+    let origin = |area_pos: f32, area_len: f32, tiles: i32, pan: f32| {
+        let len = tiles as f32 * scale;
+        if len <= area_len { area_pos + (area_len - len) / 2.0 } else { area_pos - pan * scale }
+    };
+    let origin_x = origin(area_x, area_w, map.width, view.pan_x);
+    let origin_y = origin(area_y, area_h, map.height, view.pan_y);
+    let to_screen = |x: i32, y: i32| (origin_x + x as f32 * scale, origin_y + y as f32 * scale);
+    let on_screen = |(sx, sy): (f32, f32)| {
+        sx + scale > area_x && sx < area_x + area_w && sy + scale > area_y && sy < area_y + area_h
+    };
+    
+    draw_rectangle(0.0, 0.0, screen_width(), screen_height(), BLACK);
+    draw_text_ex(&format!("MAP - {}", map.name), 10.0, 28.0, TextParams {
         font: None,
-        font_size: 22,
-        color: GREEN,
+        font_size: 24,
+        color: ORANGE,
+        ..Default::default()
+    });
+    
+    // Terrain, with remembered tiles out of sight dimmed
+    for y in 0..map.height {
+        for x in 0..map.width {
+            let pos = to_screen(x, y);
+            if !on_screen(pos) {
+                continue;
+            }
+            let color = tile_color(game, map.displayed_tile(x, y), x, y);
+            let color = if game.is_visible(x, y) {
+                color
+            } else if game.is_explored(x, y) {
+                dimmed(color)
+            } else {
+                continue;
+            };
+            draw_rectangle(pos.0, pos.1, scale, scale, color);
+        }
+    }
+    
+    // Label entrances the player has seen
+    for (&(x, y), &(map_type, map_id)) in &map.entrances {
+        let pos = to_screen(x, y);
+        if !on_screen(pos) || !(game.is_visible(x, y) || game.is_explored(x, y)) {
+            continue;
+        }
+        draw_rectangle_lines(pos.0, pos.1, scale, scale, 2.0, WHITE);
+        draw_text_ex(game.map_name(map_type, map_id), pos.0 + scale + 2.0, pos.1 + scale, TextParams {
+            font: None,
+            font_size: 16,
+            color: WHITE,
+            ..Default::default()
+        });
+    }
+    
+    // Quest targets in explored territory
+    for (x, y) in game.quest_markers() {
+        let pos = to_screen(x, y);
+        if on_screen(pos) && game.is_explored(x, y) {
+            draw_text_ex("!", pos.0, pos.1 + scale, TextParams {
+                font: None,
+                font_size: (scale * 1.5).max(16.0) as u16,
+                color: YELLOW,
+                ..Default::default()
+            });
+        }
+    }
+    
+    // Player marker
+    let (px, py) = to_screen(game.player.x, game.player.y);
+    draw_circle(px + scale / 2.0, py + scale / 2.0, (scale / 2.0).max(3.0), SKYBLUE);
+    
+    // Draw control hint
+    draw_text_ex("Arrows Pan, +/- Zoom, ESC Close", 10.0, screen_height() - 10.0, TextParams {
+        font: None,
+        font_size: 16,
+        color: DARKGRAY,
         ..Default::default()
     });
+}
 
-    // Display current node text
-    draw_text_ex(&node.text, panel_x + 10.0, panel_y + 60.0, TextParams {
+/// Draw the game over screen
+fn draw_game_over(game: &Game) {
+    // Calculate centered panel position
+    let (panel_w, panel_h) = panel_size(0.4, 0.5, (300.0, 320.0), (480.0, 380.0));
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    
+    // Draw panel background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, RED);
+    
+    draw_text_ex("YOU DIED", panel_x + 10.0, panel_y + 40.0, TextParams {
+        font: None,
+        font_size: 32,
+        color: RED,
+        ..Default::default()
+    });
+    draw_text_ex(&format!("{} fell at level {} in {}", game.player.name, game.player.level, game.current_map.name),
+              panel_x + 10.0, panel_y + 80.0, TextParams {
         font: None,
         font_size: 18,
         color: WHITE,
         ..Default::default()
     });
-
-    // Display all options, highlight selected option
-    for (i, opt) in node.options.iter().enumerate() {
-        let y = panel_y + 100.0 + i as f32 * 28.0;
-        let color = if i == selected { YELLOW } else { GRAY };
-        let prefix = if i == selected { "> " } else { "  " };
-        draw_text_ex(&format!("{}{}", prefix, opt.text), panel_x + 30.0, y, TextParams {
+    
+    // How the run went
+    for (i, line) in game.stats.lines().iter().enumerate() {
+        draw_text_ex(line, panel_x + 20.0, panel_y + 115.0 + i as f32 * 22.0, TextParams {
             font: None,
             font_size: 18,
-            color,
+            color: LIGHTGRAY,
             ..Default::default()
         });
     }
-
-    // Draw hint
-    draw_text_ex("↑↓Select, Enter/Space Confirm, ESC Exit", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
+    
+    // Draw control hint
+    draw_text_ex("Press Enter to continue", panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
         font: None,
         font_size: 16,
         color: DARKGRAY,
@@ -1016,82 +9022,253 @@ fn draw_dialogue(game: &Game, npc_idx: usize, node_idx: usize, selected: usize)
     });
 }
 
-/// Draw combat interface
-fn draw_combat(game: &Game, npc_idx: usize) {
-    // Calculate centered combat panel position
-    let panel_w = 500.0;
-    let panel_h = 250.0;
+/// Draw the run statistics screen
+fn draw_statistics(game: &Game) {
+    // Calculate centered panel position
+    let panel_w = 360.0;
+    let panel_h = 260.0;
     let panel_x = (screen_width() - panel_w) / 2.0;
     let panel_y = (screen_height() - panel_h) / 2.0;
     
-    // Draw combat panel background and border (red border indicates combat)
+    // Draw panel background and border
     draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
-    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, RED);
-    
-    // Get enemy data
-    let npc = &game.npcs[npc_idx];
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, SKYBLUE);
     
-    // Display combat title
-    draw_text_ex("COMBAT", panel_x + 10.0, panel_y + 30.0, TextParams {
+    // Draw title
+    draw_text_ex("STATISTICS", panel_x + 10.0, panel_y + 30.0, TextParams {
         font: None,
         font_size: 24,
-        color: RED,
+        color: SKYBLUE,
         ..Default::default()
     });
     
-    // Display enemy information
-    draw_text_ex(&format!("Enemy: {}", npc.name), panel_x + 10.0, panel_y + 60.0, TextParams {
-        font: None,
-        font_size: 20,
-        color: ORANGE,
-        ..Default::default()
-    });
-    draw_text_ex(&format!("Enemy HP: {}/{}", npc.hp, npc.max_hp), 
-              panel_x + 10.0, panel_y + 85.0, TextParams {
+    for (i, line) in game.stats.lines().iter().enumerate() {
+        draw_text_ex(line, panel_x + 20.0, panel_y + 60.0 + i as f32 * 22.0, TextParams {
+            font: None,
+            font_size: 18,
+            color: WHITE,
+            ..Default::default()
+        });
+    }
+    
+    // Draw close hint
+    draw_text_ex("Press S to close", panel_x + 10.0, panel_y + panel_h - 12.0, TextParams {
         font: None,
-        font_size: 18,
-        color: WHITE,
+        font_size: 16,
+        color: DARKGRAY,
         ..Default::default()
     });
+}
+
+/// Draw a titled menu of options with the selection highlighted
+/// `subtitle` lines are shown between the title and the options
+fn draw_menu(title: &str, subtitle: &[String], options: &[&str], selected: usize, hint: &str) {
+    // Calculate centered panel position
+    let (panel_w, panel_h) = panel_size(0.4, 0.4, (300.0, 220.0), (480.0, 320.0));
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
     
-    // Display player information
-    draw_text_ex(&format!("Your HP: {}/{}", game.player.hp, game.player.max_hp), 
-              panel_x + 10.0, panel_y + 110.0, TextParams {
+    // Draw panel background and border
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, GREEN);
+    
+    // Draw title
+    draw_text_ex(title, panel_x + 10.0, panel_y + 30.0, TextParams {
         font: None,
-        font_size: 18,
-        color: WHITE,
+        font_size: 24,
+        color: GREEN,
         ..Default::default()
     });
     
-    // Display combat options
-    draw_text_ex("1: Attack", panel_x + 10.0, panel_y + 150.0, TextParams {
+    let mut y = panel_y + 60.0;
+    for line in subtitle {
+        draw_text_ex(line, panel_x + 10.0, y, TextParams {
+            font: None,
+            font_size: 18,
+            color: LIGHTGRAY,
+            ..Default::default()
+        });
+        y += 22.0;
+    }
+    
+    // List options, highlighting the selection
+    y += 10.0;
+    for (i, option) in options.iter().enumerate() {
+        let prefix = if i == selected { "> " } else { "  " };
+        draw_text_ex(&format!("{}{}", prefix, option), panel_x + 10.0, y + i as f32 * 25.0, TextParams {
+            font: None,
+            font_size: 20,
+            color: if i == selected { YELLOW } else { WHITE },
+            ..Default::default()
+        });
+    }
+    
+    // Draw control hint
+    draw_text_ex(hint, panel_x + 10.0, panel_y + panel_h - 20.0, TextParams {
         font: None,
-        font_size: 18,
-        color: YELLOW,
+        font_size: 16,
+        color: DARKGRAY,
         ..Default::default()
     });
-    draw_text_ex("2: Use Item", panel_x + 10.0, panel_y + 175.0, TextParams {
+}
+
+/// Draw the new game screen for picking a difficulty and seed
+fn draw_new_game(new_game: &NewGameState) {
+    let names: Vec<&str> = Difficulty::ALL.iter().map(|d| d.name()).collect();
+    let difficulty = Difficulty::ALL[new_game.selected];
+    let mut subtitle = vec![format!(
+        "Enemy HP {}%, damage {}%, loot {}%",
+        difficulty.enemy_hp_percent(),
+        difficulty.enemy_damage_percent(),
+        difficulty.loot_chance(),
+    )];
+    if let Some(interval) = difficulty.regen_interval() {
+        subtitle.push(format!("Regenerate 1 HP every {} turns", interval));
+    }
+    if new_game.seed_input.is_empty() {
+        subtitle.push("Seed: random (type digits to set one)".to_string());
+    } else {
+        subtitle.push(format!("Seed: {}_", new_game.seed_input));
+    }
+    subtitle.push(format!("World: {}", if new_game.classic_map { "Classic map" } else { "Generated from seed" }));
+    let class = PlayerClass::ALL[new_game.class];
+    subtitle.push(format!("Class: < {} > {}", class.name(), class.description()));
+    draw_menu("NEW GAME", &subtitle, &names, new_game.selected, "↑↓Select, ←→Class, 0-9 Seed, Tab World, Enter Start");
+}
+
+/// Draw the pause menu
+fn draw_pause_menu(game: &Game, selected: usize) {
+    let subtitle = [
+        format!("Difficulty: {}", game.difficulty.name()),
+        format!("Seed: {}", game.seed),
+    ];
+    let auto_pickup = format!("{}: {}", PAUSE_OPTIONS[1], if game.auto_pickup { "On" } else { "Off" });
+    let options = [PAUSE_OPTIONS[0], &auto_pickup, PAUSE_OPTIONS[2]];
+    draw_menu("PAUSED", &subtitle, &options, selected, "↑↓Select, Enter Confirm, P/ESC Resume");
+}
+
+/// Draw the prompt asking whether to really attack a friendly NPC
+fn draw_confirm_attack(game: &Game, npc_idx: usize) {
+    let panel_w = 400.0;
+    let panel_h = 90.0;
+    let panel_x = (screen_width() - panel_w) / 2.0;
+    let panel_y = (screen_height() - panel_h) / 2.0;
+    draw_rectangle(panel_x, panel_y, panel_w, panel_h, BLACK);
+    draw_rectangle_lines(panel_x, panel_y, panel_w, panel_h, 2.0, RED);
+    draw_text_ex(&format!("Really attack {}? (Y/N)", game.npcs[npc_idx].name), panel_x + 10.0, panel_y + 35.0, TextParams {
         font: None,
-        font_size: 18,
-        color: YELLOW,
+        font_size: 22,
+        color: RED,
         ..Default::default()
     });
-    draw_text_ex("3: Run", panel_x + 10.0, panel_y + 200.0, TextParams {
+    draw_text_ex("Killing townsfolk turns the whole town against you", panel_x + 10.0, panel_y + 65.0, TextParams {
         font: None,
-        font_size: 18,
-        color: YELLOW,
+        font_size: 16,
+        color: LIGHTGRAY,
         ..Default::default()
     });
 }
 
+/// Draw the map editor's cursor and a box naming the tile under it and the keys
+fn draw_editor(game: &Game, editor: &EditorState) {
+    let (x, y) = editor.cursor;
+    let screen_x = MAP_ORIGIN.0 + (x - game.camera_x) as f32 * TILE_SIZE;
+    let screen_y = MAP_ORIGIN.1 + (y - game.camera_y) as f32 * TILE_SIZE;
+    draw_rectangle_lines(screen_x, screen_y, TILE_SIZE, TILE_SIZE, 2.0, MAGENTA);
+    
+    let tile = game.current_map.written_tile(x, y);
+    let lines = [
+        format!("EDITOR  {} ({},{})", game.current_map.name, x, y),
+        format!("Tile: {}", tile.as_char()),
+        format!("Enemy: {} (Tab)", ENEMY_TEMPLATES[editor.template].name),
+        "Click/Right-click: Tile | E: Enemy | I: Item".to_string(),
+        "Enter: Entry | Ctrl+S: Save | F4/ESC: Exit".to_string(),
+    ];
+    let (box_w, box_h) = (340.0, 20.0 + lines.len() as f32 * 18.0);
+    let (box_x, box_y) = (screen_width() - box_w - 10.0, MAP_ORIGIN.1 + 10.0);
+    draw_rectangle(box_x, box_y, box_w, box_h, Color::new(0.0, 0.0, 0.0, 0.8));
+    draw_rectangle_lines(box_x, box_y, box_w, box_h, 1.0, MAGENTA);
+    for (i, line) in lines.iter().enumerate() {
+        draw_text_ex(line, box_x + 8.0, box_y + 20.0 + i as f32 * 18.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: if i == 0 { MAGENTA } else { WHITE },
+            ..Default::default()
+        });
+    }
+}
+
+/// Draw the F3 debug overlay: grid lines and coordinates on every visible tile,
+/// plus FPS, camera, player tile, map and game state in a corner box
+fn draw_debug_overlay(game: &Game) {
+    let (start_x, start_y) = MAP_ORIGIN;
+    let (view_w, view_h) = viewport_tiles();
+    let cols = view_w.min(game.current_map.width - game.camera_x.max(0));
+    let rows = view_h.min(game.current_map.height - game.camera_y.max(0));
+    let line_color = Color::new(1.0, 1.0, 0.0, 0.25);
+    let label_color = Color::new(1.0, 1.0, 0.0, 0.6);
+    
+    for row in 0..rows {
+        for col in 0..cols {
+            let (x, y) = (game.camera_x.max(0) + col, game.camera_y.max(0) + row);
+            let screen_x = start_x + (x - game.camera_x) as f32 * TILE_SIZE;
+            let screen_y = start_y + (y - game.camera_y) as f32 * TILE_SIZE;
+            draw_rectangle_lines(screen_x, screen_y, TILE_SIZE, TILE_SIZE, 1.0, line_color);
+            draw_text_ex(&format!("{},{}", x, y), screen_x + 1.0, screen_y + 8.0, TextParams {
+                font: None,
+                font_size: 8,
+                color: label_color,
+                ..Default::default()
+            });
+        }
+    }
+    
+    let lines = [
+        format!("FPS: {}", get_fps()),
+        format!("Camera: ({}, {})", game.camera_x, game.camera_y),
+        format!("Player: ({}, {})", game.player.x, game.player.y),
+        format!("Map: {} ({}x{}) seed {}", game.current_map.name, game.current_map.width, game.current_map.height, game.seed),
+        format!("State: {}", game.state.name()),
+    ];
+    let box_y = start_y + 4.0;
+    draw_rectangle(start_x + 4.0, box_y, 300.0, lines.len() as f32 * 18.0 + 8.0, Color::new(0.0, 0.0, 0.0, 0.75));
+    for (i, line) in lines.iter().enumerate() {
+        draw_text_ex(line, start_x + 10.0, box_y + 18.0 + i as f32 * 18.0, TextParams {
+            font: None,
+            font_size: 16,
+            color: YELLOW,
+            ..Default::default()
+        });
+    }
+}
+
 // ========== Main Loop ==========
 
+/// Window settings: starts at 800x600 and can be resized freely
+fn window_conf() -> Conf {
+    Conf {
+        window_title: "Fallout-style RPG".to_string(),
+        window_width: 800,
+        window_height: 600,
+        window_resizable: true,
+        ..Default::default()
+    }
+}
+
 /// Game main loop
 /// macroquad::main macro handles window creation and event loop
-#[macroquad::main("Fallout-style RPG")]
+#[macroquad::main(window_conf)]
 async fn main() {
-    // Create game instance
-    let mut game = Game::new();
+    // Map layouts are read once; every new run builds its towns and dungeons from them
+    let layouts = MapLayouts::load().await;
+    
+    // Create game instance; the run itself starts once a difficulty is picked
+    let mut game = Game::new(Difficulty::Normal, PlayerClass::Warrior, random_seed(), false, &layouts);
+    game.state = GameState::NewGame(NewGameState::new());
+    // Key bindings and achievements last across runs
+    game.config = Config::load_from_file();
+    game.unlocked_achievements = load_achievements();
+    game.achievements_path = Some(ACHIEVEMENTS_PATH);
 
     // Game main loop - executes once per frame
     loop {
@@ -1099,79 +9276,239 @@ async fn main() {
         clear_background(BLACK);
 
         // ========== Input Processing ==========
+        // Toggle the debug overlay from anywhere: F3 key
+        if is_key_pressed(KeyCode::F3) {
+            game.debug_overlay = !game.debug_overlay;
+        }
+        
         // Handle different inputs based on current game state
         match game.state {
             // Playing state: handle movement and open inventory
             GameState::Playing => {
+                // The last step finishes before the next one starts
+                game.advance_step(get_frame_time());
+                // Shift chords like Shift+S open screens rather than walk
+                let shift_down = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
                 // Move up: W key or up arrow
-                if is_key_pressed(KeyCode::W) || is_key_pressed(KeyCode::Up) {
+                if game.config.up_pressed() && !shift_down {
                     game.move_player(0, -1);
                 }
                 // Move down: S key or down arrow
-                if is_key_pressed(KeyCode::S) || is_key_pressed(KeyCode::Down) {
+                if game.config.down_pressed() && !shift_down {
                     game.move_player(0, 1);
                 }
                 // Move left: A key or left arrow
-                if is_key_pressed(KeyCode::A) || is_key_pressed(KeyCode::Left) {
+                if game.config.left_pressed() && !shift_down {
                     game.move_player(-1, 0);
                 }
                 // Move right: D key or right arrow
-                if is_key_pressed(KeyCode::D) || is_key_pressed(KeyCode::Right) {
+                if game.config.right_pressed() && !shift_down {
                     game.move_player(1, 0);
                 }
+                // Holding a direction keeps walking
+                game.repeat_held_movement(get_frame_time());
+                // Clicking a world map tile walks there; any key press calls the journey off
+                if get_last_key_pressed().is_some() {
+                    game.auto_travel = None;
+                }
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    let (mouse_x, mouse_y) = mouse_position();
+                    game.travel_to(
+                        game.camera_x + ((mouse_x - MAP_ORIGIN.0) / TILE_SIZE).floor() as i32,
+                        game.camera_y + ((mouse_y - MAP_ORIGIN.1) / TILE_SIZE).floor() as i32,
+                    );
+                }
+                game.step_auto_travel();
                 // Open inventory: I key
-                if is_key_pressed(KeyCode::I) {
-                    game.state = GameState::Inventory;
+                if is_key_pressed(game.config.open_inventory) {
+                    game.state = GameState::Inventory(0, InventoryTab::All);
+                }
+                // Use the item on a hotbar slot: number keys 1-5
+                if let Some(slot) = HOTBAR_KEYS.iter().position(|&key| is_key_pressed(key)) {
+                    game.use_hotbar(slot);
+                }
+                // C key: craft at a workbench, else open the character sheet
+                if is_key_pressed(KeyCode::C) {
+                    let tile = game.current_map.tiles[game.player.y as usize][game.player.x as usize];
+                    if tile == TileType::Workbench {
+                        game.state = GameState::Crafting(0, None);
+                    } else {
+                        game.state = GameState::CharacterSheet;
+                    }
+                }
+                // Close an adjacent open door: X key
+                if is_key_pressed(KeyCode::X) && !game.try_close_door() {
+                    game.add_message("There is no open door next to you.".to_string());
                 }
-                // Enter town/dungeon: Space key
-                if is_key_pressed(KeyCode::Space) {
+                // Spend saved skill points: U key
+                if is_key_pressed(KeyCode::U) && game.player.skill_points > 0 {
+                    game.state = GameState::LevelUp(LevelUpState::new());
+                }
+                // Open quest journal: J key
+                if is_key_pressed(game.config.open_quest_log) {
+                    game.state = GameState::Journal;
+                }
+                // Open bestiary: B key
+                if is_key_pressed(KeyCode::B) {
+                    game.state = GameState::Bestiary(0);
+                }
+                // Open run statistics: Shift+S
+                if is_key_pressed(KeyCode::S) && shift_down {
+                    game.state = GameState::Statistics;
+                }
+                // Open the map editor: F4 key, in debug builds or with --editor
+                if is_key_pressed(KeyCode::F4) && editor_enabled() {
+                    game.state = GameState::Editor(EditorState { cursor: (game.player.x, game.player.y), template: 0 });
+                }
+                // Open dialogue log: L key
+                if is_key_pressed(KeyCode::L) {
+                    game.state = GameState::DialogueLog(0);
+                }
+                // Drink from adjacent water: R key
+                if is_key_pressed(KeyCode::R) {
+                    game.drink();
+                }
+                // Pick up the item underfoot: G key
+                if is_key_pressed(KeyCode::G) {
+                    game.pick_up_item();
+                }
+                // Toggle sneaking: tap Shift on its own (Shift+M and Shift+S still open their screens)
+                if is_key_pressed(KeyCode::LeftShift) || is_key_pressed(KeyCode::RightShift) {
+                    game.shift_chorded = false;
+                }
+                if shift_down && get_keys_pressed().iter().any(|&key| key != KeyCode::LeftShift && key != KeyCode::RightShift) {
+                    game.shift_chorded = true;
+                }
+                if (is_key_released(KeyCode::LeftShift) || is_key_released(KeyCode::RightShift)) && !shift_down {
+                    if !game.shift_chorded {
+                        game.player.is_sneaking = !game.player.is_sneaking;
+                        let msg = if game.player.is_sneaking { "You start sneaking." } else { "You stop sneaking." };
+                        game.add_message(msg.to_string());
+                    }
+                    game.shift_chorded = false;
+                }
+                // Open the full-screen map: Shift+M
+                if is_key_pressed(KeyCode::M) && shift_down {
+                    game.state = GameState::MapView(MapViewState::new());
+                }
+                // Open fast travel menu: T key
+                if is_key_pressed(game.config.open_map) {
+                    game.open_fast_travel();
+                }
+                // Unlock an adjacent door, pull a lever, read a sign, open a container,
+                // or enter town/dungeon/building: Space key
+                if is_key_pressed(game.config.interact)
+                    && !game.try_unlock_door()
+                    && !game.try_pull_lever()
+                    && !game.try_read_sign()
+                    && !game.try_open_container()
+                {
                     game.try_enter_location();
                 }
-                // Return to world map: ESC key
+                // Go back out to the previous map: ESC key (pauses on the outermost map)
                 if is_key_pressed(KeyCode::Escape) {
-                    game.return_to_world_map();
+                    if game.location_stack.is_empty() {
+                        game.state = GameState::Paused(0);
+                    } else {
+                        game.return_to_previous_map();
+                    }
                 }
+                // Open pause menu: P key
+                if is_key_pressed(KeyCode::P) && game.pending_interaction.is_none() {
+                    game.state = GameState::Paused(0);
+                }
+                // Answer the pickpocket prompt: P steals, F talks
+                if is_key_pressed(KeyCode::P) && let Some(npc_idx) = game.take_pending_interaction() {
+                    attempt_pickpocket(&mut game, npc_idx);
+                }
+                // Wait a turn: Period key
+                if is_key_pressed(KeyCode::Period) {
+                    game.wait();
+                }
+                // Attack whoever the player faces, asking first if they are friendly: K key
+                if is_key_pressed(KeyCode::K) {
+                    game.request_attack();
+                }
+                // F key: talk to the NPC waiting on a choice, else disarm a spotted trap, else search
+                if is_key_pressed(KeyCode::F) {
+                    match game.take_pending_interaction() {
+                        Some(npc_idx) => game.start_dialogue(npc_idx),
+                        None => {
+                            if !game.try_disarm_trap() {
+                                game.search();
+                            }
+                        }
+                    }
+                }
+            }
+            
+            // Inventory state: handle item selection, use and closing
+            GameState::Inventory(..) => {
+                game.handle_inventory_input();
             }
             
-            // Inventory state: handle closing inventory
-            GameState::Inventory => {
-                // I key or ESC key closes inventory
-                if is_key_pressed(KeyCode::I) || is_key_pressed(KeyCode::Escape) {
+            // Crafting state: pick two ingredients at the workbench
+            GameState::Crafting(..) => {
+                game.handle_crafting_input();
+            }
+            
+            // Looting state: take items out of a container
+            GameState::Looting(..) => {
+                game.handle_looting_input();
+            }
+            
+            // Journal state: J key or ESC key closes it
+            GameState::Journal => {
+                if is_key_pressed(game.config.open_quest_log) || is_key_pressed(KeyCode::Escape) {
                     game.state = GameState::Playing;
                 }
             }
             
-            // Dialogue state: handle option selection and transitions
-            GameState::Dialogue(npc_idx, node_idx, selected) => {
-                let npc = &game.npcs[npc_idx];
-                let node = &npc.dialogue[node_idx];
-                let num_options = node.options.len();
-                
-                // Up/Down keys to select options
-                if is_key_pressed(KeyCode::Up) || is_key_pressed(KeyCode::W) {
-                    if selected > 0 {
-                        game.state = GameState::Dialogue(npc_idx, node_idx, selected - 1);
-                    }
+            // Bestiary state: Up/Down moves through entries, B key or ESC key closes it
+            GameState::Bestiary(_) => {
+                game.handle_bestiary_input();
+            }
+            
+            // Map editor state: edit the current map, F4 key or ESC key goes back
+            GameState::Editor(_) => {
+                game.handle_editor_input();
+            }
+            
+            // Attack prompt state: Y attacks, N or ESC backs off
+            GameState::ConfirmAttack(_) => {
+                game.handle_confirm_attack_input();
+            }
+            
+            // Statistics state: S key or ESC key closes it
+            GameState::Statistics => {
+                if is_key_pressed(KeyCode::S) || is_key_pressed(KeyCode::Escape) {
+                    game.state = GameState::Playing;
                 }
-                if is_key_pressed(KeyCode::Down) || is_key_pressed(KeyCode::S) {
-                    if selected + 1 < num_options {
-                        game.state = GameState::Dialogue(npc_idx, node_idx, selected + 1);
-                    }
+            }
+            
+            // Character sheet state: C key or ESC key closes it
+            GameState::CharacterSheet => {
+                if is_key_pressed(KeyCode::C) || is_key_pressed(KeyCode::Escape) {
+                    game.state = GameState::Playing;
                 }
-                
-                // Space or Enter to confirm selection
-                if is_key_pressed(KeyCode::Space) || is_key_pressed(KeyCode::Enter) {
-                    if let Some(next) = node.options[selected].next_node {
-                        // Jump to next node
-                        game.state = GameState::Dialogue(npc_idx, next, 0);
-                    } else {
-                        // End dialogue
-                        game.state = GameState::Playing;
-                    }
+            }
+            
+            // Dialogue state: handle option selection and transitions
+            GameState::Dialogue(_) => {
+                game.handle_dialogue_input();
+            }
+            
+            // Dialogue log state: scroll through past conversations
+            GameState::DialogueLog(scroll) => {
+                let total_lines = transcript_lines(&game.dialogue_log).len();
+                if (is_key_pressed(KeyCode::PageUp) || is_key_pressed(KeyCode::Up)) && scroll + 1 < total_lines {
+                    game.state = GameState::DialogueLog(scroll + 1);
                 }
-                
-                // ESC key exits dialogue
-                if is_key_pressed(KeyCode::Escape) {
+                if (is_key_pressed(KeyCode::PageDown) || is_key_pressed(KeyCode::Down)) && scroll > 0 {
+                    game.state = GameState::DialogueLog(scroll - 1);
+                }
+                // L key or ESC key closes the log
+                if is_key_pressed(KeyCode::L) || is_key_pressed(KeyCode::Escape) {
                     game.state = GameState::Playing;
                 }
             }
@@ -1180,27 +9517,100 @@ async fn main() {
             GameState::Combat(npc_idx) => {
                 // Option 1: Attack
                 if is_key_pressed(KeyCode::Key1) {
-                    // Calculate damage
-                    let damage = 15;
-                    game.npcs[npc_idx].hp -= damage;
-                    game.add_message(format!("You dealt {} damage!", damage));
-                    
-                    // Check if enemy is defeated
-                    if game.npcs[npc_idx].hp <= 0 {
-                        game.add_message(format!("{} defeated!", game.npcs[npc_idx].name));
-                        game.npcs.remove(npc_idx);  // Remove enemy from game
-                        game.state = GameState::Playing;
-                    } else {
-                        // Enemy counterattack
-                        let enemy_damage = 10;
-                        game.player.hp -= enemy_damage;
-                        game.add_message(format!("Enemy dealt {} damage!", enemy_damage));
-                    }
+                    game.player_attack(npc_idx);
                 }
                 
                 // Option 3: Run
                 if is_key_pressed(KeyCode::Key3) {
-                    game.add_message("You ran away!".to_string());
+                    game.flee_combat();
+                }
+                
+                // Option 5: Throw
+                if is_key_pressed(KeyCode::Key5) {
+                    if game.player.throwables().is_empty() {
+                        game.add_message("You have nothing to throw.".to_string());
+                    } else {
+                        game.state = GameState::Throwing(npc_idx, 0);
+                    }
+                }
+            }
+            
+            // Throwing state: pick what to throw at the enemy
+            GameState::Throwing(..) => {
+                game.handle_throwing_input();
+            }
+            
+            // Level-up state: assign skill points to stats
+            GameState::LevelUp(_) => {
+                game.handle_level_up_input();
+            }
+            
+            // Perk selection state: pick one of the offered perks
+            GameState::PerkSelection(..) => {
+                game.handle_perk_selection_input();
+            }
+            
+            // Fast travel state: pick a discovered town
+            GameState::FastTravel(_) => {
+                game.handle_fast_travel_input();
+            }
+            
+            // Map view state: zoom and pan the full-screen map
+            GameState::MapView(_) => {
+                game.handle_map_view_input();
+            }
+            
+            // Reading state: scroll a sign or note
+            GameState::Reading(_) => {
+                game.handle_reading_input();
+            }
+            
+            // Game over state: Enter goes back to the new game screen
+            GameState::GameOver => {
+                if is_key_pressed(KeyCode::Enter) {
+                    game.state = GameState::NewGame(NewGameState::new());
+                }
+            }
+            
+            // New game state: pick a difficulty, optionally type a seed, and start a fresh run
+            GameState::NewGame(_) => {
+                if let Some((difficulty, class, seed, classic_map)) = game.handle_new_game_input() {
+                    // Preferences carry over into the new run
+                    let auto_pickup = game.auto_pickup;
+                    let debug_overlay = game.debug_overlay;
+                    let config = game.config.clone();
+                    let achievements = std::mem::take(&mut game.unlocked_achievements);
+                    let achievements_path = game.achievements_path;
+                    game = Game::new(difficulty, class, seed, classic_map, &layouts);
+                    game.auto_pickup = auto_pickup;
+                    game.debug_overlay = debug_overlay;
+                    game.config = config;
+                    game.unlocked_achievements = achievements;
+                    game.achievements_path = achievements_path;
+                    game.state = GameState::CharacterCreation(CreationState::new(&game.player.stats));
+                }
+            }
+            
+            // Character creation state: spend the starting points, then play
+            GameState::CharacterCreation(_) => {
+                game.handle_creation_input();
+            }
+            
+            // Paused state: resume, toggle auto-pickup or abandon the run
+            GameState::Paused(selected) => {
+                if game.config.up_pressed() && selected > 0 {
+                    game.state = GameState::Paused(selected - 1);
+                }
+                if game.config.down_pressed() && selected + 1 < PAUSE_OPTIONS.len() {
+                    game.state = GameState::Paused(selected + 1);
+                }
+                if is_key_pressed(KeyCode::Enter) {
+                    match selected {
+                        0 => game.state = GameState::Playing,
+                        1 => game.auto_pickup = !game.auto_pickup,
+                        _ => game.state = GameState::NewGame(NewGameState::new()),
+                    }
+                } else if is_key_pressed(KeyCode::P) || is_key_pressed(KeyCode::Escape) {
                     game.state = GameState::Playing;
                 }
             }
@@ -1208,7 +9618,40 @@ async fn main() {
         
         // ========== Update Game State ==========
         // Update camera position to follow player
-        game.update_camera();
+        game.update_camera(get_frame_time());
+        
+        // Held movement starts over after any menu, fight or conversation
+        if !matches!(game.state, GameState::Playing) {
+            game.held_move = None;
+        }
+        
+        // Advance tile animations, wrapping before the float loses precision
+        game.animation_timer = (game.animation_timer + get_frame_time()) % 1000.0;
+        game.update_floaters(get_frame_time());
+        if let Some((_, remaining)) = &mut game.achievement_popup {
+            *remaining -= get_frame_time();
+            if *remaining <= 0.0 {
+                game.achievement_popup = None;
+            }
+        }
+        
+        // The +1 popup from healing fades out
+        game.regen_popup += get_frame_time();
+        
+        // The day clock and weather only run while exploring
+        if matches!(game.state, GameState::Playing) {
+            game.stats.total_time_secs += get_frame_time();
+            game.time_of_day += get_frame_time() / DAY_LENGTH;
+            if game.time_of_day >= 1.0 {
+                game.time_of_day -= 1.0;
+                game.day += 1;
+                if game.day >= 7 {
+                    check_achievement(&mut game, "survivor");
+                }
+            }
+            game.update_weather(get_frame_time());
+            game.update_detection(get_frame_time());
+        }
         
         // ========== Rendering ==========
         // Draw main game interface (map, NPCs, player)
@@ -1216,16 +9659,535 @@ async fn main() {
         
         // Draw UI elements (status bar, message log)
         draw_ui(&game);
+        draw_hotbar(&game);
         
         // Draw additional interfaces based on current state
-        match game.state {
-            GameState::Inventory => draw_inventory(&game),         // Inventory interface
-            GameState::Dialogue(npc_idx, node_idx, selected) => draw_dialogue(&game, npc_idx, node_idx, selected), // Dialogue interface
-            GameState::Combat(idx) => draw_combat(&game, idx),     // Combat interface
+        match &game.state {
+            GameState::Inventory(selected, tab) => draw_inventory(&game, *selected, *tab), // Inventory interface
+            GameState::Crafting(cursor, first) => draw_crafting(&game, *cursor, *first), // Workbench interface
+            GameState::Looting(pos, selected) => draw_looting(&game, *pos, *selected), // Container contents
+            GameState::Dialogue(dialogue) => draw_dialogue(&game, dialogue), // Dialogue interface
+            GameState::DialogueLog(scroll) => draw_dialogue_log(&game, *scroll), // Dialogue log interface
+            GameState::Combat(idx) => draw_combat(&game, *idx, None), // Combat interface
+            GameState::Throwing(idx, selected) => draw_combat(&game, *idx, Some(*selected)), // Combat with the throw list open
+            GameState::LevelUp(level_up) => draw_level_up(&game, level_up), // Level-up interface
+            GameState::PerkSelection(choices, selected) => draw_perk_selection(choices, *selected), // Perk selection interface
+            GameState::CharacterSheet => draw_character_sheet(&game), // Character sheet interface
+            GameState::Journal => draw_journal(&game),              // Quest journal interface
+            GameState::Bestiary(selected) => draw_bestiary(&game, *selected), // Enemies fought so far
+            GameState::Statistics => draw_statistics(&game),        // Run statistics
+            GameState::ConfirmAttack(idx) => draw_confirm_attack(&game, *idx), // Attack prompt
+            GameState::Editor(editor) => draw_editor(&game, editor), // Map editor cursor and tools
+            GameState::FastTravel(selected) => draw_fast_travel(&game, *selected), // Fast travel interface
+            GameState::MapView(view) => draw_map_view(&game, view), // Full-screen map
+            GameState::Reading(reading) => draw_reading(reading),   // Sign or note text
+            GameState::GameOver => draw_game_over(&game),           // Game over screen
+            GameState::NewGame(new_game) => draw_new_game(new_game), // New game screen
+            GameState::CharacterCreation(creation) => draw_character_creation(&game, creation), // Starting stats
+            GameState::Paused(selected) => draw_pause_menu(&game, *selected), // Pause menu
             _ => {}  // Playing state doesn't need extra interfaces
         }
         
+        // Debug overlay goes on top of everything
+        if game.debug_overlay {
+            draw_debug_overlay(&game);
+        }
+        
         // Wait for next frame (controls frame rate, handles system events)
         next_frame().await;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Build a map from a tile grid drawn the way map files draw it
+    fn grid(map_type: MapType, rows: &str) -> GameMap {
+        GameMap::from_text("Test", map_type, &format!("---\n{}", rows)).expect("test map should parse")
+    }
+    
+    /// Fresh run on the classic world map with a fixed seed
+    fn new_game() -> Game {
+        let layouts = MapLayouts {
+            town: GameMap::from_text("Town", MapType::Town, TOWN_MAP).expect("built-in town should parse"),
+            cellar: GameMap::from_text("Cellar", MapType::Interior, CELLAR_MAP).expect("built-in cellar should parse"),
+            saved: HashMap::new(),
+        };
+        Game::new(Difficulty::Normal, PlayerClass::Warrior, 42, true, &layouts)
+    }
+    
+    /// Walk onto the entrance leading to a map and go in
+    fn enter(game: &mut Game, map_type: MapType, map_id: usize) {
+        let pos = game.current_map.entrances.iter()
+            .find(|&(_, &dest)| dest == (map_type, map_id))
+            .map(|(&pos, _)| pos)
+            .expect("the current map should lead there");
+        (game.player.x, game.player.y) = pos;
+        game.try_enter_location();
+    }
+    
+    /// A small maze whose only way from the top left to the bottom right winds through every corridor
+    const MAZE: &str = concat!(
+        "#######\n",
+        "#.....#\n",
+        "#####.#\n",
+        "#.....#\n",
+        "#.#####\n",
+        "#.....#\n",
+        "#######",
+    );
+    
+    #[test]
+    fn find_path_winds_through_a_maze() {
+        let map = grid(MapType::Dungeon, MAZE);
+        let path = map.find_path((1, 1), (5, 5)).expect("the maze has a way through");
+        assert_eq!(path.len(), 16);
+        assert_eq!(path.last(), Some(&(5, 5)));
+        let mut at = (1, 1);
+        for &step in &path {
+            assert_eq!((step.0 - at.0).abs() + (step.1 - at.1).abs(), 1);
+            assert!(map.is_walkable(step.0, step.1));
+            at = step;
+        }
+    }
+    
+    #[test]
+    fn find_path_fails_when_the_maze_is_closed_off() {
+        let map = grid(MapType::Dungeon, &MAZE.replacen("#.....#\n#.#####", "#.....#\n#######", 1));
+        assert_eq!(map.find_path((1, 1), (5, 5)), None);
+    }
+    
+    #[test]
+    fn find_path_on_the_world_map_keeps_to_explored_land() {
+        let mut map = grid(MapType::WorldMap, "\"\"\"\"\"\n\"\"\"\"\"\n\"\"\"\"\"");
+        for x in 0..5 {
+            map.explored[0][x] = true;
+        }
+        map.explored[1][4] = true;
+        // The short way along the middle row is unexplored, so the path keeps to the top row
+        let path = map.find_path((0, 1), (4, 1)).expect("the top row is explored");
+        assert_eq!(path, vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (4, 1)]);
+        map.explored[0][4] = false;
+        assert_eq!(map.find_path((0, 1), (4, 1)), None);
+    }
+    
+    #[test]
+    fn picked_up_items_stay_gone_after_leaving_and_coming_back() {
+        let mut game = new_game();
+        enter(&mut game, MapType::Town, 0);
+        (game.player.x, game.player.y) = (11, 15);
+        game.pick_up_item();
+        assert!(game.player.item_index("Town Supply").is_some());
+        
+        game.return_to_previous_map();
+        assert!(game.current_map.map_type == MapType::WorldMap);
+        enter(&mut game, MapType::Town, 0);
+        assert!(!game.current_map.items.values().any(|item| item.name == "Town Supply"));
+    }
+    
+    #[test]
+    fn location_stack_pushes_and_pops_two_levels() {
+        let mut game = new_game();
+        let start = (game.player.x, game.player.y);
+        enter(&mut game, MapType::Town, 1);
+        enter(&mut game, MapType::Interior, 2);
+        let hatch_pos = game.location_stack.last().map(|loc| (loc.x, loc.y));
+        assert_eq!(game.location_stack.len(), 2);
+        assert!(game.current_map.map_type == MapType::Interior);
+        assert_eq!(game.current_map_id, 2);
+        
+        game.return_to_previous_map();
+        assert!(game.current_map.map_type == MapType::Town);
+        assert_eq!(game.current_map_id, 1);
+        assert_eq!(Some((game.player.x, game.player.y)), hatch_pos);
+        
+        game.return_to_previous_map();
+        assert!(game.current_map.map_type == MapType::WorldMap);
+        assert!(game.location_stack.is_empty());
+        assert_ne!((game.player.x, game.player.y), start);
+        assert!(game.current_map.entrances.get(&(game.player.x, game.player.y)) == Some(&(MapType::Town, 1)));
+    }
+    
+    /// Give a player a single healing item and use it, returning the HP gained
+    fn heal_with(player: &mut Player, item_type: ItemType) -> i32 {
+        player.add_item(Item {
+            name: "Test Remedy".to_string(),
+            char: "!",
+            item_type,
+            durability: None,
+            quantity: 1,
+        });
+        let before = player.hp;
+        let idx = player.item_index("Test Remedy").expect("the item was just added");
+        player.use_item(idx);
+        player.hp - before
+    }
+    
+    #[test]
+    fn potions_scale_with_max_hp_while_consumables_heal_a_flat_amount() {
+        let mut player = new_game().player;
+        for (max_hp, potion_heal) in [(40, 10), (100, 25), (250, 62)] {
+            player.max_hp = max_hp;
+            player.hp = 1;
+            assert_eq!(heal_with(&mut player, ItemType::Potion { heal_pct: 25 }), potion_heal);
+            player.hp = 1;
+            let flat = ItemType::Consumable { heal: 30, hunger_restore: 0, thirst_restore: 0 };
+            assert_eq!(heal_with(&mut player, flat), 30);
+        }
+        
+        // Neither kind heals past max HP
+        player.max_hp = 100;
+        player.hp = 95;
+        assert_eq!(heal_with(&mut player, ItemType::Potion { heal_pct: 25 }), 5);
+        player.hp = 80;
+        let flat = ItemType::Consumable { heal: 30, hunger_restore: 0, thirst_restore: 0 };
+        assert_eq!(heal_with(&mut player, flat), 20);
+    }
+    
+    #[test]
+    fn field_of_view_sees_an_open_room_up_to_its_radius() {
+        let map = grid(MapType::Dungeon, "#########\n#.......#\n#########");
+        let visible = map.field_of_view(1, 1, 3);
+        assert!(visible.contains(&(1, 1)));
+        assert!(visible.contains(&(4, 1)));
+        assert!(!visible.contains(&(5, 1)));
+        
+        let map = grid(MapType::Dungeon, "#####\n#...#\n#...#\n#...#\n#####");
+        let visible = map.field_of_view(2, 2, 5);
+        assert_eq!(visible.len(), 25);
+    }
+    
+    #[test]
+    fn field_of_view_walls_are_seen_but_hide_what_lies_behind() {
+        let map = grid(MapType::Dungeon, "#####\n#...#\n#.#.#\n#...#\n#####");
+        let visible = map.field_of_view(1, 1, 5);
+        assert!(visible.contains(&(2, 2)));
+        assert!(!visible.contains(&(3, 3)));
+        assert!(visible.contains(&(3, 1)));
+        assert!(visible.contains(&(1, 3)));
+    }
+    
+    #[test]
+    fn field_of_view_does_not_slip_through_a_diagonal_wall_gap() {
+        let map = grid(MapType::Dungeon, "......\n......\n...#..\n..#...\n......\n......");
+        let visible = map.field_of_view(1, 4, 8);
+        assert!(visible.contains(&(2, 3)));
+        assert!(!visible.contains(&(4, 1)));
+        assert!(!visible.contains(&(5, 0)));
+        // Off to either side of the gap stays in view
+        assert!(visible.contains(&(1, 0)));
+        assert!(visible.contains(&(5, 4)));
+    }
+    
+    #[test]
+    fn npcs_never_share_a_tile_but_can_stand_on_items() {
+        let mut game = new_game();
+        game.npcs = vec![Npc::spawn_enemy(&RAIDER, 5, 5), Npc::spawn_enemy(&RAIDER, 6, 5)];
+        assert_eq!(game.tile_occupant(5, 5), Some(0));
+        assert_eq!(game.tile_occupant(6, 5), Some(1));
+        assert_eq!(game.tile_occupant(7, 5), None);
+        
+        // Stepping onto the other NPC is refused
+        assert!(!game.move_npc(0, 1, 0));
+        assert_eq!((game.npcs[0].x, game.npcs[0].y), (5, 5));
+        
+        // Items stay put under an NPC and are still there once it moves on
+        let stimpak = Item {
+            name: "Stimpak".to_string(),
+            char: "+",
+            item_type: ItemType::Consumable { heal: 30, hunger_restore: 0, thirst_restore: 0 },
+            durability: None,
+            quantity: 1,
+        };
+        game.current_map.items.insert((7, 5), stimpak);
+        assert!(game.move_npc(1, 1, 0));
+        assert_eq!(game.tile_occupant(7, 5), Some(1));
+        assert!(game.move_npc(1, 1, 0));
+        (game.player.x, game.player.y) = (7, 5);
+        game.pick_up_item();
+        assert!(game.player.item_index("Stimpak").is_some());
+    }
+    
+    #[test]
+    fn generated_dungeons_are_walled_in_and_fully_connected() {
+        for seed in 0..20 {
+            let map = GameMap::generate_dungeon(seed, 1 + seed as u32 % 3, Difficulty::Normal);
+            let (w, h) = (map.width as usize, map.height as usize);
+            for x in 0..w {
+                assert!(map.tiles[0][x] == TileType::Wall && map.tiles[h - 1][x] == TileType::Wall, "seed {}", seed);
+            }
+            for row in &map.tiles {
+                assert!(row[0] == TileType::Wall && row[w - 1] == TileType::Wall, "seed {}", seed);
+            }
+            
+            // Every floor tile is reachable from the spawn through doors (locked or secret ones too),
+            // weak walls and lever gates
+            let mut tiles = map.tiles.clone();
+            for &(x, y) in map.links.iter().flat_map(|link| &link.targets) {
+                tiles[y as usize][x as usize] = TileType::Floor;
+            }
+            let reached = flood_fill(&tiles, map.entry_point, |tile| tile != TileType::Wall);
+            for (y, row) in map.tiles.iter().enumerate() {
+                for (x, &tile) in row.iter().enumerate() {
+                    assert!(tile != TileType::Floor || reached[y][x], "seed {}: ({}, {}) is cut off", seed, x, y);
+                }
+            }
+        }
+    }
+    
+    /// World map sites: the spawn point followed by every entrance, in no particular order
+    fn world_sites(map: &GameMap) -> Vec<(i32, i32)> {
+        std::iter::once(map.entry_point).chain(map.entrances.keys().copied()).collect()
+    }
+    
+    #[test]
+    fn generated_worlds_spread_out_reachable_entrances() {
+        for seed in 0..20 {
+            let map = GameMap::generate_world(seed, 80, 40);
+            assert_eq!(map.entrances.len(), WORLD_TOWNS + WORLD_DUNGEONS, "seed {}", seed);
+            let (ex, ey) = map.entry_point;
+            assert!(map.is_walkable(ex, ey), "seed {}: spawn on a blocked tile", seed);
+            
+            let sites = world_sites(&map);
+            for (i, &(ax, ay)) in sites.iter().enumerate() {
+                for &(bx, by) in &sites[i + 1..] {
+                    let (dx, dy) = ((ax - bx) as f32, (ay - by) as f32);
+                    assert!((dx * dx + dy * dy).sqrt() >= 10.0, "seed {}: ({}, {}) and ({}, {}) too close", seed, ax, ay, bx, by);
+                }
+            }
+            
+            let reached = flood_fill(&map.tiles, map.entry_point, |tile| tile.is_walkable());
+            for (&(x, y), &(map_type, _)) in &map.entrances {
+                let tile = map.tiles[y as usize][x as usize];
+                assert!(tile == if map_type == MapType::Town { TileType::Town } else { TileType::Dungeon });
+                assert!(reached[y as usize][x as usize], "seed {}: entrance at ({}, {}) is cut off", seed, x, y);
+            }
+        }
+    }
+    
+    #[test]
+    fn same_seed_builds_the_same_world() {
+        let a = GameMap::generate_world(1234, 80, 40);
+        let b = GameMap::generate_world(1234, 80, 40);
+        let c = GameMap::generate_world(1235, 80, 40);
+        assert!(a.tiles == b.tiles && a.entry_point == b.entry_point && a.entrances == b.entrances);
+        assert!(a.tiles != c.tiles);
+    }
+    
+    #[test]
+    fn classic_world_keeps_its_hand_made_layout() {
+        let map = GameMap::new_world_map();
+        assert_eq!(map.entrances.len(), WORLD_TOWNS + WORLD_DUNGEONS);
+        assert!(map.tiles[7][25] == TileType::Mountain);
+        assert!(map.tiles[20][15] == TileType::Forest);
+        assert!(map.tiles[32][50] == TileType::Water);
+        let reached = flood_fill(&map.tiles, map.entry_point, |tile| tile.is_walkable());
+        assert!(map.entrances.keys().all(|&(x, y)| reached[y as usize][x as usize]));
+    }
+    
+    #[test]
+    fn stepping_onto_forest_advances_the_clock_by_two() {
+        let mut game = new_game();
+        game.npcs.clear();
+        (game.player.x, game.player.y) = (9, 15);
+        assert!(game.current_map.tiles[15][10] == TileType::Forest);
+        
+        let start = game.turns;
+        game.move_player(1, 0);
+        assert_eq!((game.player.x, game.player.y), (10, 15));
+        assert_eq!(game.turns, start + 2);
+        
+        // Back out onto grass costs the usual single turn
+        game.move_accumulator = 0.0;
+        game.move_player(-1, 0);
+        assert_eq!(game.turns, start + 3);
+        
+        // Bumping into a mountain doesn't pass any time
+        (game.player.x, game.player.y) = (19, 7);
+        game.move_accumulator = 0.0;
+        game.move_player(1, 0);
+        assert_eq!((game.player.x, game.player.y), (19, 7));
+        assert_eq!(game.turns, start + 3);
+    }
+    
+    #[test]
+    fn perception_ten_always_finds_an_adjacent_secret_door_by_searching() {
+        let mut game = new_game();
+        enter(&mut game, MapType::Dungeon, 0);
+        game.npcs.clear();
+        game.player.stats.perception = 10;
+        let door = *game.current_map.secret_doors().first().expect("every dungeon hides a secret room");
+        let (x, y) = door;
+        let spot = [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].into_iter()
+            .find(|&(x, y)| game.current_map.is_walkable(x, y))
+            .expect("a secret door opens onto walkable floor");
+        for _ in 0..100 {
+            (game.player.x, game.player.y) = spot;
+            game.current_map.tiles[door.1 as usize][door.0 as usize] = TileType::SecretDoor;
+            game.current_map.doors.remove(&door);
+            game.search();
+            assert!(game.current_map.tiles[door.1 as usize][door.0 as usize] == TileType::Door);
+        }
+    }
+    
+    #[test]
+    fn fog_at_night_never_blacks_out_the_players_surroundings() {
+        let mut game = new_game();
+        game.player.stats.perception = 1;
+        game.current_weather = Weather::Fog;
+        game.time_of_day = 0.0;
+        assert!(game.sight_penalty() >= game.player.stats.perception * 2);
+        let (x, y) = (game.player.x, game.player.y);
+        assert!(game.light_level(x, y) > 0.0);
+        assert!(game.light_level(x + 1, y) > 0.0);
+        assert!(game.light_level(x, y - 1) > 0.0);
+    }
+    
+    #[test]
+    fn only_chasers_that_lost_the_player_give_up() {
+        let mut game = new_game();
+        let (px, py) = (game.player.x, game.player.y);
+        let (fx, fy) = game.current_map.nearest_walkable(px + 30, py);
+        let mut spotted_by_meter = Npc::spawn_enemy(&RAIDER, fx, fy);
+        spotted_by_meter.alert_state = AlertState::Alerted;
+        let mut lost_track = spotted_by_meter.clone();
+        lost_track.last_seen = Some(0);
+        game.npcs = vec![spotted_by_meter, lost_track];
+        game.turns = LOSE_TRACK_TURNS;
+        
+        game.move_enemies();
+        assert!(game.npcs[0].alert_state == AlertState::Alerted);
+        assert!(game.npcs[1].alert_state == AlertState::Unaware);
+    }
+    
+    #[test]
+    fn town_guards_are_posted_once_and_cost_no_karma_to_kill() {
+        let mut game = new_game();
+        game.adjust_reputation(Faction::Townsfolk, REPUTATION_HOSTILE);
+        enter(&mut game, MapType::Town, 1);
+        let guards = |game: &Game| game.npcs.iter().filter(|npc| npc.name == TOWN_GUARD.name).count();
+        assert_eq!(guards(&game), TOWN_GUARDS);
+        
+        let karma = game.karma;
+        let guard = game.npcs.iter().position(|npc| npc.name == TOWN_GUARD.name).expect("guards were posted");
+        game.defeat_enemy(guard);
+        assert_eq!(game.karma, karma);
+        
+        game.return_to_previous_map();
+        enter(&mut game, MapType::Town, 1);
+        assert_eq!(guards(&game), TOWN_GUARDS - 1);
+    }
+    
+    #[test]
+    fn fleeing_on_the_world_map_drops_ambushers_but_keeps_people_the_player_attacked() {
+        let mut game = new_game();
+        let (x, y) = (game.player.x, game.player.y);
+        game.npcs.clear();
+        game.npcs.push(Npc::spawn_enemy(ENEMY_TEMPLATES[0], x + 1, y));
+        let mut victim = Npc::spawn_enemy(&TOWN_GUARD, x - 1, y);
+        victim.faction = Some(Faction::Townsfolk);
+        game.npcs.push(victim);
+        
+        game.start_combat(1);
+        game.flee_combat();
+        assert_eq!(game.npcs.len(), 1);
+        assert!(game.npcs[0].faction == Some(Faction::Townsfolk));
+    }
+    
+    #[test]
+    fn npcs_away_by_their_schedule_do_not_move_or_chase() {
+        let mut game = new_game();
+        let (px, py) = (game.player.x, game.player.y);
+        let (x, y) = game.current_map.nearest_walkable(px + 3, py);
+        let mut absent = Npc::spawn_enemy(&RAIDER, x, y);
+        absent.alert_state = AlertState::Alerted;
+        absent.schedule = vec![ScheduleEntry { start: 0.0, end: 1.0, anchor: None, asleep: false }];
+        game.npcs = vec![absent];
+        
+        game.move_enemies();
+        assert_eq!((game.npcs[0].x, game.npcs[0].y), (x, y));
+    }
+    
+    #[test]
+    fn caves_post_their_guard_and_beast_along_the_way_in() {
+        let mut game = new_game();
+        game.current_map = game.dungeon_maps[1].clone();
+        game.load_dungeon_npcs();
+        assert_eq!(game.npcs.len(), 2);
+        let map = &game.current_map;
+        let spots = game.npcs.iter().flat_map(|npc| npc.patrol.iter().copied().chain([(npc.x, npc.y)]));
+        for spot in spots {
+            assert!(map.is_walkable(spot.0, spot.1));
+            assert!(map.find_path(map.entry_point, spot).is_some());
+        }
+    }
+    
+    #[test]
+    fn a_step_asked_for_mid_stride_is_taken_once_the_stride_ends() {
+        let mut game = new_game();
+        game.npcs.clear();
+        (game.player.x, game.player.y) = (9, 15);
+        game.move_player(1, 0);
+        assert_eq!((game.player.x, game.player.y), (10, 15));
+        
+        // Forest strides take one and a half steps, and the next one waits for it
+        game.move_player(-1, 0);
+        game.advance_step(BASE_MOVE_INTERVAL);
+        assert_eq!((game.player.x, game.player.y), (10, 15));
+        game.advance_step(BASE_MOVE_INTERVAL / 2.0);
+        assert_eq!((game.player.x, game.player.y), (9, 15));
+    }
+    
+    #[test]
+    fn every_generated_dungeon_keeps_its_vault_stash() {
+        for seed in 0..100 {
+            let map = GameMap::generate_dungeon(seed, 1 + seed as u32 % 3, Difficulty::Normal);
+            let stashed = map.containers.values()
+                .any(|chest| chest.contents.iter().any(|item| item.name == "Vault Stash"));
+            assert!(stashed, "seed {} lost its stash", seed);
+        }
+    }
+    
+    #[test]
+    fn loading_a_map_twice_reuses_its_item_glyphs() {
+        let first = GameMap::from_text("Town", MapType::Town, TOWN_MAP).expect("built-in town should parse");
+        let second = GameMap::from_text("Town", MapType::Town, TOWN_MAP).expect("built-in town should parse");
+        for (pos, item) in &first.items {
+            assert!(std::ptr::eq(item.char, second.items[pos].char));
+        }
+    }
+    
+    #[test]
+    fn enemies_left_standing_after_a_throw_take_their_turn() {
+        let mut game = new_game();
+        let (px, py) = (game.player.x, game.player.y);
+        let mut target = Npc::spawn_enemy(&RAIDER, px + 1, py);
+        target.hp = 1;
+        let mut straggler = Npc::spawn_enemy(&RAIDER, px, py + 4);
+        straggler.alert_state = AlertState::Alerted;
+        game.npcs = vec![target, straggler];
+        game.player.add_item(Item {
+            name: "Test Grenade".to_string(),
+            char: "*",
+            item_type: ItemType::Throwable { damage: 5, radius: 0 },
+            durability: None,
+            quantity: 1,
+        });
+        
+        game.state = GameState::Throwing(0, 0);
+        let idx = game.player.item_index("Test Grenade").expect("the grenade was just added");
+        game.throw_item(0, idx);
+        assert_eq!(game.npcs.len(), 1);
+        assert!((game.npcs[0].y - py).abs() < 4);
+    }
+    
+    #[test]
+    fn town_signs_point_the_way_to_a_dungeon() {
+        let mut game = new_game();
+        enter(&mut game, MapType::Town, 0);
+        let pointer = game.current_map.signs.values()
+            .find(|text| text.contains("Dungeon #") && text.contains("paces"));
+        assert!(pointer.is_some(), "no sign points to a dungeon: {:?}", game.current_map.signs);
+    }
+}
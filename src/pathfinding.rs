@@ -0,0 +1,109 @@
+//! A* pathfinding over a map's walkable tiles
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::GameMap;
+
+/// Most tiles A* will expand before giving up on a path
+const ASTAR_MAX_NODES: usize = 2000;
+
+/// Shortest four-way path over walkable tiles from `from` to `to`, avoiding `blocked`
+/// The path leaves out `from` and ends on `to`; None if there is no path within ASTAR_MAX_NODES
+pub fn astar(map: &GameMap, from: (i32, i32), to: (i32, i32), blocked: &HashSet<(i32, i32)>) -> Option<Vec<(i32, i32)>> {
+    let distance = |(x, y): (i32, i32)| (x - to.0).abs() + (y - to.1).abs();
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+    let mut cost: HashMap<(i32, i32), i32> = HashMap::from([(from, 0)]);
+    open.push(Reverse((distance(from), 0, from)));
+    
+    let mut expanded = 0;
+    while let Some(Reverse((_, g, pos))) = open.pop() {
+        if pos == to {
+            let mut path = vec![pos];
+            let mut step = pos;
+            while let Some(&prev) = came_from.get(&step) {
+                if prev == from {
+                    break;
+                }
+                path.push(prev);
+                step = prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        // Skip stale heap entries for tiles already reached more cheaply
+        if g > cost[&pos] {
+            continue;
+        }
+        expanded += 1;
+        if expanded > ASTAR_MAX_NODES {
+            return None;
+        }
+        for next in [(pos.0 + 1, pos.1), (pos.0 - 1, pos.1), (pos.0, pos.1 + 1), (pos.0, pos.1 - 1)] {
+            if !map.is_walkable(next.0, next.1) || (blocked.contains(&next) && next != to) {
+                continue;
+            }
+            if cost.get(&next).is_none_or(|&known| g + 1 < known) {
+                cost.insert(next, g + 1);
+                came_from.insert(next, pos);
+                open.push(Reverse((g + 1 + distance(next), g + 1, next)));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MapType;
+    
+    /// Build a map from a tile grid drawn the way map files draw it
+    fn grid(rows: &str) -> GameMap {
+        GameMap::from_text("Test", MapType::Dungeon, &format!("---\n{}", rows)).expect("test map should parse")
+    }
+    
+    #[test]
+    fn straight_corridor() {
+        let map = grid("#######\n#.....#\n#######");
+        let path = astar(&map, (1, 1), (5, 1), &HashSet::new()).expect("corridor is open");
+        assert_eq!(path, vec![(2, 1), (3, 1), (4, 1), (5, 1)]);
+    }
+    
+    #[test]
+    fn u_shaped_wall_forces_a_detour() {
+        let map = grid(concat!(
+            "#######\n",
+            "#.....#\n",
+            "#.###.#\n",
+            "#.#.#.#\n",
+            "#.....#\n",
+            "#######",
+        ));
+        // Straight up from (3, 3) is walled off; the way out is around the U
+        let path = astar(&map, (3, 3), (3, 1), &HashSet::new()).expect("target is reachable");
+        assert_eq!(path.len(), 8);
+        assert_eq!(path.last(), Some(&(3, 1)));
+        for pair in path.windows(2) {
+            let ((ax, ay), (bx, by)) = (pair[0], pair[1]);
+            assert_eq!((ax - bx).abs() + (ay - by).abs(), 1);
+            assert!(map.is_walkable(bx, by));
+        }
+    }
+    
+    #[test]
+    fn blocked_tiles_are_walked_around() {
+        let map = grid("#####\n#...#\n#...#\n#####");
+        let blocked = HashSet::from([(2, 1)]);
+        let path = astar(&map, (1, 1), (3, 1), &blocked).expect("there is room to pass");
+        assert!(!path.contains(&(2, 1)));
+        assert_eq!(path.len(), 4);
+    }
+    
+    #[test]
+    fn unreachable_target_returns_none() {
+        let map = grid("#######\n#..#..#\n#######");
+        assert_eq!(astar(&map, (1, 1), (5, 1), &HashSet::new()), None);
+    }
+}